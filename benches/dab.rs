@@ -0,0 +1,54 @@
+// Per-dab cost of painting: stamping a dab and re-compositing the damaged
+// tiles should grow with the brush, not with the size of the canvas.
+//
+// `cargo test` checks the same property by counting what each dab touches;
+// this reports the timings. Run with `cargo bench --bench dab`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use image::Rgba;
+use paintmate::image_ops::ImageData;
+
+const DABS: usize = 2000;
+const ROUNDS: usize = 5;
+
+// Paints a diagonal stroke of `DABS` dabs inside the smallest canvas, uploading the damaged region after
+// each one like the canvas does, and returns the fastest time per dab.
+fn time_per_dab(ctx: &egui::Context, size: u32, radius: f32) -> Duration {
+    let mut image_data = ImageData::new(size, size);
+    let _ = image_data.get_texture(ctx);
+    
+    let mut best = Duration::MAX;
+    for _ in 0..ROUNDS {
+        image_data.begin_stroke(Rgba([200, 40, 40, 255]), 1.0, 0.5, false);
+        let start = Instant::now();
+        for i in 0..DABS {
+            let t = (i % 100) as f32 * 2.0 + radius;
+            image_data.draw_dab(t, t, radius, 0.8);
+            let _ = black_box(image_data.get_texture(ctx));
+            // Drain the queued texture upload the way the end of a frame would
+            let _ = black_box(ctx.tex_manager().write().take_delta());
+        }
+        let elapsed = start.elapsed() / DABS as u32;
+        image_data.end_stroke();
+        best = best.min(elapsed);
+    }
+    best
+}
+
+fn main() {
+    let ctx = egui::Context::default();
+    
+    println!("dab cost by canvas size (radius 8)");
+    for size in [256, 1024, 4096] {
+        let per_dab = time_per_dab(&ctx, size, 8.0);
+        println!("  {size:>5} x {size:<5} {per_dab:>10.2?} / dab");
+    }
+    
+    println!("dab cost by brush radius (4096 x 4096)");
+    for radius in [2.0, 8.0, 32.0] {
+        let per_dab = time_per_dab(&ctx, 4096, radius);
+        println!("  radius {radius:<5} {per_dab:>10.2?} / dab");
+    }
+}
@@ -1,91 +1,713 @@
-use image::{DynamicImage, ImageBuffer, Rgba};
+use anyhow::{anyhow, bail, Result};
+use image::Rgba;
 
-pub fn adjust_brightness(img: &DynamicImage, brightness: f32) -> DynamicImage {
-    let mut rgba_img = img.to_rgba8();
+// A colour adjustment that can be applied pixel by pixel. Only the Alpha
+// channel of Levels and Curves changes alpha; everything else keeps the
+// layer's transparency.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Adjustment {
+    // Both -1..=1, 0 leaves the image unchanged
+    BrightnessContrast { brightness: f32, contrast: f32 },
+    // Hue shift in degrees and a saturation multiplier, 1 leaves it unchanged
+    HueSaturation { hue_shift: f32, saturation: f32 },
+    ColorBalance(ColorBalance),
+    Levels(Levels),
+    Curves(Curves),
+}
+
+impl Adjustment {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Adjustment::BrightnessContrast { .. } => "Brightness/Contrast",
+            Adjustment::HueSaturation { .. } => "Hue/Saturation",
+            Adjustment::ColorBalance(_) => "Color Balance",
+            Adjustment::Levels(_) => "Levels",
+            Adjustment::Curves(_) => "Curves",
+        }
+    }
+    
+    // The same kind of adjustment with settings that change nothing
+    pub fn identity(&self) -> Adjustment {
+        match self {
+            Adjustment::BrightnessContrast { .. } => Adjustment::BrightnessContrast { brightness: 0.0, contrast: 0.0 },
+            Adjustment::HueSaturation { .. } => Adjustment::HueSaturation { hue_shift: 0.0, saturation: 1.0 },
+            Adjustment::ColorBalance(balance) => Adjustment::ColorBalance(ColorBalance {
+                preserve_luminosity: balance.preserve_luminosity,
+                ..ColorBalance::default()
+            }),
+            Adjustment::Levels(_) => Adjustment::Levels(Levels::default()),
+            Adjustment::Curves(_) => Adjustment::Curves(Curves::default()),
+        }
+    }
     
-    for pixel in rgba_img.pixels_mut() {
-        let r = (pixel[0] as f32 + brightness * 255.0).clamp(0.0, 255.0) as u8;
-        let g = (pixel[1] as f32 + brightness * 255.0).clamp(0.0, 255.0) as u8;
-        let b = (pixel[2] as f32 + brightness * 255.0).clamp(0.0, 255.0) as u8;
+    pub fn is_identity(&self) -> bool {
+        match self {
+            Adjustment::BrightnessContrast { brightness, contrast } => *brightness == 0.0 && *contrast == 0.0,
+            Adjustment::HueSaturation { hue_shift, saturation } => *hue_shift == 0.0 && *saturation == 1.0,
+            Adjustment::ColorBalance(balance) => balance.is_identity(),
+            Adjustment::Levels(levels) => *levels == Levels::default(),
+            Adjustment::Curves(curves) => *curves == Curves::default(),
+        }
+    }
+    
+    // Function that adjusts one pixel. Lookup tables are built once here
+    // rather than for every pixel.
+    pub fn mapper(&self) -> impl Fn(Rgba<u8>) -> Rgba<u8> + '_ {
+        let luts = match self {
+            Adjustment::Levels(levels) => Some(levels.luts()),
+            Adjustment::Curves(curves) => Some(curves.luts()),
+            _ => None,
+        };
         
-        pixel[0] = r;
-        pixel[1] = g;
-        pixel[2] = b;
+        move |pixel| match (self, &luts) {
+            (_, Some(luts)) => luts.apply(pixel),
+            (Adjustment::BrightnessContrast { brightness, contrast }, _) => {
+                contrast_pixel(brightness_pixel(pixel, *brightness), *contrast)
+            }
+            (Adjustment::HueSaturation { hue_shift, saturation }, _) => hue_saturation_pixel(pixel, *hue_shift, *saturation),
+            (Adjustment::ColorBalance(balance), _) => balance.apply(pixel),
+            _ => pixel,
+        }
+    }
+}
+
+// Channel a tonal adjustment or histogram refers to. `Rgb` is the three
+// colour channels together.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Channel {
+    Rgb,
+    Red,
+    Green,
+    Blue,
+    Alpha,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 5] = [Channel::Rgb, Channel::Red, Channel::Green, Channel::Blue, Channel::Alpha];
+    
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channel::Rgb => "RGB",
+            Channel::Red => "Red",
+            Channel::Green => "Green",
+            Channel::Blue => "Blue",
+            Channel::Alpha => "Alpha",
+        }
+    }
+    
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+    
+    // Name used in preset files
+    fn key(&self) -> &'static str {
+        match self {
+            Channel::Rgb => "rgb",
+            Channel::Red => "red",
+            Channel::Green => "green",
+            Channel::Blue => "blue",
+            Channel::Alpha => "alpha",
+        }
     }
     
-    DynamicImage::ImageRgba8(rgba_img)
+    fn from_key(key: &str) -> Option<Channel> {
+        Channel::ALL.into_iter().find(|channel| channel.key() == key)
+    }
 }
 
-pub fn adjust_contrast(img: &DynamicImage, contrast: f32) -> DynamicImage {
-    let mut rgba_img = img.to_rgba8();
-    let factor = (259.0 * (contrast * 255.0 + 255.0)) / (255.0 * (259.0 - contrast * 255.0));
+pub type Lut = [u8; 256];
+
+pub const IDENTITY_LUT: Lut = {
+    let mut lut = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        lut[i] = i as u8;
+        i += 1;
+    }
+    lut
+};
+
+// One lookup table per `Channel`. Colour values go through the `Rgb`
+// table first and then through their own channel's table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToneLuts {
+    pub tables: [Lut; 5],
+}
+
+impl ToneLuts {
+    pub fn get(&self, channel: Channel) -> &Lut {
+        &self.tables[channel.index()]
+    }
     
-    for pixel in rgba_img.pixels_mut() {
-        let r = (factor * (pixel[0] as f32 - 128.0) + 128.0).clamp(0.0, 255.0) as u8;
-        let g = (factor * (pixel[1] as f32 - 128.0) + 128.0).clamp(0.0, 255.0) as u8;
-        let b = (factor * (pixel[2] as f32 - 128.0) + 128.0).clamp(0.0, 255.0) as u8;
+    // Final output value of `value` in one of the colour channels or alpha
+    pub fn map(&self, channel: Channel, value: u8) -> u8 {
+        match channel {
+            Channel::Rgb | Channel::Alpha => self.get(channel)[value as usize],
+            _ => self.get(channel)[self.get(Channel::Rgb)[value as usize] as usize],
+        }
+    }
+    
+    pub fn apply(&self, pixel: Rgba<u8>) -> Rgba<u8> {
+        Rgba([
+            self.map(Channel::Red, pixel[0]),
+            self.map(Channel::Green, pixel[1]),
+            self.map(Channel::Blue, pixel[2]),
+            self.map(Channel::Alpha, pixel[3]),
+        ])
+    }
+}
+
+// Levels for one channel: input values from `input_black` to `input_white`
+// are stretched to the output range, with `gamma` bending the midtones
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelsChannel {
+    pub input_black: u8,
+    pub input_white: u8,
+    pub gamma: f32,
+    pub output_black: u8,
+    pub output_white: u8,
+}
+
+impl Default for LevelsChannel {
+    fn default() -> Self {
+        Self {
+            input_black: 0,
+            input_white: 255,
+            gamma: 1.0,
+            output_black: 0,
+            output_white: 255,
+        }
+    }
+}
+
+impl LevelsChannel {
+    pub fn lut(&self) -> Lut {
+        let mut lut = [0u8; 256];
+        let black = self.input_black as f32;
+        let range = (self.input_white as f32 - black).max(1.0);
+        let out_black = self.output_black as f32;
+        let out_range = self.output_white as f32 - out_black;
+        let exponent = 1.0 / self.gamma.max(0.01);
         
-        pixel[0] = r;
-        pixel[1] = g;
-        pixel[2] = b;
+        for (value, entry) in lut.iter_mut().enumerate() {
+            let t = ((value as f32 - black) / range).clamp(0.0, 1.0).powf(exponent);
+            *entry = (out_black + t * out_range).round().clamp(0.0, 255.0) as u8;
+        }
+        lut
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Levels {
+    pub channels: [LevelsChannel; 5],
+}
+
+impl Levels {
+    pub const PRESET_EXTENSION: &'static str = "pmlevels";
+    const PRESET_HEADER: &'static str = "PaintMate Levels 1";
+    
+    pub fn luts(&self) -> ToneLuts {
+        ToneLuts { tables: std::array::from_fn(|i| self.channels[i].lut()) }
+    }
+    
+    // Preset file: a header line, then one line per channel holding the
+    // input black, input white, gamma, output black and output white
+    pub fn to_preset(&self) -> String {
+        let mut text = format!("{}\n", Self::PRESET_HEADER);
+        for channel in Channel::ALL {
+            let levels = &self.channels[channel.index()];
+            text.push_str(&format!(
+                "{} {} {} {:.2} {} {}\n",
+                channel.key(),
+                levels.input_black,
+                levels.input_white,
+                levels.gamma,
+                levels.output_black,
+                levels.output_white,
+            ));
+        }
+        text
     }
     
-    DynamicImage::ImageRgba8(rgba_img)
+    // Channels missing from the preset are left unchanged
+    pub fn from_preset(text: &str) -> Result<Self> {
+        let mut levels = Levels::default();
+        for (channel, values) in preset_lines(text, Self::PRESET_HEADER)? {
+            let values: Vec<&str> = values.split_whitespace().collect();
+            if values.len() != 5 {
+                bail!("Expected 5 values for the {} channel", channel.name());
+            }
+            let byte = |i: usize| values[i].parse::<u8>().map_err(|e| anyhow!("Invalid level '{}': {}", values[i], e));
+            levels.channels[channel.index()] = LevelsChannel {
+                input_black: byte(0)?,
+                input_white: byte(1)?,
+                gamma: values[2].parse().map_err(|e| anyhow!("Invalid gamma '{}': {}", values[2], e))?,
+                output_black: byte(3)?,
+                output_white: byte(4)?,
+            };
+        }
+        Ok(levels)
+    }
+}
+
+// Tone curves: per channel, a smooth curve through control points that
+// maps input values (x) to output values (y), both 0..=255. Points are
+// kept sorted by x, and the curve is flat beyond the first and last point.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Curves {
+    pub channels: [Vec<(f32, f32)>; 5],
 }
 
-pub fn adjust_hue_saturation(img: &DynamicImage, hue_shift: f32, saturation: f32) -> DynamicImage {
-    let mut rgba_img = img.to_rgba8();
+impl Default for Curves {
+    fn default() -> Self {
+        Self { channels: std::array::from_fn(|_| vec![(0.0, 0.0), (255.0, 255.0)]) }
+    }
+}
+
+impl Curves {
+    pub const PRESET_EXTENSION: &'static str = "pmcurves";
+    const PRESET_HEADER: &'static str = "PaintMate Curves 1";
+    
+    pub fn luts(&self) -> ToneLuts {
+        ToneLuts { tables: std::array::from_fn(|i| spline_lut(&self.channels[i])) }
+    }
     
-    for pixel in rgba_img.pixels_mut() {
-        let r = pixel[0] as f32 / 255.0;
-        let g = pixel[1] as f32 / 255.0;
-        let b = pixel[2] as f32 / 255.0;
+    // Preset file: a header line, then one line per channel listing its
+    // control points as `x,y`
+    pub fn to_preset(&self) -> String {
+        let mut text = format!("{}\n", Self::PRESET_HEADER);
+        for channel in Channel::ALL {
+            text.push_str(channel.key());
+            for (x, y) in &self.channels[channel.index()] {
+                text.push_str(&format!(" {},{}", x.round(), y.round()));
+            }
+            text.push('\n');
+        }
+        text
+    }
+    
+    // Channels missing from the preset are left unchanged
+    pub fn from_preset(text: &str) -> Result<Self> {
+        let mut curves = Curves::default();
+        for (channel, values) in preset_lines(text, Self::PRESET_HEADER)? {
+            let mut points = Vec::new();
+            for point in values.split_whitespace() {
+                let (x, y) = point.split_once(',').ok_or_else(|| anyhow!("Invalid curve point '{}'", point))?;
+                let parse = |v: &str| v.parse::<f32>().map(|v| v.clamp(0.0, 255.0)).map_err(|e| anyhow!("Invalid curve point '{}': {}", point, e));
+                points.push((parse(x)?, parse(y)?));
+            }
+            if points.len() < 2 {
+                bail!("The {} curve needs at least two points", channel.name());
+            }
+            points.sort_by(|a, b| a.0.total_cmp(&b.0));
+            points.dedup_by(|a, b| a.0 == b.0);
+            curves.channels[channel.index()] = points;
+        }
+        Ok(curves)
+    }
+}
+
+// Splits a preset into (channel, rest of line) pairs after checking the header
+fn preset_lines<'a>(text: &'a str, header: &str) -> Result<Vec<(Channel, &'a str)>> {
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+    if lines.next() != Some(header) {
+        bail!("Not a {} preset", header.trim_end_matches(" 1"));
+    }
+    
+    lines
+        .map(|line| {
+            let (key, values) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let channel = Channel::from_key(key).ok_or_else(|| anyhow!("Unknown channel '{}'", key))?;
+            Ok((channel, values))
+        })
+        .collect()
+}
+
+// Natural cubic spline through `points` (sorted by x) sampled at every
+// input value
+fn spline_lut(points: &[(f32, f32)]) -> Lut {
+    let n = points.len();
+    match n {
+        0 => return IDENTITY_LUT,
+        1 => return [points[0].1.round().clamp(0.0, 255.0) as u8; 256],
+        _ => {}
+    }
+    
+    let (x, y): (Vec<f32>, Vec<f32>) = points.iter().copied().unzip();
+    
+    // Second derivatives at each point, zero at both ends
+    let mut second = vec![0.0f32; n];
+    let mut u = vec![0.0f32; n];
+    for i in 1..n - 1 {
+        let sig = (x[i] - x[i - 1]) / (x[i + 1] - x[i - 1]);
+        let p = sig * second[i - 1] + 2.0;
+        second[i] = (sig - 1.0) / p;
+        let slope_change = (y[i + 1] - y[i]) / (x[i + 1] - x[i]) - (y[i] - y[i - 1]) / (x[i] - x[i - 1]);
+        u[i] = (6.0 * slope_change / (x[i + 1] - x[i - 1]) - sig * u[i - 1]) / p;
+    }
+    for i in (0..n - 1).rev() {
+        second[i] = second[i] * second[i + 1] + u[i];
+    }
+    
+    let mut lut = [0u8; 256];
+    let mut segment = 0;
+    for (value, entry) in lut.iter_mut().enumerate() {
+        let v = value as f32;
+        let out = if v <= x[0] {
+            y[0]
+        } else if v >= x[n - 1] {
+            y[n - 1]
+        } else {
+            while x[segment + 1] < v {
+                segment += 1;
+            }
+            let (lo, hi) = (segment, segment + 1);
+            let h = x[hi] - x[lo];
+            let a = (x[hi] - v) / h;
+            let b = (v - x[lo]) / h;
+            a * y[lo] + b * y[hi] + ((a * a * a - a) * second[lo] + (b * b * b - b) * second[hi]) * h * h / 6.0
+        };
+        *entry = out.round().clamp(0.0, 255.0) as u8;
+    }
+    lut
+}
+
+// Shifts colours towards red, green or blue (positive) or cyan, magenta or
+// yellow (negative) separately in the shadows, midtones and highlights.
+// Each entry is -1..=1 per RGB channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorBalance {
+    pub shadows: [f32; 3],
+    pub midtones: [f32; 3],
+    pub highlights: [f32; 3],
+    // Keep each pixel's lightness so only the tint changes
+    pub preserve_luminosity: bool,
+}
+
+impl Default for ColorBalance {
+    fn default() -> Self {
+        Self {
+            shadows: [0.0; 3],
+            midtones: [0.0; 3],
+            highlights: [0.0; 3],
+            preserve_luminosity: true,
+        }
+    }
+}
+
+impl ColorBalance {
+    pub fn is_identity(&self) -> bool {
+        self.shadows.iter().chain(&self.midtones).chain(&self.highlights).all(|v| *v == 0.0)
+    }
+    
+    pub fn apply(&self, pixel: Rgba<u8>) -> Rgba<u8> {
+        let rgb = [pixel[0] as f32 / 255.0, pixel[1] as f32 / 255.0, pixel[2] as f32 / 255.0];
         
-        // Convert RGB to HSV
-        let max = r.max(g.max(b));
-        let min = r.min(g.min(b));
-        let delta = max - min;
+        let mut out = [0.0f32; 3];
+        for c in 0..3 {
+            let (shadows, midtones, highlights) = tonal_weights(rgb[c]);
+            out[c] = (rgb[c]
+                + shadows * self.shadows[c]
+                + midtones * self.midtones[c]
+                + highlights * self.highlights[c])
+                .clamp(0.0, 1.0);
+        }
         
-        let mut h = if delta == 0.0 {
-            0.0
-        } else if max == r {
-            60.0 * (((g - b) / delta) % 6.0)
-        } else if max == g {
-            60.0 * ((b - r) / delta + 2.0)
-        } else {
-            60.0 * ((r - g) / delta + 4.0)
+        if self.preserve_luminosity {
+            let (h, s, _) = rgb_to_hsl(out);
+            let (_, _, l) = rgb_to_hsl(rgb);
+            out = hsl_to_rgb(h, s, l);
+        }
+        
+        Rgba([
+            (out[0] * 255.0).round() as u8,
+            (out[1] * 255.0).round() as u8,
+            (out[2] * 255.0).round() as u8,
+            pixel[3],
+        ])
+    }
+}
+
+// How strongly a channel value (0..=1) belongs to the shadows, midtones
+// and highlights. The ranges overlap with soft edges, and none of them
+// reaches full strength so pure black and white can still be tinted.
+fn tonal_weights(value: f32) -> (f32, f32, f32) {
+    const EDGE: f32 = 0.25;
+    const CENTER: f32 = 0.333;
+    const SCALE: f32 = 0.7;
+    
+    let shadows = ((value - CENTER) / -EDGE + 0.5).clamp(0.0, 1.0) * SCALE;
+    let midtones = ((value - CENTER) / EDGE + 0.5).clamp(0.0, 1.0)
+        * ((value + CENTER - 1.0) / -EDGE + 0.5).clamp(0.0, 1.0)
+        * SCALE;
+    let highlights = ((value + CENTER - 1.0) / EDGE + 0.5).clamp(0.0, 1.0) * SCALE;
+    (shadows, midtones, highlights)
+}
+
+fn rgb_to_hsl(rgb: [f32; 3]) -> (f32, f32, f32) {
+    let [r, g, b] = rgb;
+    let max = r.max(g.max(b));
+    let min = r.min(g.min(b));
+    let l = (max + min) / 2.0;
+    let delta = max - min;
+    if delta == 0.0 {
+        return (0.0, 0.0, l);
+    }
+    
+    let s = delta / (1.0 - (2.0 * l - 1.0).abs());
+    let h = if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    (h, s, l)
+}
+
+fn hsl_to_rgb(h: f32, s: f32, l: f32) -> [f32; 3] {
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let (r, g, b) = hue_to_rgb(h, c);
+    let m = l - c / 2.0;
+    [(r + m).clamp(0.0, 1.0), (g + m).clamp(0.0, 1.0), (b + m).clamp(0.0, 1.0)]
+}
+
+// RGB of a pure hue `h` (degrees) with chroma `c`, before adding the lightness offset
+fn hue_to_rgb(h: f32, c: f32) -> (f32, f32, f32) {
+    let h = h.rem_euclid(360.0);
+    let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+    if h < 60.0 {
+        (c, x, 0.0)
+    } else if h < 120.0 {
+        (x, c, 0.0)
+    } else if h < 180.0 {
+        (0.0, c, x)
+    } else if h < 240.0 {
+        (0.0, x, c)
+    } else if h < 300.0 {
+        (x, 0.0, c)
+    } else {
+        (c, 0.0, x)
+    }
+}
+
+fn brightness_pixel(mut pixel: Rgba<u8>, brightness: f32) -> Rgba<u8> {
+    for c in 0..3 {
+        pixel[c] = (pixel[c] as f32 + brightness * 255.0).clamp(0.0, 255.0) as u8;
+    }
+    pixel
+}
+
+fn contrast_pixel(mut pixel: Rgba<u8>, contrast: f32) -> Rgba<u8> {
+    let factor = (259.0 * (contrast * 255.0 + 255.0)) / (255.0 * (259.0 - contrast * 255.0));
+    for c in 0..3 {
+        pixel[c] = (factor * (pixel[c] as f32 - 128.0) + 128.0).clamp(0.0, 255.0) as u8;
+    }
+    pixel
+}
+
+fn hue_saturation_pixel(pixel: Rgba<u8>, hue_shift: f32, saturation: f32) -> Rgba<u8> {
+    let r = pixel[0] as f32 / 255.0;
+    let g = pixel[1] as f32 / 255.0;
+    let b = pixel[2] as f32 / 255.0;
+    
+    // Convert RGB to HSV
+    let max = r.max(g.max(b));
+    let min = r.min(g.min(b));
+    let delta = max - min;
+    
+    let h = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    
+    let s = if max == 0.0 { 0.0 } else { delta / max };
+    let v = max;
+    
+    // Apply adjustments
+    let h = h + hue_shift;
+    let s = (s * saturation).clamp(0.0, 1.0);
+    
+    // Convert back to RGB
+    let c = v * s;
+    let m = v - c;
+    let (r_prime, g_prime, b_prime) = hue_to_rgb(h, c);
+    
+    Rgba([
+        ((r_prime + m) * 255.0).round() as u8,
+        ((g_prime + m) * 255.0).round() as u8,
+        ((b_prime + m) * 255.0).round() as u8,
+        pixel[3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // A spread of colours, including greys, primaries and partial alpha
+    fn sample_pixels() -> Vec<Rgba<u8>> {
+        let mut pixels = Vec::new();
+        for r in (0..=255).step_by(51) {
+            for g in (0..=255).step_by(85) {
+                for b in (0..=255).step_by(51) {
+                    pixels.push(Rgba([r as u8, g as u8, b as u8, ((r + b) / 2) as u8]));
+                }
+            }
+        }
+        pixels
+    }
+    
+    fn adjusted_settings() -> Vec<Adjustment> {
+        vec![
+            Adjustment::BrightnessContrast { brightness: 0.2, contrast: 0.4 },
+            Adjustment::HueSaturation { hue_shift: 120.0, saturation: 1.6 },
+            Adjustment::ColorBalance(ColorBalance {
+                shadows: [0.3, -0.2, 0.0],
+                midtones: [-0.4, 0.1, 0.5],
+                highlights: [0.0, 0.0, -0.6],
+                preserve_luminosity: true,
+            }),
+        ]
+    }
+    
+    fn lightness(pixel: Rgba<u8>) -> f32 {
+        rgb_to_hsl([pixel[0] as f32 / 255.0, pixel[1] as f32 / 255.0, pixel[2] as f32 / 255.0]).2
+    }
+    
+    #[test]
+    fn identity_settings_change_nothing() {
+        let kinds = adjusted_settings()
+            .into_iter()
+            .chain([Adjustment::Levels(Levels::default()), Adjustment::Curves(Curves::default())]);
+        for adjustment in kinds {
+            let identity = adjustment.identity();
+            assert!(identity.is_identity(), "{}", adjustment.name());
+            let map = identity.mapper();
+            for pixel in sample_pixels() {
+                assert_eq!(map(pixel), pixel, "{}", adjustment.name());
+            }
+        }
+    }
+    
+    #[test]
+    fn colour_adjustments_keep_alpha() {
+        for adjustment in adjusted_settings() {
+            assert!(!adjustment.is_identity(), "{}", adjustment.name());
+            let map = adjustment.mapper();
+            let pixels = sample_pixels();
+            assert!(pixels.iter().any(|pixel| map(*pixel) != *pixel), "{} changed nothing", adjustment.name());
+            for pixel in pixels {
+                assert_eq!(map(pixel)[3], pixel[3], "{}", adjustment.name());
+            }
+        }
+    }
+    
+    #[test]
+    fn preserve_luminosity_keeps_hsl_lightness() {
+        let mut balance = ColorBalance {
+            shadows: [0.5, 0.0, -0.5],
+            midtones: [0.6, -0.3, 0.0],
+            highlights: [0.0, -0.5, 0.4],
+            preserve_luminosity: true,
         };
+        for pixel in sample_pixels() {
+            let out = balance.apply(pixel);
+            assert!((lightness(out) - lightness(pixel)).abs() <= 1.0 / 255.0, "{:?} became {:?}", pixel, out);
+        }
         
-        let s = if max == 0.0 { 0.0 } else { delta / max };
-        let v = max;
+        balance.preserve_luminosity = false;
+        let grey = Rgba([128, 128, 128, 255]);
+        assert!((lightness(balance.apply(grey)) - lightness(grey)).abs() > 2.0 / 255.0);
+    }
+    
+    #[test]
+    fn color_balance_tints_towards_the_chosen_channel() {
+        let balance = ColorBalance { midtones: [0.5, 0.0, -0.5], preserve_luminosity: false, ..ColorBalance::default() };
+        let out = balance.apply(Rgba([128, 128, 128, 255]));
+        assert!(out[0] > 128 && out[1] == 128 && out[2] < 128, "{:?}", out);
+    }
+    
+    #[test]
+    fn default_levels_and_curves_are_identity_luts() {
+        for tables in [Levels::default().luts().tables, Curves::default().luts().tables] {
+            for lut in tables {
+                assert_eq!(lut, IDENTITY_LUT);
+            }
+        }
+    }
+    
+    #[test]
+    fn levels_stretch_between_the_black_and_white_points() {
+        let lut = LevelsChannel { input_black: 50, input_white: 200, ..LevelsChannel::default() }.lut();
+        assert_eq!(lut[0], 0);
+        assert_eq!(lut[50], 0);
+        assert_eq!(lut[125], 128);
+        assert_eq!(lut[200], 255);
+        assert_eq!(lut[255], 255);
         
-        // Apply adjustments
-        h = (h + hue_shift) % 360.0;
-        let s = (s * saturation).clamp(0.0, 1.0);
+        let lut = LevelsChannel { output_black: 20, output_white: 220, ..LevelsChannel::default() }.lut();
+        assert_eq!((lut[0], lut[255]), (20, 220));
         
-        // Convert back to RGB
-        let c = v * s;
-        let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
-        let m = v - c;
+        // Gamma 2 brightens the midtones: out = in^(1/2)
+        let lut = LevelsChannel { gamma: 2.0, ..LevelsChannel::default() }.lut();
+        assert_eq!((lut[0], lut[64], lut[255]), (0, 128, 255));
+        let lut = LevelsChannel { gamma: 0.5, ..LevelsChannel::default() }.lut();
+        assert_eq!((lut[0], lut[128], lut[255]), (0, 64, 255));
+    }
+    
+    #[test]
+    fn rgb_levels_apply_before_each_channel() {
+        let mut levels = Levels::default();
+        levels.channels[Channel::Rgb.index()].input_white = 127;
+        levels.channels[Channel::Red.index()].output_white = 128;
+        let luts = levels.luts();
+        assert_eq!(luts.apply(Rgba([255, 255, 50, 77])), Rgba([128, 255, 100, 77]));
+    }
+    
+    #[test]
+    fn curve_passes_through_its_control_points() {
+        let lut = spline_lut(&[(0.0, 0.0), (128.0, 200.0), (255.0, 255.0)]);
+        assert_eq!((lut[0], lut[128], lut[255]), (0, 200, 255));
+        assert!(lut.windows(2).all(|pair| pair[0] <= pair[1]));
         
-        let (r_prime, g_prime, b_prime) = if h < 60.0 {
-            (c, x, 0.0)
-        } else if h < 120.0 {
-            (x, c, 0.0)
-        } else if h < 180.0 {
-            (0.0, c, x)
-        } else if h < 240.0 {
-            (0.0, x, c)
-        } else if h < 300.0 {
-            (x, 0.0, c)
-        } else {
-            (c, 0.0, x)
-        };
+        // Flat beyond the end points
+        let lut = spline_lut(&[(64.0, 32.0), (192.0, 224.0)]);
+        assert_eq!((lut[0], lut[64], lut[128], lut[192], lut[255]), (32, 32, 128, 224, 224));
+    }
+    
+    #[test]
+    fn presets_round_trip() {
+        let mut levels = Levels::default();
+        levels.channels[Channel::Rgb.index()] =
+            LevelsChannel { input_black: 12, input_white: 240, gamma: 1.25, output_black: 5, output_white: 250 };
+        levels.channels[Channel::Alpha.index()].gamma = 0.5;
+        assert_eq!(Levels::from_preset(&levels.to_preset()).unwrap(), levels);
         
-        pixel[0] = ((r_prime + m) * 255.0) as u8;
-        pixel[1] = ((g_prime + m) * 255.0) as u8;
-        pixel[2] = ((b_prime + m) * 255.0) as u8;
+        let mut curves = Curves::default();
+        curves.channels[Channel::Green.index()] = vec![(0.0, 10.0), (100.0, 180.0), (255.0, 240.0)];
+        curves.channels[Channel::Alpha.index()] = vec![(30.0, 0.0), (220.0, 255.0)];
+        assert_eq!(Curves::from_preset(&curves.to_preset()).unwrap(), curves);
     }
     
-    DynamicImage::ImageRgba8(rgba_img)
+    #[test]
+    fn bad_presets_are_rejected() {
+        let curves = Curves::default().to_preset();
+        let levels = Levels::default().to_preset();
+        assert!(Levels::from_preset(&curves).unwrap_err().to_string().contains("Not a PaintMate Levels preset"));
+        assert!(Curves::from_preset(&levels).unwrap_err().to_string().contains("Not a PaintMate Curves preset"));
+        
+        let unknown = levels.replace("red ", "purple ");
+        assert!(Levels::from_preset(&unknown).unwrap_err().to_string().contains("Unknown channel 'purple'"));
+        let unknown = curves.replace("blue ", "cyan ");
+        assert!(Curves::from_preset(&unknown).unwrap_err().to_string().contains("Unknown channel 'cyan'"));
+        
+        assert!(Levels::from_preset("PaintMate Levels 1\nrgb 0 255 1.00 0\n").is_err());
+        assert!(Curves::from_preset("PaintMate Curves 1\nrgb 0,0\n").is_err());
+    }
 }
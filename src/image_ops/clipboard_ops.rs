@@ -1,16 +1,174 @@
+use std::borrow::Cow;
 use anyhow::Result;
-use image::DynamicImage;
+use image::RgbaImage;
 
-pub fn copy_to_clipboard(img: &DynamicImage) -> Result<()> {
-    // TODO: Implement clipboard copy functionality
-    // This would require platform-specific clipboard handling
-    log::info!("Copying image to clipboard");
-    Ok(())
+// Somewhere to copy pixels to and paste them from
+pub trait Clipboard {
+    fn set_image(&mut self, image: &RgbaImage) -> Result<()>;
+    
+    // The image on the clipboard, or None if it holds no image
+    fn get_image(&mut self) -> Result<Option<RgbaImage>>;
 }
 
-pub fn paste_from_clipboard() -> Result<Option<DynamicImage>> {
-    // TODO: Implement clipboard paste functionality
-    // This would require platform-specific clipboard handling
-    log::info!("Pasting image from clipboard");
-    Ok(None)
+// The operating system clipboard, shared with other applications
+pub struct SystemClipboard {
+    clipboard: arboard::Clipboard,
+}
+
+impl SystemClipboard {
+    pub fn new() -> Result<Self> {
+        Ok(Self { clipboard: arboard::Clipboard::new()? })
+    }
+}
+
+impl Clipboard for SystemClipboard {
+    fn set_image(&mut self, image: &RgbaImage) -> Result<()> {
+        self.clipboard.set_image(arboard::ImageData {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            bytes: Cow::Borrowed(image.as_raw()),
+        })?;
+        Ok(())
+    }
+    
+    fn get_image(&mut self) -> Result<Option<RgbaImage>> {
+        let data = match self.clipboard.get_image() {
+            Ok(data) => data,
+            Err(arboard::Error::ContentNotAvailable) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(RgbaImage::from_raw(data.width as u32, data.height as u32, data.bytes.into_owned()))
+    }
+}
+
+// Clipboard that only lives as long as the process, for when the system
+// one can't be opened and for tests
+#[derive(Default)]
+pub struct MemoryClipboard {
+    image: Option<RgbaImage>,
+}
+
+impl Clipboard for MemoryClipboard {
+    fn set_image(&mut self, image: &RgbaImage) -> Result<()> {
+        self.image = Some(image.clone());
+        Ok(())
+    }
+    
+    fn get_image(&mut self) -> Result<Option<RgbaImage>> {
+        Ok(self.image.clone())
+    }
+}
+
+// The system clipboard where available, otherwise an in-process one
+pub fn default_clipboard() -> Box<dyn Clipboard> {
+    match SystemClipboard::new() {
+        Ok(clipboard) => Box::new(clipboard),
+        Err(err) => {
+            log::warn!("System clipboard unavailable, copying within PaintMate only: {}", err);
+            Box::new(MemoryClipboard::default())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use crate::image_ops::ImageData;
+    use crate::image_ops::selection::{SelectionMode, SelectionShape};
+    
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+    const CLEAR: Rgba<u8> = Rgba([0, 0, 0, 0]);
+    
+    // 8x8 document: red in the top-left 4x4, blue in the bottom-right 4x4
+    fn quadrants() -> ImageData {
+        let mut image_data = ImageData::new(8, 8);
+        for y in 0..8 {
+            for x in 0..8 {
+                if x < 4 && y < 4 {
+                    image_data.draw_pixel(x, y, RED);
+                } else if x >= 4 && y >= 4 {
+                    image_data.draw_pixel(x, y, BLUE);
+                }
+            }
+        }
+        image_data
+    }
+    
+    fn select_rect(image_data: &mut ImageData, x: f32, y: f32, width: f32, height: f32) {
+        let rect = egui::Rect::from_min_size(egui::pos2(x, y), egui::vec2(width, height));
+        image_data.select(&SelectionShape::Rectangle(rect), SelectionMode::Replace);
+    }
+    
+    #[test]
+    fn empty_memory_clipboard_has_no_image() {
+        assert_eq!(MemoryClipboard::default().get_image().unwrap(), None);
+    }
+    
+    #[test]
+    fn copy_round_trips_the_selected_pixels() {
+        let mut image_data = quadrants();
+        select_rect(&mut image_data, 2.0, 2.0, 4.0, 4.0);
+        let mut clipboard = MemoryClipboard::default();
+        
+        clipboard.set_image(&image_data.copy_selection(false).unwrap()).unwrap();
+        let copied = clipboard.get_image().unwrap().unwrap();
+        
+        assert_eq!(copied.dimensions(), (4, 4));
+        assert_eq!(*copied.get_pixel(0, 0), RED);
+        assert_eq!(*copied.get_pixel(3, 3), BLUE);
+        assert_eq!(*copied.get_pixel(3, 0), CLEAR);
+        // Copying leaves the document alone
+        assert_eq!(image_data.get_active_layer().data.get_pixel(2, 2), RED);
+    }
+    
+    #[test]
+    fn cut_copies_then_clears_only_the_selection() {
+        let mut image_data = quadrants();
+        select_rect(&mut image_data, 0.0, 0.0, 2.0, 2.0);
+        let mut clipboard = MemoryClipboard::default();
+        
+        clipboard.set_image(&image_data.copy_selection(false).unwrap()).unwrap();
+        image_data.clear_selected_pixels();
+        
+        let cut = clipboard.get_image().unwrap().unwrap();
+        assert_eq!(cut.dimensions(), (2, 2));
+        assert!(cut.pixels().all(|pixel| *pixel == RED));
+        
+        let layer = &image_data.get_active_layer().data;
+        assert_eq!(layer.get_pixel(1, 1), CLEAR);
+        assert_eq!(layer.get_pixel(2, 2), RED);
+        assert_eq!(layer.get_pixel(5, 5), BLUE);
+    }
+    
+    #[test]
+    fn paste_adds_the_clipboard_as_a_layer_at_the_centre() {
+        let mut image_data = quadrants();
+        select_rect(&mut image_data, 0.0, 0.0, 2.0, 2.0);
+        let mut clipboard = MemoryClipboard::default();
+        clipboard.set_image(&image_data.copy_selection(false).unwrap()).unwrap();
+        
+        let image = clipboard.get_image().unwrap().unwrap();
+        image_data.paste_as_layer(&image, egui::pos2(6.0, 2.0));
+        
+        assert_eq!(image_data.layers.len(), 2);
+        assert_eq!(image_data.active_layer, 1);
+        assert!(image_data.selection().is_none());
+        let pasted = &image_data.get_active_layer().data;
+        assert_eq!(pasted.get_pixel(5, 1), RED);
+        assert_eq!(pasted.get_pixel(6, 2), RED);
+        assert_eq!(pasted.get_pixel(4, 1), CLEAR);
+        assert_eq!(image_data.composite_pixel(5, 1), RED);
+    }
+    
+    #[test]
+    fn paste_without_selection_round_trips_the_whole_canvas() {
+        let image_data = quadrants();
+        let mut clipboard = MemoryClipboard::default();
+        clipboard.set_image(&image_data.copy_selection(false).unwrap()).unwrap();
+        
+        let pasted = ImageData::from_image(&clipboard.get_image().unwrap().unwrap());
+        assert_eq!(pasted.flatten(), image_data.flatten());
+    }
 }
@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::image_ops::RgbaImage;
+use crate::image_ops::image_data::{BlendMode, ImageData, Layer, LayerId, LayerKind};
+use crate::image_ops::tiles::TILE_BYTES;
+
+// Fixed cost charged per entry and per recorded change, so that property-only
+// edits still count against the history budget.
+const ENTRY_OVERHEAD: usize = 256;
+const CHANGE_OVERHEAD: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+struct LayerProps {
+    name: String,
+    visible: bool,
+    opacity: f32,
+    blend_mode: BlendMode,
+    kind: LayerKind,
+    offset: (i32, i32),
+}
+
+impl LayerProps {
+    fn of(layer: &Layer) -> Self {
+        Self {
+            name: layer.name.clone(),
+            visible: layer.visible,
+            opacity: layer.opacity,
+            blend_mode: layer.blend_mode.clone(),
+            kind: layer.kind.clone(),
+            offset: layer.offset,
+        }
+    }
+    
+    fn apply(&self, layer: &mut Layer) {
+        layer.name = self.name.clone();
+        layer.visible = self.visible;
+        layer.opacity = self.opacity;
+        layer.blend_mode = self.blend_mode.clone();
+        layer.kind = self.kind.clone();
+        layer.offset = self.offset;
+    }
+}
+
+struct PropChange {
+    layer: LayerId,
+    before: LayerProps,
+    after: LayerProps,
+}
+
+struct TileChange {
+    layer: LayerId,
+    index: usize,
+    before: Option<Arc<RgbaImage>>,
+    after: Option<Arc<RgbaImage>>,
+}
+
+// Document structure on one side of a delta. `exclusive` holds the layers
+// that only exist on this side (added, removed, or resized layers).
+struct Side {
+    order: Vec<LayerId>,
+    exclusive: Vec<Layer>,
+    active_layer: usize,
+    width: u32,
+    height: u32,
+}
+
+// The difference between two document states: changed tiles of layers that
+// exist on both sides, property edits, and the layer stack on either side.
+pub struct DocumentDelta {
+    before: Side,
+    after: Side,
+    props: Vec<PropChange>,
+    tiles: Vec<TileChange>,
+}
+
+impl DocumentDelta {
+    // Returns None when the two documents are identical
+    pub fn between(before: &ImageData, after: &ImageData) -> Option<Self> {
+        let before_layers: HashMap<LayerId, &Layer> = before.layers.iter().map(|l| (l.id(), l)).collect();
+        let after_layers: HashMap<LayerId, &Layer> = after.layers.iter().map(|l| (l.id(), l)).collect();
+        
+        let mut props = Vec::new();
+        let mut tiles = Vec::new();
+        let mut before_exclusive = Vec::new();
+        let mut after_exclusive = Vec::new();
+        
+        for layer in &before.layers {
+            match after_layers.get(&layer.id()) {
+                // Layers whose tile grid changed are stored whole on both sides
+                Some(other) if layer.data.same_size(&other.data) => {
+                    let (before_props, after_props) = (LayerProps::of(layer), LayerProps::of(other));
+                    if before_props != after_props {
+                        props.push(PropChange {
+                            layer: layer.id(),
+                            before: before_props,
+                            after: after_props,
+                        });
+                    }
+                    
+                    for index in 0..layer.data.tile_count() {
+                        if !layer.data.same_tile(&other.data, index) {
+                            tiles.push(TileChange {
+                                layer: layer.id(),
+                                index,
+                                before: layer.data.tile_slot(index),
+                                after: other.data.tile_slot(index),
+                            });
+                        }
+                    }
+                }
+                Some(other) => {
+                    before_exclusive.push(layer.clone());
+                    after_exclusive.push((*other).clone());
+                }
+                None => before_exclusive.push(layer.clone()),
+            }
+        }
+        
+        for layer in &after.layers {
+            if !before_layers.contains_key(&layer.id()) {
+                after_exclusive.push(layer.clone());
+            }
+        }
+        
+        let delta = Self {
+            before: Side {
+                order: before.layers.iter().map(|l| l.id()).collect(),
+                exclusive: before_exclusive,
+                active_layer: before.active_layer,
+                width: before.width(),
+                height: before.height(),
+            },
+            after: Side {
+                order: after.layers.iter().map(|l| l.id()).collect(),
+                exclusive: after_exclusive,
+                active_layer: after.active_layer,
+                width: after.width(),
+                height: after.height(),
+            },
+            props,
+            tiles,
+        };
+        
+        if delta.is_empty() {
+            None
+        } else {
+            Some(delta)
+        }
+    }
+    
+    pub fn undo(&self, image_data: &mut ImageData) {
+        self.restore(image_data, false);
+    }
+    
+    pub fn redo(&self, image_data: &mut ImageData) {
+        self.restore(image_data, true);
+    }
+    
+    // Approximate memory held by this delta, counting every referenced tile
+    pub fn memory_bytes(&self) -> usize {
+        let tile_bytes: usize = self
+            .tiles
+            .iter()
+            .map(|t| {
+                CHANGE_OVERHEAD
+                    + t.before.as_ref().map_or(0, |_| TILE_BYTES)
+                    + t.after.as_ref().map_or(0, |_| TILE_BYTES)
+            })
+            .sum();
+        let layer_bytes: usize = self
+            .before
+            .exclusive
+            .iter()
+            .chain(&self.after.exclusive)
+            .map(|l| CHANGE_OVERHEAD + l.memory_bytes())
+            .sum();
+        
+        ENTRY_OVERHEAD + tile_bytes + layer_bytes + self.props.len() * CHANGE_OVERHEAD
+    }
+    
+    fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+            && self.props.is_empty()
+            && self.before.exclusive.is_empty()
+            && self.after.exclusive.is_empty()
+            && self.before.order == self.after.order
+            && self.before.active_layer == self.after.active_layer
+            && self.before.width == self.after.width
+            && self.before.height == self.after.height
+    }
+    
+    fn is_pixel_only(&self) -> bool {
+        self.props.is_empty()
+            && self.before.exclusive.is_empty()
+            && self.after.exclusive.is_empty()
+            && self.before.order == self.after.order
+    }
+    
+    fn restore(&self, image_data: &mut ImageData, forward: bool) {
+        let (target, other) = if forward { (&self.after, &self.before) } else { (&self.before, &self.after) };
+        
+        let mut layers: HashMap<LayerId, Layer> = std::mem::take(&mut image_data.layers)
+            .into_iter()
+            .map(|l| (l.id(), l))
+            .collect();
+        
+        for layer in &other.exclusive {
+            layers.remove(&layer.id());
+        }
+        for layer in &target.exclusive {
+            layers.insert(layer.id(), layer.clone());
+        }
+        
+        let mut damaged = Vec::new();
+        for change in &self.tiles {
+            if let Some(layer) = layers.get_mut(&change.layer) {
+                let tile = if forward { &change.after } else { &change.before };
+                layer.data.set_tile_slot(change.index, tile.clone());
+                damaged.push(layer.data.tile_rect_at(change.index));
+            }
+        }
+        
+        for change in &self.props {
+            if let Some(layer) = layers.get_mut(&change.layer) {
+                let props = if forward { &change.after } else { &change.before };
+                props.apply(layer);
+            }
+        }
+        
+        let restored = target.order.iter().filter_map(|id| layers.remove(id)).collect();
+        
+        // Pure paint edits only need the touched tiles re-composited
+        if self.is_pixel_only() && target.width == other.width && target.height == other.height {
+            image_data.layers = restored;
+            image_data.active_layer = target.active_layer;
+            for rect in damaged {
+                image_data.mark_region_dirty(rect);
+            }
+        } else {
+            image_data.set_layers(restored, target.active_layer, target.width, target.height);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use crate::image_ops::tiles::TILE_SIZE;
+    
+    type LayerState = (LayerId, LayerProps, RgbaImage);
+    
+    // Everything a delta is expected to restore
+    fn state(image_data: &ImageData) -> (Vec<LayerState>, usize, u32, u32) {
+        let layers = image_data
+            .layers
+            .iter()
+            .map(|l| (l.id(), LayerProps::of(l), l.data.to_image()))
+            .collect();
+        (layers, image_data.active_layer, image_data.width(), image_data.height())
+    }
+    
+    // Applies `edit` to a copy of `before`, then checks the delta between the
+    // two undoes back to `before` and redoes forward to the edited state again
+    fn round_trip<F: FnOnce(&mut ImageData)>(before: &ImageData, edit: F) -> DocumentDelta {
+        let mut after = before.clone();
+        edit(&mut after);
+        let delta = DocumentDelta::between(before, &after).expect("edit should produce a delta");
+        
+        let mut document = after.clone();
+        delta.undo(&mut document);
+        assert_eq!(state(&document), state(before));
+        delta.redo(&mut document);
+        assert_eq!(state(&document), state(&after));
+        delta
+    }
+    
+    fn two_layer_document() -> ImageData {
+        let mut image_data = ImageData::new(TILE_SIZE * 3, TILE_SIZE * 2);
+        image_data.draw_pixel(1, 1, Rgba([255, 0, 0, 255]));
+        image_data.add_layer("Layer 1".to_string());
+        image_data.draw_pixel(TILE_SIZE + 2, 3, Rgba([0, 255, 0, 255]));
+        image_data
+    }
+    
+    #[test]
+    fn identical_documents_have_no_delta() {
+        let image_data = two_layer_document();
+        assert!(DocumentDelta::between(&image_data, &image_data.clone()).is_none());
+    }
+    
+    #[test]
+    fn painting_swaps_only_the_touched_tiles() {
+        let before = two_layer_document();
+        let delta = round_trip(&before, |image_data| {
+            image_data.draw_pixel(5, 5, Rgba([0, 0, 255, 255]));
+            image_data.draw_pixel(TILE_SIZE * 2 + 1, TILE_SIZE + 1, Rgba([0, 0, 255, 255]));
+        });
+        
+        assert_eq!(delta.tiles.len(), 2);
+        assert!(delta.tiles.iter().all(|t| t.layer == before.layers[1].id()));
+        assert!(delta.is_pixel_only());
+        // The first tile of the upper layer was empty before the edit
+        assert!(delta.tiles[0].before.is_none());
+        assert!(delta.tiles[0].after.is_some());
+    }
+    
+    #[test]
+    fn undo_shares_untouched_tiles_with_the_original() {
+        let before = two_layer_document();
+        let mut after = before.clone();
+        after.draw_pixel(5, 5, Rgba([0, 0, 255, 255]));
+        let delta = DocumentDelta::between(&before, &after).unwrap();
+        
+        delta.undo(&mut after);
+        let (original, restored) = (&before.layers[1].data, &after.layers[1].data);
+        for index in 0..original.tile_count() {
+            assert!(original.same_tile(restored, index), "tile {index} was copied");
+        }
+    }
+    
+    #[test]
+    fn layer_props_round_trip() {
+        let before = two_layer_document();
+        let delta = round_trip(&before, |image_data| {
+            let layer = &mut image_data.layers[0];
+            layer.name = "Renamed".to_string();
+            layer.visible = false;
+            layer.opacity = 0.4;
+            layer.blend_mode = BlendMode::Multiply;
+        });
+        
+        assert_eq!(delta.props.len(), 1);
+        assert!(delta.tiles.is_empty());
+        assert!(!delta.is_pixel_only());
+    }
+    
+    #[test]
+    fn layer_offset_round_trips() {
+        let before = two_layer_document();
+        let delta = round_trip(&before, |image_data| image_data.layers[1].offset = (7, -3));
+        
+        assert_eq!(delta.props.len(), 1);
+        assert!(delta.tiles.is_empty());
+    }
+    
+    #[test]
+    fn added_layer_is_exclusive_to_the_after_side() {
+        let before = two_layer_document();
+        let delta = round_trip(&before, |image_data| image_data.add_layer("Layer 2".to_string()));
+        
+        assert!(delta.before.exclusive.is_empty());
+        assert_eq!(delta.after.exclusive.len(), 1);
+        assert_eq!(delta.after.exclusive[0].name, "Layer 2");
+    }
+    
+    #[test]
+    fn removed_layer_is_exclusive_to_the_before_side() {
+        let before = two_layer_document();
+        let delta = round_trip(&before, |image_data| image_data.remove_layer(0));
+        
+        assert_eq!(delta.before.exclusive.len(), 1);
+        assert_eq!(delta.before.exclusive[0].id(), before.layers[0].id());
+        assert!(delta.after.exclusive.is_empty());
+    }
+    
+    #[test]
+    fn resized_layers_are_stored_whole_on_both_sides() {
+        let before = two_layer_document();
+        let delta = round_trip(&before, |image_data| image_data.resize_canvas(TILE_SIZE, TILE_SIZE, 0, 0));
+        
+        assert_eq!(delta.before.exclusive.len(), 2);
+        assert_eq!(delta.after.exclusive.len(), 2);
+        assert!(delta.tiles.is_empty());
+        assert_eq!((delta.after.width, delta.after.height), (TILE_SIZE, TILE_SIZE));
+    }
+    
+    #[test]
+    fn reordering_keeps_layers_and_restores_the_order() {
+        let before = two_layer_document();
+        let delta = round_trip(&before, |image_data| image_data.move_layer(1, 0));
+        
+        assert!(delta.before.exclusive.is_empty());
+        assert!(delta.after.exclusive.is_empty());
+        assert!(delta.tiles.is_empty());
+        assert_eq!(delta.after.order, vec![before.layers[1].id(), before.layers[0].id()]);
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::BTreeSet;
+use crate::image_ops::tiles::TILE_SIZE;
+
+// Damage is tracked on the layer tile grid so repeated dabs over the same area
+// collapse into a handful of tiles instead of a growing list of rectangles.
+pub const DIRTY_TILE_SIZE: u32 = TILE_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PixelRect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self { x, y, width, height }
+    }
+    
+    // Builds a rect from signed, exclusive bounds clipped to a `width`x`height` canvas
+    pub fn from_bounds(min_x: i64, min_y: i64, max_x: i64, max_y: i64, width: u32, height: u32) -> Option<Self> {
+        let min_x = min_x.max(0);
+        let min_y = min_y.max(0);
+        let max_x = max_x.min(width as i64);
+        let max_y = max_y.min(height as i64);
+        
+        if min_x >= max_x || min_y >= max_y {
+            return None;
+        }
+        
+        Some(Self::new(min_x as u32, min_y as u32, (max_x - min_x) as u32, (max_y - min_y) as u32))
+    }
+    
+    pub fn right(&self) -> u32 {
+        self.x + self.width
+    }
+    
+    pub fn bottom(&self) -> u32 {
+        self.y + self.height
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+    
+    pub fn intersect(&self, other: &PixelRect) -> Option<PixelRect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        
+        if x >= right || y >= bottom {
+            None
+        } else {
+            Some(PixelRect::new(x, y, right - x, bottom - y))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DirtyRegion {
+    tiles: BTreeSet<(u32, u32)>,
+}
+
+impl DirtyRegion {
+    pub fn add(&mut self, rect: PixelRect) {
+        if rect.is_empty() {
+            return;
+        }
+        
+        for ty in rect.y / DIRTY_TILE_SIZE..=(rect.bottom() - 1) / DIRTY_TILE_SIZE {
+            for tx in rect.x / DIRTY_TILE_SIZE..=(rect.right() - 1) / DIRTY_TILE_SIZE {
+                self.tiles.insert((tx, ty));
+            }
+        }
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+    
+    pub fn clear(&mut self) {
+        self.tiles.clear();
+    }
+    
+    // Drains the damaged tiles as rects clipped to the canvas
+    pub fn take_rects(&mut self, width: u32, height: u32) -> Vec<PixelRect> {
+        let canvas = PixelRect::new(0, 0, width, height);
+        std::mem::take(&mut self.tiles)
+            .into_iter()
+            .filter_map(|(tx, ty)| {
+                PixelRect::new(tx * DIRTY_TILE_SIZE, ty * DIRTY_TILE_SIZE, DIRTY_TILE_SIZE, DIRTY_TILE_SIZE)
+                    .intersect(&canvas)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,292 @@
+use image::Rgba;
+use crate::image_ops::dirty::PixelRect;
+
+// Pixels just outside the tolerance that still get partial coverage when
+// anti-aliasing, as a fraction of the full colour range
+const ANTI_ALIAS_BAND: f32 = 0.25;
+
+#[derive(Debug, Clone)]
+pub struct FillOptions {
+    // Largest colour distance (0..=1) still treated as the clicked colour
+    pub tolerance: f32,
+    // Only fill pixels connected to the clicked one
+    pub contiguous: bool,
+    pub anti_alias: bool,
+    // Match colours against the flattened image instead of the active layer
+    pub sample_merged: bool,
+}
+
+impl Default for FillOptions {
+    fn default() -> Self {
+        Self {
+            tolerance: 0.1,
+            contiguous: true,
+            anti_alias: true,
+            sample_merged: false,
+        }
+    }
+}
+
+// Per-pixel coverage (0..=1) of a region, with the bounds of everything
+// that has non-zero coverage
+pub struct CoverageMask {
+    width: u32,
+    height: u32,
+    coverage: Vec<f32>,
+    bounds: Option<(u32, u32, u32, u32)>,
+}
+
+impl CoverageMask {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            coverage: vec![0.0; (width * height) as usize],
+            bounds: None,
+        }
+    }
+    
+    pub fn get(&self, x: u32, y: u32) -> f32 {
+        self.coverage[(y * self.width + x) as usize]
+    }
+    
+    pub fn set(&mut self, x: u32, y: u32, value: f32) {
+        self.coverage[(y * self.width + x) as usize] = value;
+        if value > 0.0 {
+            self.bounds = Some(match self.bounds {
+                Some((min_x, min_y, max_x, max_y)) => (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)),
+                None => (x, y, x, y),
+            });
+        }
+    }
+    
+    pub fn bounds(&self) -> Option<PixelRect> {
+        self.bounds.map(|(min_x, min_y, max_x, max_y)| {
+            PixelRect::new(min_x, min_y, max_x - min_x + 1, max_y - min_y + 1)
+        })
+    }
+}
+
+// Distance between two colours as the largest channel difference (0..=1).
+// Colour channels are weighted by alpha so all transparent pixels match.
+pub fn color_distance(a: Rgba<u8>, b: Rgba<u8>) -> f32 {
+    let alpha_a = a[3] as f32 / 255.0;
+    let alpha_b = b[3] as f32 / 255.0;
+    
+    let mut distance = (alpha_a - alpha_b).abs();
+    for i in 0..3 {
+        let channel = (a[i] as f32 * alpha_a - b[i] as f32 * alpha_b).abs() / 255.0;
+        distance = distance.max(channel);
+    }
+    distance
+}
+
+// Finds the pixels a paint bucket click at (seed_x, seed_y) covers.
+// `sample` returns the colour to match at a pixel.
+pub fn flood_fill<F>(width: u32, height: u32, seed_x: u32, seed_y: u32, options: &FillOptions, sample: F) -> CoverageMask
+where
+    F: Fn(u32, u32) -> Rgba<u8>,
+{
+    let mut mask = CoverageMask::new(width, height);
+    if seed_x >= width || seed_y >= height {
+        return mask;
+    }
+    
+    let target = sample(seed_x, seed_y);
+    let tolerance = options.tolerance.clamp(0.0, 1.0);
+    let matches = |x: u32, y: u32| color_distance(sample(x, y), target) <= tolerance;
+    
+    if options.contiguous {
+        // Scanline fill: fill a whole horizontal span, then queue the start
+        // of every matching run directly above and below it
+        let mut stack = vec![(seed_x, seed_y)];
+        while let Some((x, y)) = stack.pop() {
+            if mask.get(x, y) > 0.0 {
+                continue;
+            }
+            
+            let mut left = x;
+            while left > 0 && mask.get(left - 1, y) == 0.0 && matches(left - 1, y) {
+                left -= 1;
+            }
+            let mut right = x;
+            while right + 1 < width && mask.get(right + 1, y) == 0.0 && matches(right + 1, y) {
+                right += 1;
+            }
+            
+            for i in left..=right {
+                mask.set(i, y, 1.0);
+            }
+            
+            let above = y.checked_sub(1);
+            let below = if y + 1 < height { Some(y + 1) } else { None };
+            for row in [above, below].into_iter().flatten() {
+                let mut in_run = false;
+                for i in left..=right {
+                    let open = mask.get(i, row) == 0.0 && matches(i, row);
+                    if open && !in_run {
+                        stack.push((i, row));
+                    }
+                    in_run = open;
+                }
+            }
+        }
+    } else {
+        for y in 0..height {
+            for x in 0..width {
+                if matches(x, y) {
+                    mask.set(x, y, 1.0);
+                }
+            }
+        }
+    }
+    
+    if options.anti_alias {
+        soften_edges(&mut mask, target, tolerance, &sample);
+    }
+    
+    mask
+}
+
+// Gives pixels bordering the filled area partial coverage based on how
+// far their colour is past the tolerance, so the fill blends into
+// anti-aliased outlines instead of leaving a halo
+fn soften_edges<F>(mask: &mut CoverageMask, target: Rgba<u8>, tolerance: f32, sample: &F)
+where
+    F: Fn(u32, u32) -> Rgba<u8>,
+{
+    let bounds = match mask.bounds() {
+        Some(bounds) => bounds,
+        None => return,
+    };
+    
+    let (width, height) = (mask.width, mask.height);
+    let is_filled = |mask: &CoverageMask, x: i64, y: i64| {
+        x >= 0 && y >= 0 && x < width as i64 && y < height as i64 && mask.get(x as u32, y as u32) >= 1.0
+    };
+    
+    let mut edges = Vec::new();
+    let min_x = bounds.x.saturating_sub(1);
+    let min_y = bounds.y.saturating_sub(1);
+    let max_x = (bounds.right() + 1).min(width);
+    let max_y = (bounds.bottom() + 1).min(height);
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            if mask.get(x, y) > 0.0 {
+                continue;
+            }
+            
+            let (ix, iy) = (x as i64, y as i64);
+            let borders_fill = is_filled(mask, ix - 1, iy)
+                || is_filled(mask, ix + 1, iy)
+                || is_filled(mask, ix, iy - 1)
+                || is_filled(mask, ix, iy + 1);
+            if !borders_fill {
+                continue;
+            }
+            
+            let excess = color_distance(sample(x, y), target) - tolerance;
+            let coverage = 1.0 - excess / ANTI_ALIAS_BAND;
+            if coverage > 0.0 {
+                edges.push((x, y, coverage.min(1.0)));
+            }
+        }
+    }
+    
+    for (x, y, coverage) in edges {
+        mask.set(x, y, coverage);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+    use crate::image_ops::ImageData;
+    
+    const GREY: Rgba<u8> = Rgba([100, 100, 100, 255]);
+    const REDDER: Rgba<u8> = Rgba([110, 100, 100, 255]);
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+    
+    fn options(tolerance: f32, contiguous: bool, anti_alias: bool) -> FillOptions {
+        FillOptions { tolerance, contiguous, anti_alias, sample_merged: false }
+    }
+    
+    fn fill_image(image: &RgbaImage, options: &FillOptions) -> CoverageMask {
+        flood_fill(image.width(), image.height(), 0, 0, options, |x, y| *image.get_pixel(x, y))
+    }
+    
+    fn filled_columns(mask: &CoverageMask) -> Vec<u32> {
+        (0..mask.width).filter(|&x| (0..mask.height).all(|y| mask.get(x, y) == 1.0)).collect()
+    }
+    
+    #[test]
+    fn tolerance_decides_whether_similar_colours_fill() {
+        let image = RgbaImage::from_fn(6, 3, |x, _| if x < 3 { GREY } else { REDDER });
+        
+        let exact = fill_image(&image, &options(0.0, true, false));
+        assert_eq!(filled_columns(&exact), [0, 1, 2]);
+        assert_eq!(exact.get(3, 1), 0.0);
+        assert_eq!(exact.bounds(), Some(PixelRect::new(0, 0, 3, 3)));
+        
+        let loose = fill_image(&image, &options(0.05, true, false));
+        assert_eq!(filled_columns(&loose), [0, 1, 2, 3, 4, 5]);
+    }
+    
+    #[test]
+    fn contiguous_fill_stops_at_a_barrier() {
+        let image = RgbaImage::from_fn(5, 3, |x, _| if x == 2 { BLACK } else { GREY });
+        
+        let contiguous = fill_image(&image, &options(0.0, true, false));
+        assert_eq!(filled_columns(&contiguous), [0, 1]);
+        assert!((0..3).all(|y| contiguous.get(2, y) == 0.0 && contiguous.get(3, y) == 0.0));
+        
+        let global = fill_image(&image, &options(0.0, false, false));
+        assert_eq!(filled_columns(&global), [0, 1, 3, 4]);
+        assert!((0..3).all(|y| global.get(2, y) == 0.0));
+    }
+    
+    #[test]
+    fn anti_aliasing_only_softens_edge_pixels() {
+        // A slightly different column between the fill and a black wall
+        let edge = Rgba([125, 100, 100, 255]);
+        let image = RgbaImage::from_fn(6, 3, |x, _| match x {
+            0..=2 => GREY,
+            3 => edge,
+            _ => BLACK,
+        });
+        
+        let hard = fill_image(&image, &options(0.0, true, false));
+        assert!((0..3).all(|y| hard.get(3, y) == 0.0));
+        
+        let soft = fill_image(&image, &options(0.0, true, true));
+        assert_eq!(filled_columns(&soft), [0, 1, 2]);
+        let expected = 1.0 - (25.0 / 255.0) / ANTI_ALIAS_BAND;
+        for y in 0..3 {
+            assert!((soft.get(3, y) - expected).abs() < 1e-4, "{}", soft.get(3, y));
+            assert_eq!(soft.get(4, y), 0.0);
+            assert_eq!(soft.get(5, y), 0.0);
+        }
+    }
+    
+    #[test]
+    fn sample_merged_matches_the_composite_but_paints_the_active_layer() {
+        let mut image_data = ImageData::new(4, 1);
+        for x in 0..4 {
+            image_data.draw_pixel(x, 0, if x < 2 { GREY } else { BLACK });
+        }
+        image_data.add_layer("Paint".to_string());
+        let fill = Rgba([0, 200, 0, 255]);
+        
+        let mut merged = image_data.clone();
+        merged.fill(0, 0, fill, &FillOptions { sample_merged: true, ..options(0.0, true, false) });
+        let painted: Vec<_> = (0..4).map(|x| merged.layers[1].data.get_pixel(x, 0)).collect();
+        assert_eq!(painted, [fill, fill, Rgba([0, 0, 0, 0]), Rgba([0, 0, 0, 0])]);
+        assert_eq!(merged.layers[0].data, image_data.layers[0].data);
+        
+        // The empty active layer alone matches everywhere
+        let mut layer_only = image_data.clone();
+        layer_only.fill(0, 0, fill, &options(0.0, true, false));
+        assert!((0..4).all(|x| layer_only.layers[1].data.get_pixel(x, 0) == fill));
+    }
+}
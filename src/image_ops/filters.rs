@@ -1,22 +1,509 @@
-use image::DynamicImage;
+use image::Rgba;
+use crate::image_ops::tiles::{TiledImage, TRANSPARENT};
 
-pub fn blur(img: &DynamicImage, radius: f32) -> DynamicImage {
-    img.blur(radius)
+// What a kernel reads when it reaches past the edge of the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeMode {
+    // Repeat the nearest edge pixel
+    Clamp,
+    // Continue from the opposite edge, for tiling textures
+    Wrap,
+    Transparent,
 }
 
-pub fn sharpen(img: &DynamicImage) -> DynamicImage {
-    // Basic unsharp mask
-    let blurred = img.blur(1.0);
-    // TODO: Implement proper unsharp mask
-    img.clone()
+impl EdgeMode {
+    pub const ALL: [EdgeMode; 3] = [EdgeMode::Clamp, EdgeMode::Wrap, EdgeMode::Transparent];
+    
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeMode::Clamp => "Repeat Edge Pixels",
+            EdgeMode::Wrap => "Wrap Around",
+            EdgeMode::Transparent => "Transparent",
+        }
+    }
 }
 
-pub fn edge_detect(img: &DynamicImage) -> DynamicImage {
-    // TODO: Implement edge detection filter
-    img.clone()
+// Convolution kernel. Weights are applied as written, row by row from the
+// top-left, centred on the pixel being computed, so sizes should be odd.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Kernel {
+    // Equivalent to the outer product of the two vectors, applied as a
+    // horizontal pass then a vertical pass
+    Separable { horizontal: Vec<f32>, vertical: Vec<f32> },
+    Full { width: usize, weights: Vec<f32> },
 }
 
-pub fn emboss(img: &DynamicImage) -> DynamicImage {
-    // TODO: Implement emboss filter
-    img.clone()
+impl Kernel {
+    pub fn full(width: usize, weights: &[f32]) -> Self {
+        Kernel::Full { width, weights: weights.to_vec() }
+    }
+}
+
+// Layer pixels as floats (0..=255 per channel) for filtering, optionally
+// with colour premultiplied by alpha
+#[derive(Debug, Clone, PartialEq)]
+pub struct FloatImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 4]>,
+}
+
+impl FloatImage {
+    pub fn from_tiled(image: &TiledImage, premultiplied: bool) -> Self {
+        let (width, height) = (image.width(), image.height());
+        let mut pixels = vec![[0.0f32; 4]; (width * height) as usize];
+        for index in 0..image.tile_count() {
+            let tile = match image.tile_slot(index) {
+                Some(tile) => tile,
+                None => continue,
+            };
+            let rect = image.tile_rect_at(index);
+            for y in 0..rect.height {
+                for x in 0..rect.width {
+                    let pixel = tile.get_pixel(x, y);
+                    let scale = if premultiplied { pixel[3] as f32 / 255.0 } else { 1.0 };
+                    pixels[((rect.y + y) * width + rect.x + x) as usize] = [
+                        pixel[0] as f32 * scale,
+                        pixel[1] as f32 * scale,
+                        pixel[2] as f32 * scale,
+                        pixel[3] as f32,
+                    ];
+                }
+            }
+        }
+        Self { width, height, pixels }
+    }
+    
+    pub fn to_tiled(&self, premultiplied: bool) -> TiledImage {
+        let mut image = TiledImage::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                image.put_pixel(x, y, to_pixel(self.get(x, y), premultiplied));
+            }
+        }
+        image
+    }
+    
+    pub fn get(&self, x: u32, y: u32) -> [f32; 4] {
+        self.pixels[(y * self.width + x) as usize]
+    }
+    
+    // Pixel at a position that may lie outside the image
+    fn sample(&self, x: i64, y: i64, edge: EdgeMode) -> [f32; 4] {
+        let (width, height) = (self.width as i64, self.height as i64);
+        let (x, y) = match edge {
+            EdgeMode::Clamp => (x.clamp(0, width - 1), y.clamp(0, height - 1)),
+            EdgeMode::Wrap => (x.rem_euclid(width), y.rem_euclid(height)),
+            EdgeMode::Transparent => {
+                if x < 0 || y < 0 || x >= width || y >= height {
+                    return [0.0; 4];
+                }
+                (x, y)
+            }
+        };
+        self.get(x as u32, y as u32)
+    }
+}
+
+// Runs `kernel` over every channel of `src`
+pub fn convolve(src: &FloatImage, kernel: &Kernel, edge: EdgeMode) -> FloatImage {
+    match kernel {
+        Kernel::Separable { horizontal, vertical } => {
+            let pass = convolve_full(src, horizontal.len(), horizontal, edge);
+            convolve_full(&pass, 1, vertical, edge)
+        }
+        Kernel::Full { width, weights } => convolve_full(src, *width, weights, edge),
+    }
+}
+
+fn convolve_full(src: &FloatImage, kernel_width: usize, weights: &[f32], edge: EdgeMode) -> FloatImage {
+    let kernel_height = weights.len() / kernel_width.max(1);
+    let (center_x, center_y) = ((kernel_width / 2) as i64, (kernel_height / 2) as i64);
+    
+    let mut pixels = Vec::with_capacity(src.pixels.len());
+    for y in 0..src.height as i64 {
+        for x in 0..src.width as i64 {
+            let mut sums = [0.0f32; 4];
+            for (i, weight) in weights.iter().enumerate() {
+                if *weight == 0.0 {
+                    continue;
+                }
+                let kx = (i % kernel_width) as i64 - center_x;
+                let ky = (i / kernel_width) as i64 - center_y;
+                let pixel = src.sample(x + kx, y + ky, edge);
+                for c in 0..4 {
+                    sums[c] += pixel[c] * weight;
+                }
+            }
+            pixels.push(sums);
+        }
+    }
+    FloatImage { width: src.width, height: src.height, pixels }
+}
+
+// Normalised 1D Gaussian with standard deviation `radius`, reaching out
+// three deviations on each side
+pub fn gaussian_kernel(radius: f32) -> Vec<f32> {
+    let sigma = radius.max(0.1);
+    let half = (sigma * 3.0).ceil() as i32;
+    let weights: Vec<f32> = (-half..=half)
+        .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    weights.into_iter().map(|weight| weight / total).collect()
+}
+
+// Gradient operators for edge detection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeOperator {
+    Sobel,
+    Prewitt,
+    Laplacian,
+}
+
+impl EdgeOperator {
+    pub const ALL: [EdgeOperator; 3] = [EdgeOperator::Sobel, EdgeOperator::Prewitt, EdgeOperator::Laplacian];
+    
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeOperator::Sobel => "Sobel",
+            EdgeOperator::Prewitt => "Prewitt",
+            EdgeOperator::Laplacian => "Laplacian",
+        }
+    }
+    
+    // Horizontal and vertical gradient kernels, or a single kernel for
+    // operators that aren't directional
+    pub fn kernels(&self) -> Vec<Kernel> {
+        match self {
+            EdgeOperator::Sobel => vec![
+                Kernel::Separable { horizontal: vec![-1.0, 0.0, 1.0], vertical: vec![1.0, 2.0, 1.0] },
+                Kernel::Separable { horizontal: vec![1.0, 2.0, 1.0], vertical: vec![-1.0, 0.0, 1.0] },
+            ],
+            EdgeOperator::Prewitt => vec![
+                Kernel::Separable { horizontal: vec![-1.0, 0.0, 1.0], vertical: vec![1.0, 1.0, 1.0] },
+                Kernel::Separable { horizontal: vec![1.0, 1.0, 1.0], vertical: vec![-1.0, 0.0, 1.0] },
+            ],
+            EdgeOperator::Laplacian => vec![Kernel::full(3, &[
+                0.0, 1.0, 0.0,
+                1.0, -4.0, 1.0,
+                0.0, 1.0, 0.0,
+            ])],
+        }
+    }
+}
+
+// Filters > ... entries, applied to a whole layer
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    GaussianBlur { radius: f32 },
+    // `amount` is a fraction (1.0 = 100%); pixels differing from their
+    // blurred version by less than `threshold` levels are left alone
+    UnsharpMask { amount: f32, radius: f32, threshold: u8 },
+    EdgeDetect(EdgeOperator),
+    // Light comes from `angle` degrees counter-clockwise from the right;
+    // `height` is how far in pixels the relief reaches
+    Emboss { angle: f32, height: u32, amount: f32 },
+}
+
+impl Filter {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filter::GaussianBlur { .. } => "Gaussian Blur",
+            Filter::UnsharpMask { .. } => "Unsharp Mask",
+            Filter::EdgeDetect(_) => "Edge Detect",
+            Filter::Emboss { .. } => "Emboss",
+        }
+    }
+    
+    pub fn apply(&self, src: &TiledImage, edge: EdgeMode) -> TiledImage {
+        match self {
+            Filter::GaussianBlur { radius } => gaussian_blur(src, *radius, edge),
+            Filter::UnsharpMask { amount, radius, threshold } => unsharp_mask(src, *amount, *radius, *threshold, edge),
+            Filter::EdgeDetect(operator) => detect_edges(src, *operator, edge),
+            Filter::Emboss { angle, height, amount } => emboss_relief(src, *angle, *height, *amount, edge),
+        }
+    }
+}
+
+pub fn gaussian_blur(src: &TiledImage, radius: f32, edge: EdgeMode) -> TiledImage {
+    let weights = gaussian_kernel(radius);
+    let kernel = Kernel::Separable { horizontal: weights.clone(), vertical: weights };
+    // Premultiplied so transparent pixels don't darken the edges
+    convolve(&FloatImage::from_tiled(src, true), &kernel, edge).to_tiled(true)
+}
+
+// Sharpens by adding back the difference between each pixel and a blurred copy
+pub fn unsharp_mask(src: &TiledImage, amount: f32, radius: f32, threshold: u8, edge: EdgeMode) -> TiledImage {
+    let weights = gaussian_kernel(radius);
+    let kernel = Kernel::Separable { horizontal: weights.clone(), vertical: weights };
+    let blurred = convolve(&FloatImage::from_tiled(src, true), &kernel, edge);
+    
+    map_layer(src, |x, y, pixel| {
+        let blurred = to_pixel(blurred.get(x, y), true);
+        if blurred[3] == 0 {
+            return pixel;
+        }
+        let difference: [f32; 3] = std::array::from_fn(|c| pixel[c] as f32 - blurred[c] as f32);
+        if difference.iter().all(|d| d.abs() < threshold as f32) {
+            return pixel;
+        }
+        
+        let mut out = pixel;
+        for c in 0..3 {
+            out[c] = (pixel[c] as f32 + difference[c] * amount).round().clamp(0.0, 255.0) as u8;
+        }
+        out
+    })
+}
+
+// Gradient magnitude of each colour channel; transparency is kept as is
+pub fn detect_edges(src: &TiledImage, operator: EdgeOperator, edge: EdgeMode) -> TiledImage {
+    let straight = FloatImage::from_tiled(src, false);
+    let gradients: Vec<FloatImage> = operator.kernels().iter().map(|kernel| convolve(&straight, kernel, edge)).collect();
+    
+    map_layer(src, |x, y, pixel| {
+        let mut out = pixel;
+        for c in 0..3 {
+            let magnitude = gradients.iter().map(|g| g.get(x, y)[c].powi(2)).sum::<f32>().sqrt();
+            out[c] = magnitude.round().clamp(0.0, 255.0) as u8;
+        }
+        out
+    })
+}
+
+// Grey relief lit from `angle`: each pixel becomes mid grey plus the
+// brightness gradient along the light direction
+pub fn emboss_relief(src: &TiledImage, angle: f32, height: u32, amount: f32, edge: EdgeMode) -> TiledImage {
+    let reach = height.max(1) as i64;
+    let size = (2 * reach + 1) as usize;
+    let (sin, cos) = angle.to_radians().sin_cos();
+    // Screen y points down, so the light's y component is flipped
+    let mut weights: Vec<f32> = (0..size * size)
+        .map(|i| {
+            let kx = (i % size) as i64 - reach;
+            let ky = (i / size) as i64 - reach;
+            kx as f32 * cos - ky as f32 * sin
+        })
+        .collect();
+    // Scale so the lit side sums to one
+    let positive: f32 = weights.iter().filter(|w| **w > 0.0).sum();
+    for weight in weights.iter_mut() {
+        *weight /= positive;
+    }
+    
+    let relief = convolve(&FloatImage::from_tiled(src, false), &Kernel::Full { width: size, weights }, edge);
+    
+    map_layer(src, |x, y, pixel| {
+        let [r, g, b, _] = relief.get(x, y);
+        let luminance = 0.299 * r + 0.587 * g + 0.114 * b;
+        let value = (128.0 + luminance * amount).round().clamp(0.0, 255.0) as u8;
+        Rgba([value, value, value, pixel[3]])
+    })
+}
+
+// Copy of `src` with `f` applied to each painted pixel and its position
+fn map_layer<F>(src: &TiledImage, f: F) -> TiledImage
+where
+    F: Fn(u32, u32, Rgba<u8>) -> Rgba<u8>,
+{
+    let mut out = TiledImage::new(src.width(), src.height());
+    for index in 0..src.tile_count() {
+        let tile = match src.tile_slot(index) {
+            Some(tile) => tile,
+            None => continue,
+        };
+        let rect = src.tile_rect_at(index);
+        for y in 0..rect.height {
+            for x in 0..rect.width {
+                let pixel = *tile.get_pixel(x, y);
+                if pixel[3] != 0 {
+                    out.put_pixel(rect.x + x, rect.y + y, f(rect.x + x, rect.y + y, pixel));
+                }
+            }
+        }
+    }
+    out
+}
+
+fn to_pixel(value: [f32; 4], premultiplied: bool) -> Rgba<u8> {
+    let alpha = value[3].clamp(0.0, 255.0);
+    if alpha < 0.5 {
+        return TRANSPARENT;
+    }
+    let scale = if premultiplied { 255.0 / alpha } else { 1.0 };
+    Rgba([
+        (value[0] * scale).round().clamp(0.0, 255.0) as u8,
+        (value[1] * scale).round().clamp(0.0, 255.0) as u8,
+        (value[2] * scale).round().clamp(0.0, 255.0) as u8,
+        alpha.round() as u8,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // Opaque grey image from row-major values
+    fn grey(width: u32, values: &[u8]) -> TiledImage {
+        let height = values.len() as u32 / width;
+        let mut image = TiledImage::new(width, height);
+        for (i, value) in values.iter().enumerate() {
+            image.put_pixel(i as u32 % width, i as u32 / width, Rgba([*value, *value, *value, 255]));
+        }
+        image
+    }
+    
+    fn red_channel(image: &TiledImage) -> Vec<u8> {
+        image.to_image().pixels().map(|pixel| pixel[0]).collect()
+    }
+    
+    fn float_row(values: &[f32]) -> FloatImage {
+        FloatImage {
+            width: values.len() as u32,
+            height: 1,
+            pixels: values.iter().map(|v| [*v, *v, *v, 255.0]).collect(),
+        }
+    }
+    
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!((a - e).abs() < 1e-3, "index {i}: {a} != {e} in {actual:?}");
+        }
+    }
+    
+    #[test]
+    fn box_kernel_follows_each_edge_mode() {
+        let src = float_row(&[10.0, 20.0, 30.0, 40.0]);
+        let kernel = Kernel::full(3, &[1.0 / 3.0; 3]);
+        let cases = [
+            (EdgeMode::Clamp, [40.0 / 3.0, 20.0, 30.0, 110.0 / 3.0]),
+            (EdgeMode::Wrap, [70.0 / 3.0, 20.0, 30.0, 80.0 / 3.0]),
+            (EdgeMode::Transparent, [10.0, 20.0, 30.0, 70.0 / 3.0]),
+        ];
+        
+        for (edge, expected) in cases {
+            let out = convolve(&src, &kernel, edge);
+            let red: Vec<f32> = out.pixels.iter().map(|p| p[0]).collect();
+            assert_close(&red, &expected);
+        }
+    }
+    
+    #[test]
+    fn box_blur_spreads_a_single_pixel_evenly() {
+        let mut values = [0u8; 9];
+        values[4] = 90;
+        let src = FloatImage::from_tiled(&grey(3, &values), false);
+        let out = convolve(&src, &Kernel::full(3, &[1.0 / 9.0; 9]), EdgeMode::Transparent);
+        
+        let red: Vec<f32> = out.pixels.iter().map(|p| p[0]).collect();
+        assert_close(&red, &[10.0; 9]);
+    }
+    
+    #[test]
+    fn separable_kernel_matches_its_full_outer_product() {
+        let values: Vec<u8> = (0..20).map(|i| (i * 37 % 256) as u8).collect();
+        let src = FloatImage::from_tiled(&grey(5, &values), false);
+        let taps = [1.0, 2.0, 1.0];
+        let full: Vec<f32> = (0..9).map(|i| taps[i / 3] * taps[i % 3] / 16.0).collect();
+        let taps: Vec<f32> = taps.iter().map(|t| t / 4.0).collect();
+        
+        for edge in EdgeMode::ALL {
+            let separable = convolve(&src, &Kernel::Separable { horizontal: taps.clone(), vertical: taps.clone() }, edge);
+            let full = convolve(&src, &Kernel::full(3, &full), edge);
+            for (a, b) in separable.pixels.iter().zip(&full.pixels) {
+                assert_close(a, b);
+            }
+        }
+    }
+    
+    #[test]
+    fn gaussian_kernel_is_normalised_and_symmetric() {
+        let kernel = gaussian_kernel(1.0);
+        assert_eq!(kernel.len(), 7);
+        assert!((kernel.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        for i in 0..3 {
+            assert!((kernel[i] - kernel[6 - i]).abs() < 1e-7);
+        }
+        // One deviation out the weight drops by e^(-1/2)
+        assert!((kernel[4] / kernel[3] - (-0.5f32).exp()).abs() < 1e-5);
+    }
+    
+    #[test]
+    fn gaussian_blur_keeps_flat_areas_and_colour_at_transparent_edges() {
+        let flat = grey(4, &[120; 16]);
+        for edge in [EdgeMode::Clamp, EdgeMode::Wrap] {
+            assert_eq!(red_channel(&gaussian_blur(&flat, 1.5, edge)), vec![120; 16]);
+        }
+        
+        // A lone red pixel fades out but stays red rather than darkening
+        let mut dot = TiledImage::new(5, 5);
+        dot.put_pixel(2, 2, Rgba([255, 0, 0, 255]));
+        let blurred = gaussian_blur(&dot, 1.0, EdgeMode::Transparent);
+        let neighbour = blurred.get_pixel(3, 2);
+        assert_eq!(&neighbour.0[..3], &[255, 0, 0]);
+        assert!(neighbour[3] > 0 && neighbour[3] < blurred.get_pixel(2, 2)[3]);
+    }
+    
+    #[test]
+    fn unsharp_mask_steepens_a_step() {
+        let step = grey(10, &[50, 50, 50, 50, 50, 200, 200, 200, 200, 200]);
+        let sharpened = red_channel(&unsharp_mask(&step, 1.0, 1.0, 0, EdgeMode::Clamp));
+        
+        assert!(sharpened[4] < 50, "{sharpened:?}");
+        assert!(sharpened[5] > 200, "{sharpened:?}");
+        // Beyond the blur's reach nothing changes
+        assert_eq!((sharpened[0], sharpened[9]), (50, 200));
+        
+        // Differences under the threshold are left alone
+        let gentle = red_channel(&unsharp_mask(&step, 1.0, 1.0, 255, EdgeMode::Clamp));
+        assert_eq!(gentle, red_channel(&step));
+    }
+    
+    #[test]
+    fn sobel_follows_each_edge_mode() {
+        // Three identical rows, so only the middle one is free of the
+        // vertical gradient that Transparent adds at the top and bottom
+        let ramp = grey(3, &[0, 10, 30, 0, 10, 30, 0, 10, 30]);
+        let cases = [
+            (EdgeMode::Clamp, [40, 120, 80]),
+            (EdgeMode::Wrap, [80, 120, 40]),
+            (EdgeMode::Transparent, [40, 120, 40]),
+        ];
+        
+        for (edge, expected) in cases {
+            let edges = red_channel(&detect_edges(&ramp, EdgeOperator::Sobel, edge));
+            assert_eq!(&edges[3..6], &expected, "{edge:?}");
+        }
+    }
+    
+    #[test]
+    fn laplacian_of_a_single_pixel() {
+        let mut values = [0u8; 9];
+        values[4] = 10;
+        let edges = red_channel(&detect_edges(&grey(3, &values), EdgeOperator::Laplacian, EdgeMode::Transparent));
+        assert_eq!(edges, vec![0, 10, 0, 10, 40, 10, 0, 10, 0]);
+    }
+    
+    #[test]
+    fn emboss_leaves_flat_areas_mid_grey() {
+        let flat = grey(4, &[200; 16]);
+        let embossed = red_channel(&emboss_relief(&flat, 135.0, 1, 1.0, EdgeMode::Clamp));
+        assert_eq!(embossed, vec![128; 16]);
+    }
+    
+    #[test]
+    fn filters_skip_transparent_pixels() {
+        let mut image = TiledImage::new(3, 1);
+        image.put_pixel(0, 0, Rgba([200, 200, 200, 255]));
+        for filter in [
+            Filter::UnsharpMask { amount: 1.0, radius: 1.0, threshold: 0 },
+            Filter::EdgeDetect(EdgeOperator::Sobel),
+            Filter::Emboss { angle: 0.0, height: 1, amount: 1.0 },
+        ] {
+            let out = filter.apply(&image, EdgeMode::Clamp);
+            assert_eq!(out.get_pixel(2, 0), TRANSPARENT, "{}", filter.name());
+        }
+    }
 }
@@ -0,0 +1,124 @@
+use crate::image_ops::adjustments::{Channel, ToneLuts};
+use crate::image_ops::tiles::TiledImage;
+
+// Number of pixels at each value, per channel. Colour channels skip fully
+// transparent pixels; `Rgb` adds the red, green and blue counts together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    counts: [[u32; 256]; 5],
+}
+
+impl Histogram {
+    pub fn of(image: &TiledImage) -> Self {
+        let mut counts = [[0u32; 256]; 5];
+        let mut empty_pixels = 0u32;
+        
+        for index in 0..image.tile_count() {
+            let rect = image.tile_rect_at(index);
+            let tile = match image.tile_slot(index) {
+                Some(tile) => tile,
+                None => {
+                    empty_pixels += rect.width * rect.height;
+                    continue;
+                }
+            };
+            
+            for y in 0..rect.height {
+                for x in 0..rect.width {
+                    let pixel = tile.get_pixel(x, y);
+                    counts[Channel::Alpha.index()][pixel[3] as usize] += 1;
+                    if pixel[3] == 0 {
+                        continue;
+                    }
+                    for (channel, value) in [Channel::Red, Channel::Green, Channel::Blue].into_iter().zip(pixel.0) {
+                        counts[channel.index()][value as usize] += 1;
+                        counts[Channel::Rgb.index()][value as usize] += 1;
+                    }
+                }
+            }
+        }
+        counts[Channel::Alpha.index()][0] += empty_pixels;
+        
+        Self { counts }
+    }
+    
+    pub fn counts(&self, channel: Channel) -> &[u32; 256] {
+        &self.counts[channel.index()]
+    }
+    
+    // What the histogram becomes once `luts` are applied to the image
+    pub fn remapped(&self, luts: &ToneLuts) -> Self {
+        let mut counts = [[0u32; 256]; 5];
+        for channel in [Channel::Red, Channel::Green, Channel::Blue, Channel::Alpha] {
+            for (value, count) in self.counts(channel).iter().enumerate() {
+                let mapped = luts.map(channel, value as u8) as usize;
+                counts[channel.index()][mapped] += count;
+                if channel != Channel::Alpha {
+                    counts[Channel::Rgb.index()][mapped] += count;
+                }
+            }
+        }
+        Self { counts }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use crate::image_ops::adjustments::Levels;
+    use crate::image_ops::tiles::TILE_SIZE;
+    
+    // Three visible pixels and one fully transparent pixel in the first
+    // tile; the second tile is never painted
+    fn tiny_image() -> TiledImage {
+        let mut image = TiledImage::new(TILE_SIZE + 2, 2);
+        image.put_pixel(0, 0, Rgba([10, 20, 30, 255]));
+        image.put_pixel(1, 0, Rgba([10, 40, 50, 128]));
+        image.put_pixel(0, 1, Rgba([20, 20, 20, 255]));
+        image.put_pixel(1, 1, Rgba([99, 99, 99, 0]));
+        image
+    }
+    
+    fn nonzero(counts: &[u32; 256]) -> Vec<(usize, u32)> {
+        counts.iter().enumerate().filter(|(_, &count)| count > 0).map(|(value, &count)| (value, count)).collect()
+    }
+    
+    #[test]
+    fn counts_skip_transparent_pixels_except_in_alpha() {
+        let histogram = Histogram::of(&tiny_image());
+        assert_eq!(nonzero(histogram.counts(Channel::Red)), vec![(10, 2), (20, 1)]);
+        assert_eq!(nonzero(histogram.counts(Channel::Green)), vec![(20, 2), (40, 1)]);
+        assert_eq!(nonzero(histogram.counts(Channel::Blue)), vec![(20, 1), (30, 1), (50, 1)]);
+        assert_eq!(
+            nonzero(histogram.counts(Channel::Rgb)),
+            vec![(10, 2), (20, 4), (30, 1), (40, 1), (50, 1)]
+        );
+        
+        // Every pixel of the canvas is counted in alpha, painted or not:
+        // the rest of the first tile plus the whole unpainted second tile
+        let alpha = histogram.counts(Channel::Alpha);
+        assert_eq!(nonzero(alpha), vec![(0, TILE_SIZE * 2 - 3 + 2 * 2), (128, 1), (255, 2)]);
+        assert_eq!(alpha.iter().sum::<u32>(), (TILE_SIZE + 2) * 2);
+    }
+    
+    #[test]
+    fn remapped_matches_the_histogram_of_the_adjusted_image() {
+        let mut levels = Levels::default();
+        levels.channels[Channel::Rgb.index()].input_white = 127;
+        levels.channels[Channel::Blue.index()].output_black = 100;
+        let luts = levels.luts();
+        
+        let image = tiny_image();
+        let mut adjusted = TiledImage::new(image.width(), image.height());
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                adjusted.put_pixel(x, y, luts.apply(image.get_pixel(x, y)));
+            }
+        }
+        
+        let remapped = Histogram::of(&image).remapped(&luts);
+        assert_eq!(remapped, Histogram::of(&adjusted));
+        assert_eq!(nonzero(remapped.counts(Channel::Red)), vec![(20, 2), (40, 1)]);
+    }
+}
@@ -1,68 +1,349 @@
 use crate::image_ops::ImageData;
+use crate::image_ops::delta::DocumentDelta;
 use std::collections::VecDeque;
 
+pub const DEFAULT_HISTORY_BUDGET: usize = 256 * 1024 * 1024;
+
+struct HistoryEntry {
+    label: String,
+    delta: DocumentDelta,
+    memory_bytes: usize,
+}
+
 pub struct ImageHistory {
-    history: VecDeque<ImageData>,
+    history: VecDeque<HistoryEntry>,
     current_index: usize,
-    max_history: usize,
+    memory_budget: usize,
+    memory_used: usize,
+    pending: Option<(String, ImageData)>,
+    depth: usize,
+    base_label: String,
+}
+
+impl Default for ImageHistory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ImageHistory {
     pub fn new() -> Self {
+        Self::with_budget(DEFAULT_HISTORY_BUDGET)
+    }
+    
+    pub fn with_budget(memory_budget: usize) -> Self {
         Self {
             history: VecDeque::new(),
             current_index: 0,
-            max_history: 50, // Keep last 50 states
+            memory_budget,
+            memory_used: 0,
+            pending: None,
+            depth: 0,
+            base_label: "Initial state".to_string(),
         }
     }
     
-    pub fn push_state(&mut self, image_data: ImageData) {
+    pub fn memory_budget(&self) -> usize {
+        self.memory_budget
+    }
+    
+    pub fn set_memory_budget(&mut self, bytes: usize) {
+        self.memory_budget = bytes;
+        self.enforce_budget();
+    }
+    
+    pub fn memory_used(&self) -> usize {
+        self.memory_used
+    }
+    
+    // Records the change from `before` to `after` as a single undo step.
+    // Nothing is recorded if the two states are identical.
+    pub fn push_state(&mut self, label: &str, before: &ImageData, after: &ImageData) {
+        let delta = match DocumentDelta::between(before, after) {
+            Some(delta) => delta,
+            None => return,
+        };
+        
         // Remove any states after current index (when we're not at the end)
         while self.history.len() > self.current_index {
-            self.history.pop_back();
+            if let Some(entry) = self.history.pop_back() {
+                self.memory_used -= entry.memory_bytes;
+            }
         }
         
         // Add new state
-        self.history.push_back(image_data);
+        let memory_bytes = delta.memory_bytes();
+        self.history.push_back(HistoryEntry {
+            label: label.to_string(),
+            delta,
+            memory_bytes,
+        });
+        self.memory_used += memory_bytes;
         self.current_index = self.history.len();
         
-        // Limit history size
-        while self.history.len() > self.max_history {
-            self.history.pop_front();
-            if self.current_index > 0 {
-                self.current_index -= 1;
+        self.enforce_budget();
+    }
+    
+    // Starts an undoable edit. Nested begin/commit pairs are folded into the
+    // outermost one, so a compound operation still records a single step.
+    pub fn begin(&mut self, label: &str, image_data: &ImageData) {
+        if self.depth == 0 {
+            self.pending = Some((label.to_string(), image_data.clone()));
+        }
+        self.depth += 1;
+    }
+    
+    pub fn commit(&mut self, image_data: &ImageData) {
+        if self.depth == 0 {
+            return;
+        }
+        
+        self.depth -= 1;
+        if self.depth == 0 {
+            if let Some((label, before)) = self.pending.take() {
+                self.push_state(&label, &before, image_data);
             }
         }
     }
     
+    // Abandons the open edit and puts the document back as it was at `begin`
+    pub fn cancel(&mut self, image_data: &mut ImageData) {
+        self.depth = 0;
+        if let Some((_, before)) = self.pending.take() {
+            *image_data = before;
+        }
+    }
+    
+    pub fn in_transaction(&self) -> bool {
+        self.depth > 0
+    }
+    
+    // Runs `edit` as one undoable step
+    pub fn record<F: FnOnce(&mut ImageData)>(&mut self, label: &str, image_data: &mut ImageData, edit: F) {
+        self.begin(label, image_data);
+        edit(image_data);
+        self.commit(image_data);
+    }
+    
     pub fn can_undo(&self) -> bool {
-        self.current_index > 1
+        self.current_index > 0 && !self.in_transaction()
     }
     
     pub fn can_redo(&self) -> bool {
-        self.current_index < self.history.len()
+        self.current_index < self.history.len() && !self.in_transaction()
     }
     
-    pub fn undo(&mut self) -> Option<&ImageData> {
+    pub fn undo(&mut self, image_data: &mut ImageData) -> bool {
         if self.can_undo() {
             self.current_index -= 1;
-            self.history.get(self.current_index - 1)
+            self.history[self.current_index].delta.undo(image_data);
+            true
         } else {
-            None
+            false
         }
     }
     
-    pub fn redo(&mut self) -> Option<&ImageData> {
+    pub fn redo(&mut self, image_data: &mut ImageData) -> bool {
         if self.can_redo() {
+            self.history[self.current_index].delta.redo(image_data);
             self.current_index += 1;
-            self.history.get(self.current_index - 1)
+            true
         } else {
-            None
+            false
+        }
+    }
+    
+    // Steps to the state after `index` entries have been applied (0 is the base state)
+    pub fn jump_to(&mut self, index: usize, image_data: &mut ImageData) -> bool {
+        let index = index.min(self.history.len());
+        if index == self.current_index || self.in_transaction() {
+            return false;
+        }
+        
+        while self.current_index > index {
+            self.undo(image_data);
         }
+        while self.current_index < index {
+            self.redo(image_data);
+        }
+        true
+    }
+    
+    pub fn current_index(&self) -> usize {
+        self.current_index
+    }
+    
+    pub fn labels(&self) -> impl Iterator<Item = &str> {
+        self.history.iter().map(|entry| entry.label.as_str())
+    }
+    
+    pub fn base_label(&self) -> &str {
+        &self.base_label
+    }
+    
+    // Starts a fresh history for a newly created or opened document
+    pub fn reset(&mut self, base_label: &str) {
+        self.clear();
+        self.base_label = base_label.to_string();
     }
     
     pub fn clear(&mut self) {
         self.history.clear();
         self.current_index = 0;
+        self.memory_used = 0;
+        self.pending = None;
+        self.depth = 0;
+    }
+    
+    // Drops the oldest undo steps (then the furthest redo steps) until the
+    // history fits its budget. The most recent step is always kept. Once the
+    // oldest step is gone, the state it produced becomes the new base.
+    fn enforce_budget(&mut self) {
+        while self.memory_used > self.memory_budget && self.history.len() > 1 {
+            let entry = if self.current_index > 0 {
+                self.current_index -= 1;
+                self.history.pop_front().inspect(|entry| self.base_label = entry.label.clone())
+            } else {
+                self.history.pop_back()
+            };
+            
+            if let Some(entry) = entry {
+                self.memory_used -= entry.memory_bytes;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    
+    fn pixels(image_data: &ImageData) -> Vec<(String, image::RgbaImage)> {
+        image_data.layers.iter().map(|l| (l.name.clone(), l.data.to_image())).collect()
+    }
+    
+    // A short editing session: each step is recorded the way the UI does it
+    fn play_session(history: &mut ImageHistory, image_data: &mut ImageData) -> Vec<Vec<(String, image::RgbaImage)>> {
+        let mut states = vec![pixels(image_data)];
+        
+        history.record("Pencil", image_data, |d| d.draw_pixel(1, 1, Rgba([255, 0, 0, 255])));
+        states.push(pixels(image_data));
+        
+        history.record("New layer", image_data, |d| d.add_layer("Layer 1".to_string()));
+        states.push(pixels(image_data));
+        
+        // A stroke spanning several frames is one transaction
+        history.begin("Brush", image_data);
+        for x in 0..8 {
+            image_data.draw_pixel(x, 4, Rgba([0, 0, 255, 255]));
+        }
+        history.commit(image_data);
+        states.push(pixels(image_data));
+        
+        history.record("Rename layer", image_data, |d| d.layers[1].name = "Ink".to_string());
+        states.push(pixels(image_data));
+        
+        history.record("Canvas size", image_data, |d| d.resize_canvas(12, 12, 2, 2));
+        states.push(pixels(image_data));
+        
+        states
+    }
+    
+    #[test]
+    fn scripted_session_undoes_back_to_the_start() {
+        let mut history = ImageHistory::new();
+        let mut image_data = ImageData::new(16, 16);
+        let states = play_session(&mut history, &mut image_data);
+        
+        assert_eq!(history.current_index(), 5);
+        assert_eq!(
+            history.labels().collect::<Vec<_>>(),
+            ["Pencil", "New layer", "Brush", "Rename layer", "Canvas size"]
+        );
+        
+        for expected in states.iter().rev().skip(1) {
+            assert!(history.undo(&mut image_data));
+            assert_eq!(&pixels(&image_data), expected);
+        }
+        assert!(!history.undo(&mut image_data));
+        assert_eq!((image_data.width(), image_data.height()), (16, 16));
+        
+        for expected in states.iter().skip(1) {
+            assert!(history.redo(&mut image_data));
+            assert_eq!(&pixels(&image_data), expected);
+        }
+        assert!(!history.redo(&mut image_data));
+    }
+    
+    #[test]
+    fn jump_to_reaches_any_state_in_the_session() {
+        let mut history = ImageHistory::new();
+        let mut image_data = ImageData::new(16, 16);
+        let states = play_session(&mut history, &mut image_data);
+        
+        for index in [0, 3, 1, 5, 2] {
+            history.jump_to(index, &mut image_data);
+            assert_eq!(history.current_index(), index);
+            assert_eq!(pixels(&image_data), states[index]);
+        }
+    }
+    
+    #[test]
+    fn new_edit_after_undo_drops_the_redo_steps() {
+        let mut history = ImageHistory::new();
+        let mut image_data = ImageData::new(16, 16);
+        play_session(&mut history, &mut image_data);
+        
+        history.jump_to(2, &mut image_data);
+        history.record("Pencil", &mut image_data, |d| d.draw_pixel(9, 9, Rgba([0, 255, 0, 255])));
+        assert_eq!(history.labels().collect::<Vec<_>>(), ["Pencil", "New layer", "Pencil"]);
+        assert!(!history.can_redo());
+    }
+    
+    #[test]
+    fn cancel_restores_the_document_and_records_nothing() {
+        let mut history = ImageHistory::new();
+        let mut image_data = ImageData::new(16, 16);
+        let before = pixels(&image_data);
+        
+        history.begin("Brush", &image_data);
+        image_data.draw_pixel(3, 3, Rgba([255, 255, 255, 255]));
+        assert!(!history.can_undo());
+        history.cancel(&mut image_data);
+        
+        assert_eq!(pixels(&image_data), before);
+        assert_eq!(history.labels().count(), 0);
+        assert!(!history.in_transaction());
+    }
+    
+    #[test]
+    fn evicting_the_oldest_steps_moves_the_base_label() {
+        let mut history = ImageHistory::new();
+        let mut image_data = ImageData::new(16, 16);
+        history.reset("Open image");
+        let states = play_session(&mut history, &mut image_data);
+        assert_eq!(history.base_label(), "Open image");
+        
+        // Room for the newest step only
+        history.set_memory_budget(0);
+        assert_eq!(history.labels().collect::<Vec<_>>(), ["Canvas size"]);
+        assert_eq!(history.base_label(), "Rename layer");
+        
+        assert!(history.undo(&mut image_data));
+        assert_eq!(pixels(&image_data), states[4]);
+        assert!(!history.can_undo());
+    }
+    
+    #[test]
+    fn evicting_redo_steps_keeps_the_base_label() {
+        let mut history = ImageHistory::new();
+        let mut image_data = ImageData::new(16, 16);
+        play_session(&mut history, &mut image_data);
+        
+        history.jump_to(0, &mut image_data);
+        history.set_memory_budget(0);
+        assert_eq!(history.labels().collect::<Vec<_>>(), ["Pencil"]);
+        assert_eq!(history.base_label(), "Initial state");
     }
 }
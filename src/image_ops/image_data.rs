@@ -1,28 +1,66 @@
 use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use anyhow::Result;
-use image::{DynamicImage, ImageBuffer, Rgba, RgbaImage};
+use image::{ImageBuffer, Rgba, RgbaImage};
 use eframe::egui;
+use serde::{Deserialize, Serialize};
+use crate::image_ops::project;
+use crate::image_ops::dirty::{DirtyRegion, PixelRect};
+use crate::image_ops::tiles::{TiledImage, TRANSPARENT};
+use crate::image_ops::paint::{self, Stroke};
+use crate::image_ops::fill::{self, CoverageMask, FillOptions};
+use crate::image_ops::shapes::{self, Shape};
+use crate::image_ops::text::TextLayer;
+use crate::image_ops::transform::{self, Anchor, Resampling};
+use crate::image_ops::adjustments::Adjustment;
+use crate::image_ops::filters::{EdgeMode, Filter};
+use crate::image_ops::selection::{self, SelectionMask, SelectionMode, SelectionModify, SelectionShape};
 
-#[derive(Clone)]
 pub struct ImageData {
     pub layers: Vec<Layer>,
     pub active_layer: usize,
     width: u32,
     height: u32,
     texture_handle: Option<egui::TextureHandle>,
+    composite: Option<RgbaImage>,
+    dirty: DirtyRegion,
     needs_update: bool,
+    stroke: Option<Stroke>,
+    // Painting and filters only touch selected pixels while this is set.
+    // Shared so history snapshots don't copy it.
+    selection: Option<Arc<SelectionMask>>,
 }
 
+pub type LayerId = u64;
+
+static NEXT_LAYER_ID: AtomicU64 = AtomicU64::new(1);
+
+// Clones keep the same id so history can match layers across snapshots;
+// use `Layer::duplicate` for a copy that is a distinct layer.
 #[derive(Debug, Clone)]
 pub struct Layer {
+    id: LayerId,
     pub name: String,
     pub visible: bool,
     pub opacity: f32,
     pub blend_mode: BlendMode,
-    pub data: RgbaImage,
+    pub kind: LayerKind,
+    pub data: TiledImage,
+    // How far the layer is drawn from where its pixels are. Only non-zero
+    // while the Move tool drags the layer; `apply_layer_offset` moves the
+    // pixels themselves once it is released.
+    pub offset: (i32, i32),
 }
 
 #[derive(Debug, Clone, PartialEq)]
+pub enum LayerKind {
+    Raster,
+    // Re-editable text. `data` holds its rendering until the layer is rasterized.
+    Text(TextLayer),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum BlendMode {
     Normal,
     Multiply,
@@ -38,46 +76,147 @@ pub enum BlendMode {
     Exclusion,
 }
 
+impl BlendMode {
+    pub const ALL: [BlendMode; 12] = [
+        BlendMode::Normal,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+        BlendMode::Overlay,
+        BlendMode::SoftLight,
+        BlendMode::HardLight,
+        BlendMode::ColorDodge,
+        BlendMode::ColorBurn,
+        BlendMode::Darken,
+        BlendMode::Lighten,
+        BlendMode::Difference,
+        BlendMode::Exclusion,
+    ];
+    
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlendMode::Normal => "Normal",
+            BlendMode::Multiply => "Multiply",
+            BlendMode::Screen => "Screen",
+            BlendMode::Overlay => "Overlay",
+            BlendMode::SoftLight => "Soft Light",
+            BlendMode::HardLight => "Hard Light",
+            BlendMode::ColorDodge => "Color Dodge",
+            BlendMode::ColorBurn => "Color Burn",
+            BlendMode::Darken => "Darken",
+            BlendMode::Lighten => "Lighten",
+            BlendMode::Difference => "Difference",
+            BlendMode::Exclusion => "Exclusion",
+        }
+    }
+    
+    // Separable blend functions from the W3C Compositing and Blending spec.
+    // `base` is the backdrop (Cb), `overlay` the source (Cs), both in 0..=1.
+    pub fn blend_channel(&self, base: f32, overlay: f32) -> f32 {
+        match self {
+            BlendMode::Normal => overlay,
+            BlendMode::Multiply => base * overlay,
+            BlendMode::Screen => base + overlay - base * overlay,
+            BlendMode::Overlay => BlendMode::HardLight.blend_channel(overlay, base),
+            BlendMode::SoftLight => {
+                if overlay <= 0.5 {
+                    base - (1.0 - 2.0 * overlay) * base * (1.0 - base)
+                } else {
+                    let d = if base <= 0.25 {
+                        ((16.0 * base - 12.0) * base + 4.0) * base
+                    } else {
+                        base.sqrt()
+                    };
+                    base + (2.0 * overlay - 1.0) * (d - base)
+                }
+            }
+            BlendMode::HardLight => {
+                if overlay <= 0.5 {
+                    BlendMode::Multiply.blend_channel(base, 2.0 * overlay)
+                } else {
+                    BlendMode::Screen.blend_channel(base, 2.0 * overlay - 1.0)
+                }
+            }
+            BlendMode::ColorDodge => {
+                if base == 0.0 {
+                    0.0
+                } else if overlay >= 1.0 {
+                    1.0
+                } else {
+                    (base / (1.0 - overlay)).min(1.0)
+                }
+            }
+            BlendMode::ColorBurn => {
+                if base >= 1.0 {
+                    1.0
+                } else if overlay == 0.0 {
+                    0.0
+                } else {
+                    1.0 - ((1.0 - base) / overlay).min(1.0)
+                }
+            }
+            BlendMode::Darken => base.min(overlay),
+            BlendMode::Lighten => base.max(overlay),
+            BlendMode::Difference => (base - overlay).abs(),
+            BlendMode::Exclusion => base + overlay - 2.0 * base * overlay,
+        }
+    }
+}
+
+// The texture and composite cache are tied to the document they were built from,
+// so a clone starts without them and re-composites on first use
+impl Clone for ImageData {
+    fn clone(&self) -> Self {
+        let mut clone = Self::from_layers(self.layers.clone(), self.active_layer, self.width, self.height);
+        clone.selection = self.selection.clone();
+        clone
+    }
+}
+
 impl ImageData {
     pub fn new(width: u32, height: u32) -> Self {
-        let mut layers = Vec::new();
-        layers.push(Layer::new("Background".to_string(), width, height));
+        let layers = vec![Layer::new("Background".to_string(), width, height)];
         
+        Self::from_layers(layers, 0, width, height)
+    }
+    
+    pub fn from_layers(layers: Vec<Layer>, active_layer: usize, width: u32, height: u32) -> Self {
         Self {
             layers,
-            active_layer: 0,
+            active_layer,
             width,
             height,
             texture_handle: None,
+            composite: None,
+            dirty: DirtyRegion::default(),
             needs_update: true,
+            stroke: None,
+            selection: None,
         }
     }
     
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        if project::is_project_file(&path) {
+            return project::load_project(path);
+        }
+        
         let img = image::open(path)?;
-        let rgba_img = img.to_rgba8();
-        let (width, height) = rgba_img.dimensions();
+        Ok(Self::from_image(&img.to_rgba8()))
+    }
+    
+    // Single-layer document holding `image`
+    pub fn from_image(image: &RgbaImage) -> Self {
+        let (width, height) = image.dimensions();
         
-        let mut layers = Vec::new();
-        layers.push(Layer {
-            name: "Background".to_string(),
-            visible: true,
-            opacity: 1.0,
-            blend_mode: BlendMode::Normal,
-            data: rgba_img,
-        });
+        let layers = vec![Layer::with_data("Background".to_string(), TiledImage::from_image(image))];
         
-        Ok(Self {
-            layers,
-            active_layer: 0,
-            width,
-            height,
-            texture_handle: None,
-            needs_update: true,
-        })
+        Self::from_layers(layers, 0, width, height)
     }
     
     pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        if project::has_project_extension(&path) {
+            return project::save_project(self, path);
+        }
+        
         let flattened = self.flatten();
         flattened.save(path)?;
         Ok(())
@@ -108,6 +247,459 @@ impl ImageData {
         }
     }
     
+    pub fn duplicate_layer(&mut self, index: usize) {
+        if index < self.layers.len() {
+            let layer = self.layers[index].duplicate();
+            self.layers.insert(index + 1, layer);
+            self.active_layer = index + 1;
+            self.needs_update = true;
+        }
+    }
+    
+    // Moves the layer at `from` so it ends up at index `to`. The active
+    // layer stays the same layer.
+    pub fn move_layer(&mut self, from: usize, to: usize) {
+        if from >= self.layers.len() || to >= self.layers.len() || from == to {
+            return;
+        }
+        let active_id = self.layers[self.active_layer].id;
+        let layer = self.layers.remove(from);
+        self.layers.insert(to, layer);
+        self.active_layer = self.layers.iter().position(|layer| layer.id == active_id).unwrap_or(to);
+        self.needs_update = true;
+    }
+    
+    // Whether `merge_down(index)` would change anything. Both layers must be
+    // visible, and the lower one must use Normal blending so its opacity can
+    // be baked into its pixels without changing the composite.
+    pub fn can_merge_down(&self, index: usize) -> bool {
+        index > 0
+            && index < self.layers.len()
+            && self.layers[index].visible
+            && self.layers[index - 1].visible
+            && self.layers[index - 1].blend_mode == BlendMode::Normal
+    }
+    
+    // Blends the layer at `index` into the one below it with its blend mode
+    // and opacity, and makes the result active. The merged layer keeps the
+    // lower layer's name, with its opacity baked into the pixels and reset
+    // to 1.0. An upper blend mode other than Normal blends against the lower
+    // layer alone, so it can look different to how it did over the stack.
+    pub fn merge_down(&mut self, index: usize) {
+        if !self.can_merge_down(index) {
+            return;
+        }
+        self.apply_layer_offset(index);
+        self.apply_layer_offset(index - 1);
+        
+        let upper = self.layers.remove(index);
+        let lower = &mut self.layers[index - 1];
+        lower.kind = LayerKind::Raster;
+        if lower.opacity < 1.0 {
+            let opacity = lower.opacity;
+            lower.data = lower.data.map_pixels(|mut pixel| {
+                pixel[3] = (pixel[3] as f32 * opacity).round() as u8;
+                pixel
+            });
+            lower.opacity = 1.0;
+        }
+        let full = PixelRect::new(0, 0, self.width, self.height);
+        for (tx, ty) in upper.data.tiles_in(full) {
+            // Unallocated tiles are fully transparent and leave the lower layer untouched
+            let tile = match upper.data.tile(tx, ty) {
+                Some(tile) => tile,
+                None => continue,
+            };
+            let rect = upper.data.tile_rect(tx, ty);
+            for y in rect.y..rect.bottom() {
+                for x in rect.x..rect.right() {
+                    let pixel = *tile.get_pixel(x - rect.x, y - rect.y);
+                    if pixel[3] == 0 {
+                        continue;
+                    }
+                    let base = lower.data.get_pixel(x, y);
+                    lower.data.put_pixel(x, y, Self::blend_pixels(base, pixel, &upper.blend_mode, upper.opacity));
+                }
+            }
+        }
+        
+        self.active_layer = index - 1;
+        self.needs_update = true;
+    }
+    
+    // Replaces every visible layer with one layer holding their composite,
+    // placed where the lowest of them was. Hidden layers are kept as they are.
+    pub fn merge_visible(&mut self) {
+        let visible: Vec<usize> = (0..self.layers.len()).filter(|&i| self.layers[i].visible).collect();
+        if visible.len() < 2 {
+            return;
+        }
+        
+        let mut data = TiledImage::from_image(&self.flatten());
+        data.release_empty_tiles();
+        let merged = Layer::with_data(self.layers[visible[0]].name.clone(), data);
+        let index = visible[0];
+        self.layers.retain(|layer| !layer.visible);
+        self.layers.insert(index, merged);
+        self.active_layer = index;
+        self.needs_update = true;
+    }
+    
+    // Collapses the image to a single layer that looks the same. Hidden
+    // layers are discarded.
+    pub fn flatten_image(&mut self) {
+        let mut data = TiledImage::from_image(&self.flatten());
+        data.release_empty_tiles();
+        self.layers = vec![Layer::with_data("Background".to_string(), data)];
+        self.active_layer = 0;
+        self.needs_update = true;
+    }
+    
+    // Adds a text layer on top of the stack and makes it active
+    pub fn add_text_layer(&mut self, text: TextLayer) {
+        let mut layer = Layer::with_data(text.layer_name(), text.render(self.width, self.height));
+        layer.kind = LayerKind::Text(text);
+        self.layers.push(layer);
+        self.active_layer = self.layers.len() - 1;
+        self.needs_update = true;
+    }
+    
+    // Changes the text of a text layer and renders it again
+    pub fn set_text(&mut self, index: usize, text: TextLayer) {
+        let (width, height) = (self.width, self.height);
+        if let Some(layer) = self.layers.get_mut(index) {
+            if layer.text().is_some() {
+                layer.name = text.layer_name();
+                layer.data = text.render(width, height);
+                layer.kind = LayerKind::Text(text);
+                self.needs_update = true;
+            }
+        }
+    }
+    
+    // Turns a text layer into plain pixels so it can be painted on
+    pub fn rasterize_layer(&mut self, index: usize) {
+        if let Some(layer) = self.layers.get_mut(index) {
+            layer.kind = LayerKind::Raster;
+        }
+    }
+    
+    // Replaces the whole layer stack, e.g. when restoring from history
+    pub fn set_layers(&mut self, layers: Vec<Layer>, active_layer: usize, width: u32, height: u32) {
+        self.layers = layers;
+        self.active_layer = active_layer.min(self.layers.len().saturating_sub(1));
+        if (width, height) != (self.width, self.height) {
+            self.selection = None;
+        }
+        self.width = width;
+        self.height = height;
+        self.needs_update = true;
+    }
+    
+    pub fn selection(&self) -> Option<&SelectionMask> {
+        self.selection.as_deref()
+    }
+    
+    // Replaces the selection. An empty mask means nothing is selected,
+    // which is stored as no selection so tools work on the whole canvas.
+    pub fn set_selection(&mut self, selection: Option<SelectionMask>) {
+        self.selection = selection
+            .filter(|mask| !mask.is_empty() && mask.width() == self.width && mask.height() == self.height)
+            .map(Arc::new);
+    }
+    
+    // Combines `shape` with the current selection
+    pub fn select(&mut self, shape: &SelectionShape, mode: SelectionMode) {
+        let mask = SelectionMask::from_shape(self.width, self.height, shape);
+        self.select_mask(mask, mode);
+    }
+    
+    // Combines a mask the size of the canvas with the current selection
+    pub fn select_mask(&mut self, mask: SelectionMask, mode: SelectionMode) {
+        let combined = match (self.selection(), mode) {
+            (Some(current), _) => current.combine(&mask, mode),
+            // Without a selection there is nothing to subtract from or intersect with
+            (None, SelectionMode::Subtract | SelectionMode::Intersect) => return,
+            (None, _) => mask,
+        };
+        self.set_selection(Some(combined));
+    }
+    
+    pub fn deselect(&mut self) {
+        self.selection = None;
+    }
+    
+    pub fn select_all(&mut self) {
+        self.set_selection(Some(SelectionMask::all(self.width, self.height)));
+    }
+    
+    // Swaps selected and unselected pixels. Without a selection everything
+    // counts as selected, so there is nothing to invert.
+    pub fn invert_selection(&mut self) {
+        let inverted = self.selection().map(SelectionMask::inverted);
+        if inverted.is_some() {
+            self.set_selection(inverted);
+        }
+    }
+    
+    pub fn modify_selection(&mut self, modify: SelectionModify, radius: f32) {
+        let modified = self.selection().map(|selection| selection.modified(modify, radius));
+        if modified.is_some() {
+            self.set_selection(modified);
+        }
+    }
+    
+    // Magic wand: selects the region a paint bucket click at (x, y) would
+    // fill, using the same tolerance, contiguous and sample merged options
+    pub fn magic_wand(&mut self, x: u32, y: u32, options: &FillOptions, mode: SelectionMode) {
+        let region = self.fill_region(x, y, options);
+        let mask = SelectionMask::from_fn(self.width, self.height, |x, y| (region.get(x, y) * 255.0).round() as u8);
+        self.select_mask(mask, mode);
+    }
+    
+    // Mask for Select > Color Range, see `SelectionMask::from_color_range`
+    pub fn color_range_mask(&self, color: Rgba<u8>, fuzziness: f32, sample_merged: bool) -> SelectionMask {
+        let (width, height) = (self.width, self.height);
+        if sample_merged {
+            let composite = self.flatten();
+            SelectionMask::from_color_range(width, height, color, fuzziness, |x, y| *composite.get_pixel(x, y))
+        } else {
+            let layer = &self.get_active_layer().data;
+            SelectionMask::from_color_range(width, height, color, fuzziness, |x, y| layer.get_pixel(x, y))
+        }
+    }
+    
+    // Selected pixels, cropped to the selection's bounds, for copying to the
+    // clipboard. Soft selection edges fade out the alpha. Takes the whole
+    // canvas when nothing is selected, and reads the composite instead of
+    // the active layer when `merged` is set.
+    pub fn copy_selection(&self, merged: bool) -> Option<RgbaImage> {
+        let area = match self.selection() {
+            Some(selection) => selection.bounds()?,
+            None => PixelRect::new(0, 0, self.width, self.height),
+        };
+        Some(self.selected_pixels(area, merged))
+    }
+    
+    // Pixels a transform picks up from the active layer: the selected ones,
+    // or everything on the layer, cropped to where there is anything. Returns
+    // them with the area they came from.
+    pub fn lift_selection(&self) -> Option<(PixelRect, RgbaImage)> {
+        let area = match self.selection() {
+            Some(selection) => selection.bounds()?,
+            None => self.get_active_layer().data.content_bounds()?,
+        };
+        Some((area, self.selected_pixels(area, false)))
+    }
+    
+    fn selected_pixels(&self, area: PixelRect, merged: bool) -> RgbaImage {
+        let composite = if merged { Some(self.flatten()) } else { None };
+        let layer = &self.get_active_layer().data;
+        let selection = self.selection();
+        
+        ImageBuffer::from_fn(area.width, area.height, |x, y| {
+            let (x, y) = (area.x + x, area.y + y);
+            let mut pixel = match &composite {
+                Some(composite) => *composite.get_pixel(x, y),
+                None => layer.get_pixel(x, y),
+            };
+            pixel[3] = (pixel[3] as f32 * selection::strength(selection, x, y)).round() as u8;
+            pixel
+        })
+    }
+    
+    // Moves a layer's pixels by its offset and clears the offset, once the
+    // Move tool lets go of it. Pixels moved off the canvas are dropped.
+    pub fn apply_layer_offset(&mut self, index: usize) {
+        let (width, height) = (self.width, self.height);
+        let layer = match self.layers.get_mut(index) {
+            Some(layer) => layer,
+            None => return,
+        };
+        let (offset_x, offset_y) = std::mem::take(&mut layer.offset);
+        if (offset_x, offset_y) == (0, 0) {
+            return;
+        }
+        
+        layer.data = layer.data.resize_canvas(width, height, offset_x as i64, offset_y as i64);
+        if let LayerKind::Text(text) = &mut layer.kind {
+            text.x += offset_x as f32;
+            text.y += offset_y as f32;
+        }
+        self.needs_update = true;
+    }
+    
+    // Erases the selected pixels of the active layer, or all of them when
+    // nothing is selected. Used by Cut and to lift pixels for a transform.
+    pub fn clear_selected_pixels(&mut self) {
+        let area = match self.selection() {
+            Some(selection) => match selection.bounds() {
+                Some(bounds) => bounds,
+                None => return,
+            },
+            None => PixelRect::new(0, 0, self.width, self.height),
+        };
+        
+        let selection = self.selection.as_deref();
+        let layer = &mut self.layers[self.active_layer];
+        layer.kind = LayerKind::Raster;
+        for y in area.y..area.bottom() {
+            for x in area.x..area.right() {
+                let mut pixel = layer.data.get_pixel(x, y);
+                if pixel[3] == 0 {
+                    continue;
+                }
+                pixel[3] = (pixel[3] as f32 * (1.0 - selection::strength(selection, x, y))).round() as u8;
+                layer.data.put_pixel(x, y, if pixel[3] == 0 { TRANSPARENT } else { pixel });
+            }
+        }
+        layer.data.release_empty_tiles();
+        self.mark_region_dirty(area);
+    }
+    
+    // Adds `image` as a new layer above the active one, centred on `center`
+    // in image coordinates. Whatever falls outside the canvas is cut off.
+    pub fn paste_as_layer(&mut self, image: &RgbaImage, center: egui::Pos2) {
+        let left = (center.x - image.width() as f32 / 2.0).round() as i64;
+        let top = (center.y - image.height() as f32 / 2.0).round() as i64;
+        
+        let mut data = TiledImage::new(self.width, self.height);
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let (x, y) = (left + x as i64, top + y as i64);
+            if pixel[3] > 0 && x >= 0 && y >= 0 && x < self.width as i64 && y < self.height as i64 {
+                data.put_pixel(x as u32, y as u32, *pixel);
+            }
+        }
+        
+        let index = (self.active_layer + 1).min(self.layers.len());
+        self.layers.insert(index, Layer::with_data("Pasted".to_string(), data));
+        self.active_layer = index;
+        self.selection = None;
+        self.needs_update = true;
+    }
+    
+    // Crops every layer to `rect`, which becomes the whole canvas
+    pub fn crop(&mut self, rect: PixelRect) {
+        self.resize_canvas(rect.width, rect.height, -(rect.x as i64), -(rect.y as i64));
+    }
+    
+    // Changes the canvas size without scaling, moving every layer's content
+    // by the offset
+    pub fn resize_canvas(&mut self, width: u32, height: u32, offset_x: i64, offset_y: i64) {
+        self.selection = None;
+        for layer in &mut self.layers {
+            layer.data = layer.data.resize_canvas(width, height, offset_x, offset_y);
+            if let LayerKind::Text(text) = &mut layer.kind {
+                text.x += offset_x as f32;
+                text.y += offset_y as f32;
+            }
+        }
+        self.width = width;
+        self.height = height;
+        self.needs_update = true;
+    }
+    
+    // Scales every layer to `width`x`height`. Text layers stay editable when
+    // the aspect ratio is kept and are rasterized otherwise.
+    pub fn resize_image(&mut self, width: u32, height: u32, resampling: Resampling) {
+        let scale_x = width as f32 / self.width as f32;
+        let scale_y = height as f32 / self.height as f32;
+        let uniform = (scale_x - scale_y).abs() < 0.01;
+        self.selection = None;
+        
+        for layer in &mut self.layers {
+            match &mut layer.kind {
+                LayerKind::Text(text) if uniform => {
+                    text.x *= scale_x;
+                    text.y *= scale_y;
+                    text.size *= scale_x;
+                    layer.data = text.render(width, height);
+                }
+                _ => {
+                    layer.data = transform::resize(&layer.data, width, height, resampling);
+                    layer.kind = LayerKind::Raster;
+                }
+            }
+        }
+        self.width = width;
+        self.height = height;
+        self.needs_update = true;
+    }
+    
+    // Changes the canvas size, keeping the content pinned to `anchor`.
+    // Area added to the bottom layer is filled with `background` if given,
+    // otherwise it stays transparent.
+    pub fn resize_canvas_anchored(&mut self, width: u32, height: u32, anchor: Anchor, background: Option<Rgba<u8>>) {
+        let (old_width, old_height) = (self.width, self.height);
+        let (offset_x, offset_y) = anchor.offset(old_width, old_height, width, height);
+        self.resize_canvas(width, height, offset_x, offset_y);
+        
+        let color = match background {
+            Some(color) => color,
+            None => return,
+        };
+        let bottom = &mut self.layers[0];
+        bottom.kind = LayerKind::Raster;
+        for y in 0..height {
+            let old_y = y as i64 - offset_y;
+            let row_is_new = old_y < 0 || old_y >= old_height as i64;
+            for x in 0..width {
+                let old_x = x as i64 - offset_x;
+                if row_is_new || old_x < 0 || old_x >= old_width as i64 {
+                    bottom.data.put_pixel(x, y, color);
+                }
+            }
+        }
+    }
+    
+    // Turns every layer clockwise by `turns` quarter turns. Text layers are
+    // rasterized, since text is only ever laid out horizontally.
+    pub fn rotate_quarter_turns(&mut self, turns: u32) {
+        if turns.is_multiple_of(4) {
+            return;
+        }
+        
+        self.selection = None;
+        for layer in &mut self.layers {
+            layer.data = layer.data.rotate_quarter_turns(turns);
+            layer.kind = LayerKind::Raster;
+        }
+        if turns % 2 == 1 {
+            std::mem::swap(&mut self.width, &mut self.height);
+        }
+        self.needs_update = true;
+    }
+    
+    // Mirrors every layer, left to right when `horizontal` is set
+    pub fn flip(&mut self, horizontal: bool) {
+        self.selection = None;
+        for layer in &mut self.layers {
+            layer.data = layer.data.flip(horizontal);
+            layer.kind = LayerKind::Raster;
+        }
+        self.needs_update = true;
+    }
+    
+    // Rotates every layer clockwise by any angle, growing the canvas so
+    // nothing is cut off. Multiples of 90° are turned exactly.
+    pub fn rotate(&mut self, degrees: f32, resampling: Resampling) {
+        let degrees = degrees.rem_euclid(360.0);
+        let quarter_turns = degrees / 90.0;
+        if (quarter_turns - quarter_turns.round()).abs() < 1e-4 {
+            self.rotate_quarter_turns(quarter_turns.round() as u32);
+            return;
+        }
+        
+        let (width, height) = (self.width, self.height);
+        self.selection = None;
+        for layer in &mut self.layers {
+            layer.data = transform::rotate(&layer.data, width, height, degrees, resampling);
+            layer.kind = LayerKind::Raster;
+        }
+        (self.width, self.height) = transform::rotated_size(width, height, degrees);
+        self.needs_update = true;
+    }
+    
     pub fn get_active_layer_mut(&mut self) -> &mut Layer {
         &mut self.layers[self.active_layer]
     }
@@ -118,33 +710,122 @@ impl ImageData {
     
     pub fn flatten(&self) -> RgbaImage {
         let mut result = ImageBuffer::new(self.width, self.height);
+        self.composite_region(&mut result, PixelRect::new(0, 0, self.width, self.height));
+        result
+    }
+    
+    // Composited colour of a single pixel, as it appears on screen
+    pub fn composite_pixel(&self, x: u32, y: u32) -> Rgba<u8> {
+        let mut pixel = Rgba([255, 255, 255, 0]);
+        for layer in &self.layers {
+            if layer.visible {
+                pixel = Self::blend_pixels(pixel, layer.canvas_pixel(x, y), &layer.blend_mode, layer.opacity);
+            }
+        }
+        pixel
+    }
+    
+    // Average colour of the square extending `radius` pixels around (x, y),
+    // clipped to the canvas. Colours are weighted by alpha so transparent
+    // pixels don't darken the result. Samples the active layer unless
+    // `merged` is set.
+    pub fn sample_color(&self, x: u32, y: u32, radius: u32, merged: bool) -> Option<Rgba<u8>> {
+        let area = PixelRect::from_bounds(
+            x as i64 - radius as i64,
+            y as i64 - radius as i64,
+            x as i64 + radius as i64 + 1,
+            y as i64 + radius as i64 + 1,
+            self.width,
+            self.height,
+        )?;
+        if x >= self.width || y >= self.height {
+            return None;
+        }
         
+        let mut sums = [0.0f64; 4];
+        for sy in area.y..area.bottom() {
+            for sx in area.x..area.right() {
+                let pixel = if merged {
+                    self.composite_pixel(sx, sy)
+                } else {
+                    self.get_active_layer().data.get_pixel(sx, sy)
+                };
+                let alpha = pixel[3] as f64;
+                for i in 0..3 {
+                    sums[i] += pixel[i] as f64 * alpha;
+                }
+                sums[3] += alpha;
+            }
+        }
+        
+        if sums[3] == 0.0 {
+            return Some(Rgba([0, 0, 0, 0]));
+        }
+        
+        let count = (area.width * area.height) as f64;
+        Some(Rgba([
+            (sums[0] / sums[3]).round() as u8,
+            (sums[1] / sums[3]).round() as u8,
+            (sums[2] / sums[3]).round() as u8,
+            (sums[3] / count).round() as u8,
+        ]))
+    }
+    
+    // Re-composites `rect` of the visible layer stack into `target`
+    fn composite_region(&self, target: &mut RgbaImage, rect: PixelRect) {
         // Fill with transparent pixels
-        for pixel in result.pixels_mut() {
-            *pixel = Rgba([255, 255, 255, 0]);
+        for y in rect.y..rect.bottom() {
+            for x in rect.x..rect.right() {
+                target.put_pixel(x, y, Rgba([255, 255, 255, 0]));
+            }
         }
         
         // Blend layers from bottom to top
         for layer in &self.layers {
             if layer.visible {
-                self.blend_layer(&mut result, layer);
+                self.blend_layer(target, layer, rect);
             }
         }
-        
-        result
     }
     
-    fn blend_layer(&self, base: &mut RgbaImage, layer: &Layer) {
-        for (x, y, base_pixel) in base.enumerate_pixels_mut() {
-            if x < layer.data.width() && y < layer.data.height() {
-                let layer_pixel = layer.data.get_pixel(x, y);
-                let blended = self.blend_pixels(*base_pixel, *layer_pixel, &layer.blend_mode, layer.opacity);
-                *base_pixel = blended;
+    fn blend_layer(&self, base: &mut RgbaImage, layer: &Layer, rect: PixelRect) {
+        // A layer being moved no longer lines up with its tiles
+        if layer.offset != (0, 0) {
+            for y in rect.y..rect.bottom() {
+                for x in rect.x..rect.right() {
+                    let layer_pixel = layer.canvas_pixel(x, y);
+                    if layer_pixel[3] > 0 {
+                        let base_pixel = base.get_pixel_mut(x, y);
+                        *base_pixel = Self::blend_pixels(*base_pixel, layer_pixel, &layer.blend_mode, layer.opacity);
+                    }
+                }
+            }
+            return;
+        }
+        
+        for (tx, ty) in layer.data.tiles_in(rect) {
+            // Unallocated tiles are fully transparent and leave the base untouched
+            let tile = match layer.data.tile(tx, ty) {
+                Some(tile) => tile,
+                None => continue,
+            };
+            let tile_rect = layer.data.tile_rect(tx, ty);
+            let area = match rect.intersect(&tile_rect) {
+                Some(area) => area,
+                None => continue,
+            };
+            
+            for y in area.y..area.bottom() {
+                for x in area.x..area.right() {
+                    let layer_pixel = tile.get_pixel(x - tile_rect.x, y - tile_rect.y);
+                    let base_pixel = base.get_pixel_mut(x, y);
+                    *base_pixel = Self::blend_pixels(*base_pixel, *layer_pixel, &layer.blend_mode, layer.opacity);
+                }
             }
         }
     }
     
-    fn blend_pixels(&self, base: Rgba<u8>, overlay: Rgba<u8>, blend_mode: &BlendMode, opacity: f32) -> Rgba<u8> {
+    pub fn blend_pixels(base: Rgba<u8>, overlay: Rgba<u8>, blend_mode: &BlendMode, opacity: f32) -> Rgba<u8> {
         let base_alpha = base[3] as f32 / 255.0;
         let overlay_alpha = (overlay[3] as f32 / 255.0) * opacity;
         
@@ -164,22 +845,13 @@ impl ImageData {
             let base_c = base[i] as f32 / 255.0;
             let overlay_c = overlay[i] as f32 / 255.0;
             
-            let blended_c = match blend_mode {
-                BlendMode::Normal => overlay_c,
-                BlendMode::Multiply => base_c * overlay_c,
-                BlendMode::Screen => 1.0 - (1.0 - base_c) * (1.0 - overlay_c),
-                BlendMode::Overlay => {
-                    if base_c < 0.5 {
-                        2.0 * base_c * overlay_c
-                    } else {
-                        1.0 - 2.0 * (1.0 - base_c) * (1.0 - overlay_c)
-                    }
-                }
-                _ => overlay_c, // Simplified for now
-            };
+            // W3C compositing: the blend result only applies where the backdrop
+            // has coverage, otherwise the source colour shows through unchanged
+            let blended_c = blend_mode.blend_channel(base_c, overlay_c);
+            let mixed_c = (1.0 - base_alpha) * overlay_c + base_alpha * blended_c;
             
-            let final_c = (blended_c * overlay_alpha + base_c * base_alpha * (1.0 - overlay_alpha)) / result_alpha;
-            result[i] = (final_c * 255.0).round() as u8;
+            let final_c = (mixed_c * overlay_alpha + base_c * base_alpha * (1.0 - overlay_alpha)) / result_alpha;
+            result[i] = (final_c.clamp(0.0, 1.0) * 255.0).round() as u8;
         }
         
         result[3] = (result_alpha * 255.0).round() as u8;
@@ -187,11 +859,11 @@ impl ImageData {
     }
     
     pub fn get_texture(&mut self, ctx: &egui::Context) -> egui::TextureHandle {
-        if self.texture_handle.is_none() || self.needs_update {
+        if self.texture_handle.is_none() || self.composite.is_none() || self.needs_update {
             let flattened = self.flatten();
             let color_image = egui::ColorImage::from_rgba_unmultiplied(
                 [self.width as usize, self.height as usize],
-                &flattened.as_raw(),
+                flattened.as_raw(),
             );
             
             self.texture_handle = Some(ctx.load_texture(
@@ -199,55 +871,883 @@ impl ImageData {
                 color_image,
                 egui::TextureOptions::NEAREST,
             ));
+            self.composite = Some(flattened);
+            self.dirty.clear();
             self.needs_update = false;
+        } else if !self.dirty.is_empty() {
+            self.update_dirty_tiles();
         }
         
         self.texture_handle.as_ref().unwrap().clone()
     }
     
+    // Re-composites only the damaged tiles and uploads them as partial texture updates
+    fn update_dirty_tiles(&mut self) {
+        let rects = self.dirty.take_rects(self.width, self.height);
+        let mut composite = match self.composite.take() {
+            Some(composite) => composite,
+            None => return,
+        };
+        
+        for rect in rects {
+            self.composite_region(&mut composite, rect);
+            
+            let mut pixels = Vec::with_capacity(rect.width as usize * rect.height as usize * 4);
+            for y in rect.y..rect.bottom() {
+                let row_start = ((y * self.width + rect.x) * 4) as usize;
+                let row_end = row_start + rect.width as usize * 4;
+                pixels.extend_from_slice(&composite.as_raw()[row_start..row_end]);
+            }
+            
+            let color_image = egui::ColorImage::from_rgba_unmultiplied(
+                [rect.width as usize, rect.height as usize],
+                &pixels,
+            );
+            
+            if let Some(texture) = self.texture_handle.as_mut() {
+                texture.set_partial(
+                    [rect.x as usize, rect.y as usize],
+                    color_image,
+                    egui::TextureOptions::NEAREST,
+                );
+            }
+        }
+        
+        self.composite = Some(composite);
+    }
+    
     pub fn mark_dirty(&mut self) {
         self.needs_update = true;
     }
     
+    pub fn mark_region_dirty(&mut self, rect: PixelRect) {
+        self.dirty.add(rect);
+    }
+    
     pub fn draw_pixel(&mut self, x: u32, y: u32, color: Rgba<u8>) {
         if x < self.width && y < self.height {
+            let coverage = selection::strength(self.selection(), x, y);
             let layer = self.get_active_layer_mut();
-            layer.data.put_pixel(x, y, color);
-            self.needs_update = true;
+            let pixel = paint::source_over(layer.data.get_pixel(x, y), color, coverage);
+            layer.data.put_pixel(x, y, pixel);
+            self.mark_region_dirty(PixelRect::new(x, y, 1, 1));
         }
     }
     
-    pub fn draw_circle(&mut self, center_x: u32, center_y: u32, radius: f32, color: Rgba<u8>) {
-        let width = self.width;
-        let height = self.height;
-        let layer = self.get_active_layer_mut();
+    // Pixels touched by a circle including its anti-aliased edge, clipped
+    // to the canvas. Raster primitives only visit these pixels.
+    fn circle_bounds(&self, center_x: f32, center_y: f32, radius: f32) -> Option<PixelRect> {
+        let reach = radius + 0.5;
+        PixelRect::from_bounds(
+            (center_x - reach).floor() as i64,
+            (center_y - reach).floor() as i64,
+            (center_x + reach).ceil() as i64,
+            (center_y + reach).ceil() as i64,
+            self.width,
+            self.height,
+        )
+    }
+    
+    // Rasterises `shape` onto the active layer. The border is drawn inside
+    // the outline, `width` pixels wide, and the fill covers the rest.
+    pub fn draw_shape(&mut self, shape: &Shape, border: Option<(Rgba<u8>, f32)>, fill: Option<Rgba<u8>>) {
+        let area = shape.bounds();
+        let bounds = PixelRect::from_bounds(
+            area.min.x.floor() as i64,
+            area.min.y.floor() as i64,
+            area.max.x.ceil() as i64,
+            area.max.y.ceil() as i64,
+            self.width,
+            self.height,
+        );
+        let bounds = match bounds {
+            Some(bounds) => bounds,
+            None => return,
+        };
         
-        for y in 0..height {
-            for x in 0..width {
-                let dx = x as f32 - center_x as f32;
-                let dy = y as f32 - center_y as f32;
-                let distance = (dx * dx + dy * dy).sqrt();
+        let selection = self.selection.as_deref();
+        let layer = &mut self.layers[self.active_layer];
+        for y in bounds.y..bounds.bottom() {
+            for x in bounds.x..bounds.right() {
+                let distance = shape.signed_distance(egui::pos2(x as f32 + 0.5, y as f32 + 0.5));
+                let outer = shapes::distance_coverage(distance);
+                if outer <= 0.0 {
+                    continue;
+                }
+                
+                let (border_color, border_weight, inner) = match border {
+                    Some((color, width)) => {
+                        let inner = shapes::distance_coverage(distance + width);
+                        (color, outer - inner, inner)
+                    }
+                    None => (TRANSPARENT, 0.0, outer),
+                };
+                let (fill_color, fill_weight) = match fill {
+                    Some(color) => (color, inner),
+                    None => (TRANSPARENT, 0.0),
+                };
                 
-                if distance <= radius {
-                    layer.data.put_pixel(x, y, color);
+                let (color, coverage) = shapes::mix_paint(fill_color, fill_weight, border_color, border_weight);
+                let coverage = coverage * selection::strength(selection, x, y);
+                if coverage > 0.0 {
+                    let pixel = paint::source_over(layer.data.get_pixel(x, y), color, coverage);
+                    layer.data.put_pixel(x, y, pixel);
                 }
             }
         }
         
-        self.needs_update = true;
+        self.mark_region_dirty(bounds);
+    }
+    
+    // Replaces layer `index` with `source` run through `adjustment`. Live
+    // previews call this on every change, always starting from the
+    // unadjusted pixels.
+    pub fn apply_adjustment(&mut self, index: usize, source: &TiledImage, adjustment: &Adjustment) {
+        if self.layers.get(index).is_some_and(|layer| layer.data.same_size(source)) {
+            let mapper = adjustment.mapper();
+            let adjusted = self.clip_to_selection(source, source.map_pixels(mapper));
+            self.replace_layer_pixels(index, adjusted);
+        }
+    }
+    
+    // Replaces layer `index` with `source` run through `filter`, like
+    // `apply_adjustment`
+    pub fn apply_filter(&mut self, index: usize, source: &TiledImage, filter: &Filter, edge: EdgeMode) {
+        if self.layers.get(index).is_some_and( |layer| layer.data.same_size(source)) {
+            let filtered = self.clip_to_selection(source, filter.apply(source, edge));
+            self.replace_layer_pixels(index, filtered);
+        }
+    }
+    
+    // `result` of a whole-layer operation on `source`, kept only where the
+    // pixels are selected
+    fn clip_to_selection(&self, source: &TiledImage, mut result: TiledImage) -> TiledImage {
+        let selection = match self.selection() {
+            Some(selection) => selection,
+            None => return result,
+        };
+        
+        for index in 0..result.tile_count() {
+            let rect = result.tile_rect_at(index);
+            let area = match selection.bounds().and_then(|bounds| bounds.intersect(&rect)) {
+                Some(area) => area,
+                None => {
+                    // Untouched tiles stay shared with the source
+                    result.set_tile_slot(index, source.tile_slot(index));
+                    continue;
+                }
+            };
+            
+            for y in rect.y..rect.bottom() {
+                for x in rect.x..rect.right() {
+                    let inside = x >= area.x && x < area.right() && y >= area.y && y < area.bottom();
+                    let before = source.get_pixel(x, y);
+                    let pixel = if inside {
+                        selection::clip_pixel(Some(selection), x, y, before, result.get_pixel(x, y))
+                    } else {
+                        before
+                    };
+                    result.put_pixel(x, y, pixel);
+                }
+            }
+        }
+        result.release_empty_tiles();
+        result
+    }
+    
+    // Swaps in new pixels for a layer, turning it into a raster layer and
+    // redrawing only the tiles that changed
+    fn replace_layer_pixels(&mut self, index: usize, data: TiledImage) {
+        let layer = &mut self.layers[index];
+        let damaged: Vec<PixelRect> = (0..data.tile_count())
+            .filter(|&i| !data.same_tile(&layer.data, i))
+            .map(|i| data.tile_rect_at(i))
+            .collect();
+        layer.data = data;
+        layer.kind = LayerKind::Raster;
+        for rect in damaged {
+            self.mark_region_dirty(rect);
+        }
+    }
+    
+    // Paint bucket: fills the region around (x, y) on the active layer with
+    // `color`. See `fill::FillOptions` for how the region is chosen.
+    pub fn fill(&mut self, x: u32, y: u32, color: Rgba<u8>, options: &FillOptions) {
+        let mask = self.fill_region(x, y, options);
+        let bounds = match mask.bounds() {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        
+        let selection = self.selection.as_deref();
+        let layer = &mut self.layers[self.active_layer];
+        for y in bounds.y..bounds.bottom() {
+            for x in bounds.x..bounds.right() {
+                let coverage = mask.get(x, y) * selection::strength(selection, x, y);
+                if coverage > 0.0 {
+                    let pixel = paint::source_over(layer.data.get_pixel(x, y), color, coverage);
+                    layer.data.put_pixel(x, y, pixel);
+                }
+            }
+        }
+        
+        self.mark_region_dirty(bounds);
+    }
+    
+    // Pixels the paint bucket or magic wand picks for a click at (x, y)
+    fn fill_region(&self, x: u32, y: u32, options: &FillOptions) -> CoverageMask {
+        let (width, height) = (self.width, self.height);
+        if options.sample_merged {
+            let composite = self.flatten();
+            fill::flood_fill(width, height, x, y, options, |x, y| *composite.get_pixel(x, y))
+        } else {
+            let layer = &self.get_active_layer().data;
+            fill::flood_fill(width, height, x, y, options, |x, y| layer.get_pixel(x, y))
+        }
+    }
+    
+    // Starts a stroke on the active layer. Dabs drawn until `end_stroke`
+    // accumulate with `flow` but never exceed `opacity`, see `paint::Stroke`.
+    // With `erase` set the stroke removes alpha instead of adding colour.
+    pub fn begin_stroke(&mut self, color: Rgba<u8>, opacity: f32, flow: f32, erase: bool) {
+        let origin = self.get_active_layer().data.clone();
+        self.stroke = Some(Stroke::new(origin, color, opacity, flow, erase));
+    }
+    
+    pub fn end_stroke(&mut self) {
+        self.stroke = None;
+    }
+    
+    // Stamps one round dab of the current stroke. Pixels are measured from
+    // their centres and `hardness` controls the soft edge.
+    pub fn draw_dab(&mut self, center_x: f32, center_y: f32, radius: f32, hardness: f32) {
+        let bounds = match self.circle_bounds(center_x, center_y, radius) {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let stroke = match self.stroke.as_mut() {
+            Some(stroke) => stroke,
+            None => return,
+        };
+        
+        let selection = self.selection.as_deref();
+        let layer = &mut self.layers[self.active_layer];
+        for y in bounds.y..bounds.bottom() {
+            for x in bounds.x..bounds.right() {
+                let dx = x as f32 + 0.5 - center_x;
+                let dy = y as f32 + 0.5 - center_y;
+                let coverage = paint::dab_falloff((dx * dx + dy * dy).sqrt(), radius, hardness);
+                if coverage <= 0.0 {
+                    continue;
+                }
+                
+                let out = stroke.apply(x, y, coverage, selection::strength(selection, x, y));
+                if out != layer.data.get_pixel(x, y) {
+                    layer.data.put_pixel(x, y, out);
+                }
+            }
+        }
+        
+        self.mark_region_dirty(bounds);
     }
 }
 
 impl Layer {
     pub fn new(name: String, width: u32, height: u32) -> Self {
-        let data = ImageBuffer::from_fn(width, height, |_, _| Rgba([255, 255, 255, 0]));
-        
+        Self::with_data(name, TiledImage::new(width, height))
+    }
+    
+    pub fn with_data(name: String, data: TiledImage) -> Self {
         Self {
+            id: NEXT_LAYER_ID.fetch_add(1, Ordering::Relaxed),
             name,
             visible: true,
             opacity: 1.0,
             blend_mode: BlendMode::Normal,
+            kind: LayerKind::Raster,
             data,
+            offset: (0, 0),
+        }
+    }
+    
+    // Pixel drawn at canvas position (x, y), taking the offset into account
+    pub fn canvas_pixel(&self, x: u32, y: u32) -> Rgba<u8> {
+        let x = x as i64 - self.offset.0 as i64;
+        let y = y as i64 - self.offset.1 as i64;
+        if x < 0 || y < 0 || x >= self.data.width() as i64 || y >= self.data.height() as i64 {
+            TRANSPARENT
+        } else {
+            self.data.get_pixel(x as u32, y as u32)
+        }
+    }
+    
+    pub fn text(&self) -> Option<&TextLayer> {
+        match &self.kind {
+            LayerKind::Text(text) => Some(text),
+            LayerKind::Raster => None,
+        }
+    }
+    
+    pub fn duplicate(&self) -> Self {
+        let mut layer = self.clone();
+        layer.id = NEXT_LAYER_ID.fetch_add(1, Ordering::Relaxed);
+        layer.name = format!("{} copy", self.name);
+        layer
+    }
+    
+    pub fn id(&self) -> LayerId {
+        self.id
+    }
+    
+    pub fn memory_bytes(&self) -> usize {
+        self.data.memory_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_ops::tiles::TILE_SIZE;
+    
+    // (mode, backdrop, source, expected), worked out by hand from the W3C
+    // Compositing and Blending formulas
+    const BLEND_CASES: &[(BlendMode, f32, f32, f32)] = &[
+        (BlendMode::Normal, 0.2, 0.7, 0.7),
+        (BlendMode::Multiply, 0.5, 0.5, 0.25),
+        (BlendMode::Multiply, 1.0, 0.3, 0.3),
+        (BlendMode::Screen, 0.5, 0.5, 0.75),
+        (BlendMode::Screen, 0.0, 0.4, 0.4),
+        (BlendMode::Overlay, 0.25, 0.5, 0.25),
+        (BlendMode::Overlay, 0.75, 0.5, 0.75),
+        (BlendMode::SoftLight, 0.5, 0.25, 0.375),
+        (BlendMode::SoftLight, 0.16, 0.75, 0.279168),
+        (BlendMode::SoftLight, 0.64, 0.75, 0.72),
+        (BlendMode::HardLight, 0.5, 0.25, 0.25),
+        (BlendMode::HardLight, 0.5, 0.75, 0.75),
+        (BlendMode::ColorDodge, 0.0, 0.5, 0.0),
+        (BlendMode::ColorDodge, 0.5, 1.0, 1.0),
+        (BlendMode::ColorDodge, 0.25, 0.5, 0.5),
+        (BlendMode::ColorDodge, 0.6, 0.5, 1.0),
+        (BlendMode::ColorBurn, 1.0, 0.2, 1.0),
+        (BlendMode::ColorBurn, 0.5, 0.0, 0.0),
+        (BlendMode::ColorBurn, 0.75, 0.5, 0.5),
+        (BlendMode::ColorBurn, 0.2, 0.5, 0.0),
+        (BlendMode::Darken, 0.3, 0.6, 0.3),
+        (BlendMode::Lighten, 0.3, 0.6, 0.6),
+        (BlendMode::Difference, 0.3, 0.8, 0.5),
+        (BlendMode::Difference, 0.8, 0.3, 0.5),
+        (BlendMode::Exclusion, 0.5, 0.5, 0.5),
+        (BlendMode::Exclusion, 0.2, 1.0, 0.8),
+    ];
+    
+    #[test]
+    fn blend_channel_matches_w3c_formulas() {
+        for (mode, backdrop, source, expected) in BLEND_CASES {
+            let result = mode.blend_channel(*backdrop, *source);
+            assert!(
+                (result - expected).abs() < 1e-5,
+                "{:?}({}, {}) = {}, expected {}",
+                mode, backdrop, source, result, expected
+            );
+        }
+    }
+    
+    #[test]
+    fn every_mode_has_a_case() {
+        for mode in BlendMode::ALL {
+            assert!(BLEND_CASES.iter().any(|(case, ..)| *case == mode), "{:?} is untested", mode);
         }
     }
+    
+    #[test]
+    fn blend_over_transparent_backdrop_shows_the_source() {
+        for mode in BlendMode::ALL {
+            let result = ImageData::blend_pixels(Rgba([0, 0, 0, 0]), Rgba([200, 100, 50, 255]), &mode, 1.0);
+            assert_eq!(result, Rgba([200, 100, 50, 255]), "{:?}", mode);
+        }
+    }
+    
+    // Pixels uploaded to the texture for one dab, after the first full upload
+    fn uploaded_pixels_per_dab(ctx: &egui::Context, size: u32) -> usize {
+        let mut image_data = ImageData::new(size, size);
+        let _ = image_data.get_texture(ctx);
+        let _ = ctx.tex_manager().write().take_delta();
+        
+        image_data.begin_stroke(Rgba([200, 40, 40, 255]), 1.0, 1.0, false);
+        image_data.draw_dab(100.0, 100.0, 8.0, 0.8);
+        let _ = image_data.get_texture(ctx);
+        let delta = ctx.tex_manager().write().take_delta();
+        delta
+            .set
+            .iter()
+            .map(|(_, image)| {
+                assert!(image.pos.is_some(), "dab re-uploaded the whole texture");
+                image.image.size()[0] * image.image.size()[1]
+            })
+            .sum()
+    }
+    
+    #[test]
+    fn dab_upload_does_not_depend_on_canvas_size() {
+        let ctx = egui::Context::default();
+        let small = uploaded_pixels_per_dab(&ctx, 256);
+        let large = uploaded_pixels_per_dab(&ctx, 4096);
+        assert!(small > 0);
+        assert_eq!(small, large);
+        assert!(large <= (4 * TILE_SIZE * TILE_SIZE) as usize);
+    }
+    
+    // Pixels one dab changes, the layer memory it allocates and the area it
+    // marks for re-compositing
+    fn dab_footprint(size: u32, radius: f32) -> (usize, usize, u32) {
+        let mut image_data = ImageData::new(size, size);
+        image_data.begin_stroke(Rgba([200, 40, 40, 255]), 1.0, 1.0, false);
+        image_data.draw_dab(100.0, 100.0, radius, 0.8);
+        
+        let layer = &image_data.layers[0].data;
+        let changed = (0..size.min(256))
+            .flat_map(|y| (0..size.min(256)).map(move |x| (x, y)))
+            .filter(|&(x, y)| layer.get_pixel(x, y)[3] != 0)
+            .count();
+        let damaged = image_data.dirty.take_rects(size, size).iter().map(|rect| rect.width * rect.height).sum();
+        (changed, layer.memory_bytes(), damaged)
+    }
+    
+    #[test]
+    fn dab_cost_depends_on_brush_size_not_canvas_size() {
+        for radius in [2.0, 8.0, 32.0] {
+            assert_eq!(dab_footprint(256, radius), dab_footprint(4096, radius), "radius {}", radius);
+        }
+        
+        let (small, ..) = dab_footprint(4096, 2.0);
+        let (medium, ..) = dab_footprint(4096, 8.0);
+        let (large, ..) = dab_footprint(4096, 32.0);
+        assert!(small < medium && medium < large, "{} {} {}", small, medium, large);
+    }
+    
+    #[test]
+    fn crop_shrinks_the_canvas_and_moves_every_layer() {
+        let mut image_data = ImageData::new(100, 80);
+        image_data.draw_pixel(70, 50, Rgba([255, 0, 0, 255]));
+        image_data.add_layer("Top".to_string());
+        image_data.draw_pixel(10, 10, Rgba([0, 255, 0, 255]));
+        image_data.draw_pixel(75, 60, Rgba([0, 0, 255, 255]));
+        image_data.get_active_layer_mut().opacity = 0.5;
+        
+        image_data.crop(PixelRect::new(65, 45, 20, 30));
+        
+        assert_eq!((image_data.width(), image_data.height()), (20, 30));
+        assert_eq!(image_data.layers[0].data.get_pixel(5, 5), Rgba([255, 0, 0, 255]));
+        assert_eq!(image_data.layers[1].data.get_pixel(10, 15), Rgba([0, 0, 255, 255]));
+        // The pixel outside the crop is gone
+        assert_eq!(image_data.layers[1].data.allocated_tiles(), 1);
+        assert_eq!(image_data.layers[1].data.content_bounds(), Some(PixelRect::new(10, 15, 1, 1)));
+        assert_eq!(image_data.layers[1].opacity, 0.5);
+    }
+    
+    // Two layers on a canvas spanning several tiles, each with one pixel
+    // painted away from the centre
+    fn rotation_document() -> ImageData {
+        let mut image_data = ImageData::new(TILE_SIZE * 2 + 10, TILE_SIZE + 5);
+        image_data.draw_pixel(1, 2, Rgba([255, 0, 0, 255]));
+        image_data.add_layer("Top".to_string());
+        image_data.draw_pixel(TILE_SIZE + 3, 4, Rgba([0, 0, 255, 255]));
+        let top = image_data.get_active_layer_mut();
+        top.opacity = 0.7;
+        top.blend_mode = BlendMode::Screen;
+        top.visible = false;
+        image_data
+    }
+    
+    #[test]
+    fn quarter_turns_move_every_layer_and_keep_layer_props() {
+        let original = rotation_document();
+        let (width, height) = (original.width(), original.height());
+        
+        let mut image_data = original.clone();
+        image_data.rotate(90.0, Resampling::Bilinear);
+        assert_eq!((image_data.width(), image_data.height()), (height, width));
+        assert_eq!(image_data.layers[0].data.get_pixel(height - 1 - 2, 1), Rgba([255, 0, 0, 255]));
+        assert_eq!(image_data.layers[1].data.get_pixel(height - 1 - 4, TILE_SIZE + 3), Rgba([0, 0, 255, 255]));
+        let top = &image_data.layers[1];
+        assert_eq!((top.name.as_str(), top.opacity, top.visible), ("Top", 0.7, false));
+        assert_eq!(top.blend_mode, BlendMode::Screen);
+        
+        image_data.rotate(180.0, Resampling::Bilinear);
+        assert_eq!(image_data.layers[0].data.get_pixel(2, width - 1 - 1), Rgba([255, 0, 0, 255]));
+        // -270° is another quarter turn clockwise, back to the start
+        image_data.rotate(-270.0, Resampling::Bilinear);
+        assert_eq!((image_data.width(), image_data.height()), (width, height));
+        for (turned, layer) in image_data.layers.iter().zip(&original.layers) {
+            assert_eq!(turned.data, layer.data);
+        }
+    }
+    
+    #[test]
+    fn flips_mirror_every_layer() {
+        let original = rotation_document();
+        let (width, height) = (original.width(), original.height());
+        
+        let mut image_data = original.clone();
+        image_data.flip(true);
+        assert_eq!((image_data.width(), image_data.height()), (width, height));
+        assert_eq!(image_data.layers[0].data.get_pixel(width - 1 - 1, 2), Rgba([255, 0, 0, 255]));
+        assert_eq!(image_data.layers[1].data.get_pixel(width - 1 - TILE_SIZE - 3, 4), Rgba([0, 0, 255, 255]));
+        
+        image_data.flip(false);
+        assert_eq!(image_data.layers[0].data.get_pixel(width - 1 - 1, height - 1 - 2), Rgba([255, 0, 0, 255]));
+        assert_eq!(image_data.layers[1].opacity, 0.7);
+    }
+    
+    #[test]
+    fn arbitrary_rotation_grows_the_canvas_to_fit() {
+        let mut image_data = ImageData::new(100, 50);
+        for y in 0..50 {
+            for x in 0..100 {
+                image_data.draw_pixel(x, y, Rgba([0, 0, 0, 255]));
+            }
+        }
+        
+        let mut turned = image_data.clone();
+        turned.rotate(30.0, Resampling::Bilinear);
+        // 100·cos 30° + 50·sin 30° by 100·sin 30° + 50·cos 30°, rounded up
+        assert_eq!((turned.width(), turned.height()), (112, 94));
+        let layer = &turned.layers[0].data;
+        assert_eq!(layer.get_pixel(56, 47)[3], 255);
+        assert_eq!(layer.get_pixel(0, 0)[3], 0);
+        assert_eq!(layer.get_pixel(111, 93)[3], 0);
+        
+        image_data.rotate(45.0, Resampling::Nearest);
+        assert_eq!((image_data.width(), image_data.height()), (107, 107));
+    }
+    
+    fn quadrants() -> ImageData {
+        let mut image_data = ImageData::new(2, 2);
+        image_data.draw_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        image_data.draw_pixel(1, 0, Rgba([0, 255, 0, 255]));
+        image_data.draw_pixel(0, 1, Rgba([0, 0, 255, 255]));
+        image_data.draw_pixel(1, 1, Rgba([255, 255, 0, 128]));
+        image_data
+    }
+    
+    #[test]
+    fn nearest_neighbour_doubling_repeats_each_pixel() {
+        let original = quadrants();
+        let mut image_data = original.clone();
+        image_data.resize_image(4, 4, Resampling::Nearest);
+        
+        assert_eq!((image_data.width(), image_data.height()), (4, 4));
+        for y in 0..4 {
+            for x in 0..4 {
+                assert_eq!(
+                    image_data.layers[0].data.get_pixel(x, y),
+                    original.layers[0].data.get_pixel(x / 2, y / 2),
+                    "({}, {})", x, y
+                );
+            }
+        }
+    }
+    
+    #[test]
+    fn resizing_to_the_same_size_changes_nothing() {
+        let original = quadrants();
+        for resampling in Resampling::ALL {
+            let mut image_data = original.clone();
+            image_data.resize_image(2, 2, resampling);
+            assert_eq!(image_data.layers[0].data, original.layers[0].data, "{:?}", resampling);
+        }
+        
+        let mut image_data = original.clone();
+        image_data.resize_canvas_anchored(2, 2, Anchor::CENTER, Some(Rgba([255, 255, 255, 255])));
+        assert_eq!(image_data.layers[0].data, original.layers[0].data);
+    }
+    
+    #[test]
+    fn canvas_growth_fills_only_the_new_area_of_the_bottom_layer() {
+        let mut original = quadrants();
+        original.add_layer("Top".to_string());
+        let white = Rgba([255, 255, 255, 255]);
+        
+        let mut filled = original.clone();
+        filled.resize_canvas_anchored(4, 3, Anchor { column: 2, row: 0 }, Some(white));
+        assert_eq!((filled.width(), filled.height()), (4, 3));
+        let bottom = &filled.layers[0].data;
+        // The old pixels sit in the top-right corner
+        assert_eq!(bottom.get_pixel(2, 0), Rgba([255, 0, 0, 255]));
+        assert_eq!(bottom.get_pixel(3, 1), Rgba([255, 255, 0, 128]));
+        for (x, y) in [(0, 0), (1, 1), (0, 2), (3, 2)] {
+            assert_eq!(bottom.get_pixel(x, y), white, "({}, {})", x, y);
+        }
+        assert_eq!(filled.layers[1].data.allocated_tiles(), 0);
+        
+        let mut transparent = original.clone();
+        transparent.resize_canvas_anchored(4, 3, Anchor { column: 2, row: 0 }, None);
+        let bottom = &transparent.layers[0].data;
+        assert_eq!(bottom.get_pixel(2, 0), Rgba([255, 0, 0, 255]));
+        for (x, y) in [(0, 0), (1, 1), (0, 2), (3, 2)] {
+            assert_eq!(bottom.get_pixel(x, y), TRANSPARENT, "({}, {})", x, y);
+        }
+    }
+    
+    #[test]
+    fn canvas_shrink_keeps_the_anchored_part() {
+        let mut image_data = quadrants();
+        image_data.resize_canvas_anchored(1, 1, Anchor { column: 2, row: 2 }, Some(Rgba([255, 255, 255, 255])));
+        assert_eq!((image_data.width(), image_data.height()), (1, 1));
+        assert_eq!(image_data.layers[0].data.get_pixel(0, 0), Rgba([255, 255, 0, 128]));
+    }
+    
+    // 8x8 document whose left half is selected, with a half selected
+    // column between the two halves
+    fn half_selected(background: Rgba<u8>) -> ImageData {
+        let mut image_data = ImageData::from_image(&RgbaImage::from_pixel(8, 8, background));
+        let mask = SelectionMask::from_fn(8, 8, |x, _| match x {
+            0..=3 => 255,
+            4 => 128,
+            _ => 0,
+        });
+        image_data.set_selection(Some(mask));
+        image_data
+    }
+    
+    // Checks pixels right of the selection are untouched, the selected
+    // ones changed and the soft column landed in between
+    fn assert_clipped(image_data: &ImageData, before: &TiledImage) {
+        let after = &image_data.layers[0].data;
+        for y in 0..8 {
+            for x in 5..8 {
+                assert_eq!(after.get_pixel(x, y), before.get_pixel(x, y), "({}, {})", x, y);
+            }
+        }
+        let changed = |x: u32, y: u32| after.get_pixel(x, y) != before.get_pixel(x, y);
+        assert!((0..4).any(|x| changed(x, 4)), "nothing inside the selection changed");
+        assert!(changed(4, 4));
+    }
+    
+    #[test]
+    fn dabs_only_paint_inside_the_selection() {
+        let mut image_data = half_selected(TRANSPARENT);
+        let before = image_data.layers[0].data.clone();
+        image_data.begin_stroke(Rgba([200, 40, 40, 255]), 1.0, 1.0, false);
+        image_data.draw_dab(4.0, 4.0, 3.0, 1.0);
+        assert_clipped(&image_data, &before);
+        
+        let layer = &image_data.layers[0].data;
+        assert_eq!(layer.get_pixel(3, 4), Rgba([200, 40, 40, 255]));
+        assert_eq!(layer.get_pixel(4, 4)[3], 128);
+    }
+    
+    #[test]
+    fn fill_only_covers_the_selection() {
+        let mut image_data = half_selected(Rgba([255, 255, 255, 255]));
+        let before = image_data.layers[0].data.clone();
+        let options = FillOptions { tolerance: 0.0, contiguous: true, anti_alias: false, sample_merged: false };
+        image_data.fill(7, 7, Rgba([0, 0, 0, 255]), &options);
+        assert_clipped(&image_data, &before);
+        
+        let layer = &image_data.layers[0].data;
+        assert_eq!(layer.get_pixel(0, 0), Rgba([0, 0, 0, 255]));
+        assert_eq!(layer.get_pixel(4, 0), Rgba([127, 127, 127, 255]));
+    }
+    
+    #[test]
+    fn adjustments_and_filters_only_change_the_selection() {
+        let mut image_data = half_selected(Rgba([100, 100, 100, 255]));
+        let source = image_data.layers[0].data.clone();
+        image_data.apply_adjustment(0, &source, &Adjustment::BrightnessContrast { brightness: 0.5, contrast: 0.0 });
+        assert_clipped(&image_data, &source);
+        
+        let mut image_data = half_selected(TRANSPARENT);
+        let mut source = image_data.layers[0].data.clone();
+        for y in 0..8 {
+            for x in (0..8).step_by(2) {
+                source.put_pixel(x, y, Rgba([0, 0, 255, 255]));
+            }
+        }
+        image_data.layers[0].data = source.clone();
+        image_data.apply_filter(0, &source, &Filter::GaussianBlur { radius: 2.0 }, EdgeMode::Clamp);
+        assert_clipped(&image_data, &source);
+    }
+    
+    // Grey columns either side of a red one, with a slightly redder grey
+    // column at the right
+    fn wand_document() -> ImageData {
+        let grey = Rgba([128, 128, 128, 255]);
+        let columns = [grey, grey, Rgba([255, 0, 0, 255]), grey, grey, Rgba([140, 128, 128, 255])];
+        ImageData::from_image(&RgbaImage::from_fn(6, 3, |x, _| columns[x as usize]))
+    }
+    
+    fn selected_columns(image_data: &ImageData) -> Vec<u32> {
+        let selection = image_data.selection().expect("no selection");
+        (0..6)
+            .filter(|&x| {
+                let column: Vec<u8> = (0..3).map(|y| selection.get(x, y)).collect();
+                assert!(column == [0; 3] || column == [255; 3], "column {} is {:?}", x, column);
+                column[0] == 255
+            })
+            .collect()
+    }
+    
+    #[test]
+    fn magic_wand_contiguous_and_global() {
+        let wand = |tolerance: f32, contiguous: bool| {
+            let mut image_data = wand_document();
+            let options = FillOptions { tolerance, contiguous, anti_alias: false, sample_merged: false };
+            image_data.magic_wand(0, 1, &options, SelectionMode::Replace);
+            selected_columns(&image_data)
+        };
+        assert_eq!(wand(0.0, true), [0, 1]);
+        assert_eq!(wand(0.0, false), [0, 1, 3, 4]);
+        // 12 levels apart is within a tolerance of 0.1 but not of 0.04
+        assert_eq!(wand(0.04, false), [0, 1, 3, 4]);
+        assert_eq!(wand(0.1, false), [0, 1, 3, 4, 5]);
+        assert_eq!(wand(0.1, true), [0, 1]);
+    }
+    
+    #[test]
+    fn magic_wand_combines_with_the_selection() {
+        let mut image_data = wand_document();
+        let options = FillOptions { tolerance: 0.0, contiguous: true, anti_alias: false, sample_merged: false };
+        image_data.magic_wand(0, 0, &options, SelectionMode::Replace);
+        image_data.magic_wand(5, 0, &options, SelectionMode::Add);
+        assert_eq!(selected_columns(&image_data), [0, 1, 5]);
+        image_data.magic_wand(1, 2, &options, SelectionMode::Subtract);
+        assert_eq!(selected_columns(&image_data), [5]);
+    }
+    
+    #[test]
+    fn applying_a_layer_offset_moves_its_pixels_and_text() {
+        let image = RgbaImage::from_fn(4, 3, |x, y| Rgba([(x * 60) as u8, (y * 60) as u8, 0, 255]));
+        let mut image_data = ImageData::from_image(&image);
+        image_data.layers[0].offset = (1, -1);
+        image_data.apply_layer_offset(0);
+        
+        let layer = &image_data.layers[0];
+        assert_eq!(layer.offset, (0, 0));
+        for y in 0..3 {
+            for x in 0..4 {
+                let expected = if x >= 1 && y < 2 { *image.get_pixel(x - 1, y + 1) } else { TRANSPARENT };
+                assert_eq!(layer.data.get_pixel(x, y), expected, "({}, {})", x, y);
+            }
+        }
+        
+        let mut text = Layer::new("Text".to_string(), 4, 3);
+        text.kind = LayerKind::Text(TextLayer { x: 5.0, y: 6.0, ..TextLayer::default() });
+        text.offset = (-2, 3);
+        image_data.layers.push(text);
+        image_data.apply_layer_offset(1);
+        let text = image_data.layers[1].text().unwrap();
+        assert_eq!((text.x, text.y), (3.0, 9.0));
+    }
+    
+    // Three layers on a 4x1 canvas: an opaque background, a half-opaque red
+    // layer over the first three pixels and a blue layer over the last three
+    fn layered_document() -> ImageData {
+        let mut image_data = ImageData::new(4, 1);
+        for x in 0..4 {
+            image_data.layers[0].data.put_pixel(x, 0, Rgba([200, 200, 200, 255]));
+        }
+        image_data.add_layer("Red".to_string());
+        for x in 0..3 {
+            image_data.layers[1].data.put_pixel(x, 0, Rgba([255, 0, 0, 255]));
+        }
+        image_data.layers[1].opacity = 0.5;
+        image_data.add_layer("Blue".to_string());
+        for x in 1..4 {
+            image_data.layers[2].data.put_pixel(x, 0, Rgba([0, 0, 255, 160]));
+        }
+        image_data.layers[2].opacity = 0.6;
+        image_data
+    }
+    
+    fn assert_looks_the_same(before: &RgbaImage, after: &RgbaImage) {
+        for (x, y, pixel) in before.enumerate_pixels() {
+            let merged = after.get_pixel(x, y);
+            for channel in 0..4 {
+                assert!(
+                    (pixel[channel] as i32 - merged[channel] as i32).abs() <= 2,
+                    "({}, {}): {:?} became {:?}", x, y, pixel, merged
+                );
+            }
+        }
+    }
+    
+    fn names(image_data: &ImageData) -> Vec<&str> {
+        image_data.layers.iter().map(|layer| layer.name.as_str()).collect()
+    }
+    
+    #[test]
+    fn move_layer_keeps_the_active_layer() {
+        let mut image_data = layered_document();
+        image_data.active_layer = 1;
+        image_data.move_layer(2, 0);
+        assert_eq!(names(&image_data), ["Blue", "Background", "Red"]);
+        assert_eq!(image_data.active_layer, 2);
+        
+        image_data.move_layer(2, 1);
+        assert_eq!(names(&image_data), ["Blue", "Red", "Background"]);
+        assert_eq!(image_data.active_layer, 1);
+        
+        // Out of range moves do nothing
+        image_data.move_layer(3, 0);
+        assert_eq!(names(&image_data), ["Blue", "Red", "Background"]);
+    }
+    
+    #[test]
+    fn merge_down_bakes_the_lower_layer_opacity() {
+        let mut image_data = layered_document();
+        let before = image_data.flatten();
+        image_data.merge_down(2);
+        
+        assert_eq!(names(&image_data), ["Background", "Red"]);
+        assert_eq!(image_data.active_layer, 1);
+        let merged = &image_data.layers[1];
+        assert_eq!(merged.opacity, 1.0);
+        assert_eq!(merged.blend_mode, BlendMode::Normal);
+        assert_looks_the_same(&before, &image_data.flatten());
+    }
+    
+    #[test]
+    fn merge_down_refuses_layers_it_cannot_merge_faithfully() {
+        let mut image_data = layered_document();
+        image_data.layers[1].visible = false;
+        assert!(!image_data.can_merge_down(2));
+        image_data.merge_down(2);
+        assert_eq!(image_data.layers.len(), 3);
+        
+        image_data.layers[1].visible = true;
+        image_data.layers[1].blend_mode = BlendMode::Screen;
+        assert!(!image_data.can_merge_down(2));
+        image_data.merge_down(2);
+        assert_eq!(image_data.layers.len(), 3);
+        
+        image_data.layers[2].visible = false;
+        assert!(!image_data.can_merge_down(2));
+        assert!(!image_data.can_merge_down(0));
+    }
+    
+    #[test]
+    fn merge_visible_keeps_hidden_layers() {
+        let mut image_data = layered_document();
+        image_data.layers[0].visible = false;
+        let before = image_data.flatten();
+        image_data.merge_visible();
+        
+        assert_eq!(names(&image_data), ["Background", "Red"]);
+        assert!(!image_data.layers[0].visible);
+        assert_eq!(image_data.active_layer, 1);
+        assert_looks_the_same(&before, &image_data.flatten());
+    }
+    
+    #[test]
+    fn flatten_image_leaves_one_matching_layer() {
+        let mut image_data = layered_document();
+        image_data.layers[1].visible = false;
+        let before = image_data.flatten();
+        image_data.flatten_image();
+        
+        assert_eq!(names(&image_data), ["Background"]);
+        assert_eq!(image_data.active_layer, 0);
+        assert_eq!(image_data.flatten(), before);
+    }
 }
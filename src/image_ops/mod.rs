@@ -3,9 +3,18 @@ pub mod history;
 pub mod adjustments;
 pub mod filters;
 pub mod clipboard_ops;
+pub mod project;
+pub mod dirty;
+pub mod tiles;
+pub mod delta;
+pub mod paint;
+pub mod fill;
+pub mod shapes;
+pub mod text;
+pub mod transform;
+pub mod histogram;
+pub mod selection;
 
-use std::path::Path;
-use anyhow::Result;
 use image::{DynamicImage, ImageBuffer, Rgba};
 
 pub use image_data::ImageData;
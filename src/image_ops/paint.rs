@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use image::Rgba;
+use crate::image_ops::tiles::{TiledImage, TILE_SIZE, TRANSPARENT};
+
+// Pixel-level painting operations on straight (non-premultiplied) RGBA.
+// `coverage` scales the source alpha, e.g. for soft brush edges.
+
+pub fn source_over(dst: Rgba<u8>, src: Rgba<u8>, coverage: f32) -> Rgba<u8> {
+    let src_alpha = src[3] as f32 / 255.0 * coverage.clamp(0.0, 1.0);
+    if src_alpha <= 0.0 {
+        return dst;
+    }
+    
+    let dst_alpha = dst[3] as f32 / 255.0;
+    let out_alpha = src_alpha + dst_alpha * (1.0 - src_alpha);
+    if out_alpha <= 0.0 {
+        return TRANSPARENT;
+    }
+    
+    let mut out = [0u8; 4];
+    for i in 0..3 {
+        let c = (src[i] as f32 * src_alpha + dst[i] as f32 * dst_alpha * (1.0 - src_alpha)) / out_alpha;
+        out[i] = c.round().clamp(0.0, 255.0) as u8;
+    }
+    out[3] = (out_alpha * 255.0).round() as u8;
+    Rgba(out)
+}
+
+// Interpolates from `from` to `to` by `t` (0..=1), weighting colour by
+// alpha so transparent pixels don't tint the result
+pub fn mix(from: Rgba<u8>, to: Rgba<u8>, t: f32) -> Rgba<u8> {
+    let t = t.clamp(0.0, 1.0);
+    let from_alpha = from[3] as f32 * (1.0 - t);
+    let to_alpha = to[3] as f32 * t;
+    let alpha = from_alpha + to_alpha;
+    if alpha <= 0.0 {
+        return TRANSPARENT;
+    }
+    
+    let mut out = [0u8; 4];
+    for i in 0..3 {
+        let c = (from[i] as f32 * from_alpha + to[i] as f32 * to_alpha) / alpha;
+        out[i] = c.round().clamp(0.0, 255.0) as u8;
+    }
+    out[3] = alpha.round().clamp(0.0, 255.0) as u8;
+    Rgba(out)
+}
+
+// Removes `strength` (0..=1) of the destination's alpha
+pub fn erase(dst: Rgba<u8>, strength: f32) -> Rgba<u8> {
+    let alpha = dst[3] as f32 * (1.0 - strength.clamp(0.0, 1.0));
+    Rgba([dst[0], dst[1], dst[2], alpha.round() as u8])
+}
+
+// Fraction of a pixel covered by a disc edge, given the distance from
+// the pixel centre to the disc centre. Gives a one pixel wide ramp.
+pub fn edge_coverage(distance: f32, radius: f32) -> f32 {
+    (radius + 0.5 - distance).clamp(0.0, 1.0)
+}
+
+// Radial falloff of a round dab. Everything inside `hardness * radius`
+// is painted at full strength, then it eases out to zero at `radius`.
+// The outer edge is anti-aliased, so fully hard dabs stay smooth.
+pub fn dab_falloff(distance: f32, radius: f32, hardness: f32) -> f32 {
+    let edge = edge_coverage(distance, radius);
+    if edge <= 0.0 {
+        return 0.0;
+    }
+    
+    // A fully hard dab has no ramp of its own, only the anti-aliased edge
+    let hard_radius = radius * hardness.clamp(0.0, 1.0);
+    if distance <= hard_radius || radius - hard_radius <= 0.0 {
+        return edge;
+    }
+    
+    let t = ((distance - hard_radius) / (radius - hard_radius)).min(1.0);
+    edge * (1.0 - t * t * (3.0 - 2.0 * t))
+}
+
+// Paint laid down by one stroke. Each dab adds coverage at `flow`, but
+// pixels are always recomposited from the layer as it was when the stroke
+// began, with the stroke capped at `opacity`. Overlapping dabs inside one
+// stroke therefore build up to the stroke opacity and never beyond it.
+pub struct Stroke {
+    color: Rgba<u8>,
+    opacity: f32,
+    flow: f32,
+    erase: bool,
+    origin: TiledImage,
+    coverage: HashMap<(u32, u32), Vec<f32>>,
+}
+
+impl Stroke {
+    // `opacity` and `flow` are 0..=1 and multiply the colour's own alpha
+    pub fn new(origin: TiledImage, color: Rgba<u8>, opacity: f32, flow: f32, erase: bool) -> Self {
+        Self {
+            color,
+            opacity: opacity.clamp(0.0, 1.0),
+            flow: flow.clamp(0.0, 1.0),
+            erase,
+            origin,
+            coverage: HashMap::new(),
+        }
+    }
+    
+    // Adds one dab's `amount` (0..=1) at a pixel and returns the new pixel
+    // value. `limit` (0..=1) caps the stroke there, e.g. on a soft selection edge.
+    pub fn apply(&mut self, x: u32, y: u32, amount: f32, limit: f32) -> Rgba<u8> {
+        let tile = self.coverage
+            .entry((x / TILE_SIZE, y / TILE_SIZE))
+            .or_insert_with(|| vec![0.0; (TILE_SIZE * TILE_SIZE) as usize]);
+        let coverage = &mut tile[((y % TILE_SIZE) * TILE_SIZE + x % TILE_SIZE) as usize];
+        let dab = (amount * self.flow).clamp(0.0, 1.0);
+        *coverage += dab * (1.0 - *coverage);
+        
+        let strength = *coverage * self.opacity * limit.clamp(0.0, 1.0);
+        let dst = self.origin.get_pixel(x, y);
+        if self.erase {
+            erase(dst, strength * self.color[3] as f32 / 255.0)
+        } else {
+            source_over(dst, self.color, strength)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn hard_dab_keeps_its_anti_aliased_edge() {
+        // Pixel centres in the outer half-pixel of a radius 4 disc
+        for (distance, coverage) in [(4.0, 0.5), (4.25, 0.25), (3.75, 0.75), (3.5, 1.0)] {
+            assert!((dab_falloff(distance, 4.0, 1.0) - coverage).abs() < 1e-6, "distance {distance}");
+        }
+        assert_eq!(dab_falloff(4.5, 4.0, 1.0), 0.0);
+    }
+    
+    #[test]
+    fn soft_dab_eases_out_to_the_radius() {
+        assert_eq!(dab_falloff(1.0, 4.0, 0.5), 1.0);
+        assert!((dab_falloff(3.0, 4.0, 0.5) - 0.5).abs() < 1e-6);
+        assert_eq!(dab_falloff(4.0, 4.0, 0.5), 0.0);
+        assert!(dab_falloff(2.5, 4.0, 0.5) > dab_falloff(3.5, 4.0, 0.5));
+    }
+    
+    #[test]
+    fn falloff_is_finite_for_every_hardness() {
+        for hardness in [0.0, 0.5, 0.99, 1.0, 1.5] {
+            for step in 0..20 {
+                let coverage = dab_falloff(step as f32 * 0.25, 4.0, hardness);
+                assert!((0.0..=1.0).contains(&coverage), "hardness {hardness}: {coverage}");
+            }
+        }
+    }
+}
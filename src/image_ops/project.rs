@@ -0,0 +1,324 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Cursor, Read, Write};
+use std::path::Path;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+use crate::image_ops::image_data::{BlendMode, ImageData, Layer, LayerKind};
+use crate::image_ops::text::TextLayer;
+use crate::image_ops::tiles::TiledImage;
+
+// Native PaintMate document: a zip container holding a JSON manifest and
+// one PNG per layer, bottom layer first.
+pub const PROJECT_EXTENSION: &str = "pmate";
+pub const FORMAT_VERSION: u32 = 1;
+
+const MANIFEST_NAME: &str = "manifest.json";
+const ZIP_MAGIC: [u8; 4] = [0x50, 0x4b, 0x03, 0x04];
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    format_version: u32,
+    width: u32,
+    height: u32,
+    active_layer: usize,
+    layers: Vec<LayerEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LayerEntry {
+    name: String,
+    #[serde(default = "default_visible")]
+    visible: bool,
+    #[serde(default = "default_opacity")]
+    opacity: f32,
+    #[serde(default = "default_blend_mode")]
+    blend_mode: BlendMode,
+    file: String,
+    // Present for text layers, which stay editable after reopening
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<TextLayer>,
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+fn default_blend_mode() -> BlendMode {
+    BlendMode::Normal
+}
+
+pub fn has_project_extension<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case(PROJECT_EXTENSION))
+        .unwrap_or(false)
+}
+
+pub fn is_project_file<P: AsRef<Path>>(path: P) -> bool {
+    if has_project_extension(&path) {
+        return true;
+    }
+    
+    // Fall back to sniffing the contents so renamed projects still open.
+    // Other zip-based formats (.docx, .ora, ...) have no manifest.
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return false,
+    };
+    let mut header = [0u8; 4];
+    if file.read_exact(&mut header).is_err() || header != ZIP_MAGIC {
+        return false;
+    }
+    match ZipArchive::new(BufReader::new(file)) {
+        Ok(mut zip) => zip.by_name(MANIFEST_NAME).is_ok(),
+        Err(_) => false,
+    }
+}
+
+pub fn save_project<P: AsRef<Path>>(image_data: &ImageData, path: P) -> Result<()> {
+    let file = File::create(path.as_ref())
+        .with_context(|| format!("Failed to create {}", path.as_ref().display()))?;
+    let mut zip = ZipWriter::new(BufWriter::new(file));
+    
+    let mut entries = Vec::with_capacity(image_data.layers.len());
+    for (i, layer) in image_data.layers.iter().enumerate() {
+        let file_name = format!("layers/{:04}.png", i);
+        let mut png = Vec::new();
+        layer.data.to_image().write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)?;
+        
+        // PNG data is already compressed, so store it as-is
+        zip.start_file(&file_name, FileOptions::default().compression_method(CompressionMethod::Stored))?;
+        zip.write_all(&png)?;
+        
+        entries.push(LayerEntry {
+            name: layer.name.clone(),
+            visible: layer.visible,
+            opacity: layer.opacity,
+            blend_mode: layer.blend_mode.clone(),
+            file: file_name,
+            text: layer.text().cloned(),
+        });
+    }
+    
+    let manifest = Manifest {
+        format_version: FORMAT_VERSION,
+        width: image_data.width(),
+        height: image_data.height(),
+        active_layer: image_data.active_layer,
+        layers: entries,
+    };
+    
+    zip.start_file(MANIFEST_NAME, FileOptions::default().compression_method(CompressionMethod::Deflated))?;
+    zip.write_all(serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    zip.finish()?.flush()?;
+    
+    Ok(())
+}
+
+pub fn load_project<P: AsRef<Path>>(path: P) -> Result<ImageData> {
+    let file = File::open(path.as_ref())
+        .with_context(|| format!("Failed to open {}", path.as_ref().display()))?;
+    let mut zip = ZipArchive::new(BufReader::new(file))?;
+    
+    let manifest: Manifest = {
+        let mut entry = zip
+            .by_name(MANIFEST_NAME)
+            .map_err(|_| anyhow!("Not a PaintMate project: missing {}", MANIFEST_NAME))?;
+        let mut json = String::new();
+        entry.read_to_string(&mut json)?;
+        serde_json::from_str(&json)?
+    };
+    
+    if manifest.format_version == 0 || manifest.format_version > FORMAT_VERSION {
+        bail!(
+            "Unsupported project format version {} (this build reads up to {})",
+            manifest.format_version,
+            FORMAT_VERSION
+        );
+    }
+    
+    if manifest.layers.is_empty() {
+        bail!("Project contains no layers");
+    }
+    
+    let mut layers = Vec::with_capacity(manifest.layers.len());
+    for entry in manifest.layers {
+        let mut png = Vec::new();
+        zip.by_name(&entry.file)
+            .map_err(|_| anyhow!("Project is missing layer data {}", entry.file))?
+            .read_to_end(&mut png)?;
+        
+        let data = image::load_from_memory_with_format(&png, image::ImageFormat::Png)?.to_rgba8();
+        if data.dimensions() != (manifest.width, manifest.height) {
+            bail!(
+                "Layer '{}' is {}x{}, expected {}x{}",
+                entry.name,
+                data.width(),
+                data.height(),
+                manifest.width,
+                manifest.height
+            );
+        }
+        
+        let mut layer = Layer::with_data(entry.name, TiledImage::from_image(&data));
+        layer.visible = entry.visible;
+        layer.opacity = entry.opacity;
+        layer.blend_mode = entry.blend_mode;
+        if let Some(mut text) = entry.text {
+            text.reload_font();
+            layer.kind = LayerKind::Text(text);
+        }
+        layers.push(layer);
+    }
+    
+    let active_layer = manifest.active_layer.min(layers.len() - 1);
+    Ok(ImageData::from_layers(layers, active_layer, manifest.width, manifest.height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use crate::image_ops::text::TextAlign;
+    
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("paintmate-{}-{}", std::process::id(), name))
+    }
+    
+    // Four layers spanning several tiles, with every property set away from
+    // its default somewhere and the second layer active
+    fn layered_document() -> ImageData {
+        let mut image_data = ImageData::new(70, 40);
+        image_data.draw_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        image_data.draw_pixel(69, 39, Rgba([0, 0, 255, 128]));
+        
+        image_data.add_layer("Shadow".to_string());
+        image_data.draw_pixel(65, 10, Rgba([10, 20, 30, 200]));
+        let shadow = image_data.get_active_layer_mut();
+        shadow.visible = false;
+        shadow.opacity = 0.35;
+        shadow.blend_mode = BlendMode::Multiply;
+        
+        image_data.add_text_layer(TextLayer {
+            text: "Hi\nthere".to_string(),
+            x: 60.0,
+            y: 4.0,
+            size: 12.0,
+            color: [0, 128, 0, 255],
+            align: TextAlign::Right,
+            line_spacing: 1.5,
+            ..TextLayer::default()
+        });
+        image_data.get_active_layer_mut().blend_mode = BlendMode::Screen;
+        
+        image_data.add_layer("Glow".to_string());
+        image_data.draw_pixel(3, 33, Rgba([255, 255, 0, 255]));
+        let glow = image_data.get_active_layer_mut();
+        glow.opacity = 0.8;
+        glow.blend_mode = BlendMode::Overlay;
+        
+        image_data.active_layer = 1;
+        image_data
+    }
+    
+    // Writes a project by hand so the loader can be fed what save_project
+    // would never produce
+    fn write_project(path: &Path, format_version: u32, layer: &RgbaImage) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        zip.start_file("layers/0000.png", FileOptions::default()).unwrap();
+        let mut png = Vec::new();
+        layer.write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png).unwrap();
+        zip.write_all(&png).unwrap();
+        
+        let manifest = Manifest {
+            format_version,
+            width: 4,
+            height: 4,
+            active_layer: 0,
+            layers: vec![LayerEntry {
+                name: "Background".to_string(),
+                visible: true,
+                opacity: 1.0,
+                blend_mode: BlendMode::Normal,
+                file: "layers/0000.png".to_string(),
+                text: None,
+            }],
+        };
+        zip.start_file(MANIFEST_NAME, FileOptions::default()).unwrap();
+        zip.write_all(serde_json::to_string(&manifest).unwrap().as_bytes()).unwrap();
+        zip.finish().unwrap();
+    }
+    
+    #[test]
+    fn save_and_load_rebuild_the_layer_stack() {
+        let path = temp_path("round-trip.pmate");
+        let saved = layered_document();
+        save_project(&saved, &path).unwrap();
+        let loaded = load_project(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        
+        assert_eq!((loaded.width(), loaded.height()), (70, 40));
+        assert_eq!(loaded.active_layer, 1);
+        assert_eq!(loaded.layers.len(), saved.layers.len());
+        for (loaded, saved) in loaded.layers.iter().zip(&saved.layers) {
+            assert_eq!(loaded.name, saved.name);
+            assert_eq!(loaded.visible, saved.visible);
+            assert_eq!(loaded.opacity, saved.opacity);
+            assert_eq!(loaded.blend_mode, saved.blend_mode);
+            assert_eq!(loaded.kind, saved.kind, "layer {}", saved.name);
+            assert_eq!(loaded.data.to_image(), saved.data.to_image(), "layer {}", saved.name);
+        }
+    }
+    
+    #[test]
+    fn unsupported_format_versions_are_rejected() {
+        let layer = RgbaImage::new(4, 4);
+        for version in [0, FORMAT_VERSION + 1] {
+            let path = temp_path(&format!("version-{}.pmate", version));
+            write_project(&path, version, &layer);
+            let error = load_project(&path).err().expect("version should be rejected");
+            std::fs::remove_file(&path).unwrap();
+            assert!(error.to_string().contains("Unsupported project format version"), "{}", error);
+        }
+        
+        let path = temp_path("version-current.pmate");
+        write_project(&path, FORMAT_VERSION, &layer);
+        assert!(load_project(&path).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+    
+    #[test]
+    fn layers_of_the_wrong_size_are_rejected() {
+        let path = temp_path("wrong-size.pmate");
+        write_project(&path, FORMAT_VERSION, &RgbaImage::new(4, 5));
+        let error = load_project(&path).err().expect("layer size should be rejected");
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.to_string().contains("is 4x5, expected 4x4"), "{}", error);
+    }
+    
+    #[test]
+    fn renamed_project_is_detected_by_its_manifest() {
+        let path = temp_path("project.zip");
+        save_project(&ImageData::new(4, 4), &path).unwrap();
+        assert!(is_project_file(&path));
+        std::fs::remove_file(&path).unwrap();
+    }
+    
+    #[test]
+    fn other_zip_files_are_not_projects() {
+        let path = temp_path("document.docx");
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        zip.start_file("word/document.xml", FileOptions::default()).unwrap();
+        zip.write_all(b"<document/>").unwrap();
+        zip.finish().unwrap();
+        
+        assert!(!is_project_file(&path));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,708 @@
+use std::collections::VecDeque;
+use eframe::egui;
+use image::Rgba;
+use crate::image_ops::dirty::PixelRect;
+use crate::image_ops::fill;
+use crate::image_ops::filters::{self, EdgeMode, FloatImage, Kernel};
+use crate::image_ops::paint;
+use crate::image_ops::shapes::{self, Shape};
+use crate::image_ops::transform;
+
+// Supersampling rows per pixel when rasterising polygons
+const POLYGON_SUBSAMPLES: u32 = 4;
+
+// How a new selection combines with the existing one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelectionMode {
+    Replace,
+    Add,
+    Subtract,
+    Intersect,
+}
+
+impl SelectionMode {
+    pub const ALL: [SelectionMode; 4] = [SelectionMode::Replace, SelectionMode::Add, SelectionMode::Subtract, SelectionMode::Intersect];
+    
+    pub fn name(&self) -> &'static str {
+        match self {
+            SelectionMode::Replace => "Replace",
+            SelectionMode::Add => "Add",
+            SelectionMode::Subtract => "Subtract",
+            SelectionMode::Intersect => "Intersect",
+        }
+    }
+    
+    // Shift adds, Alt subtracts and both together intersect
+    pub fn from_modifiers(modifiers: &egui::Modifiers) -> Option<SelectionMode> {
+        match (modifiers.shift, modifiers.alt) {
+            (true, true) => Some(SelectionMode::Intersect),
+            (true, false) => Some(SelectionMode::Add),
+            (false, true) => Some(SelectionMode::Subtract),
+            (false, false) => None,
+        }
+    }
+}
+
+// Select > Modify commands, each taking a distance in pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelectionModify {
+    Grow,
+    Shrink,
+    // Softens the edge, fading out over about the radius on each side
+    Feather,
+    // Band of the given width centred on the edge
+    Border,
+}
+
+impl SelectionModify {
+    pub const ALL: [SelectionModify; 4] = [SelectionModify::Grow, SelectionModify::Shrink, SelectionModify::Feather, SelectionModify::Border];
+    
+    pub fn name(&self) -> &'static str {
+        match self {
+            SelectionModify::Grow => "Grow",
+            SelectionModify::Shrink => "Shrink",
+            SelectionModify::Feather => "Feather",
+            SelectionModify::Border => "Border",
+        }
+    }
+}
+
+// Outline of a new selection in image coordinates
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectionShape {
+    Rectangle(egui::Rect),
+    // Ellipse inscribed in the rect
+    Ellipse(egui::Rect),
+    // Closed polygon, from the lasso tools
+    Polygon(Vec<egui::Pos2>),
+}
+
+// How strongly each pixel of the document is selected, 0 (not at all) to
+// 255 (fully). Soft values come from anti-aliased or feathered edges.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectionMask {
+    width: u32,
+    height: u32,
+    values: Vec<u8>,
+    bounds: Option<PixelRect>,
+    // Pixel edges between selected and unselected pixels, merged into
+    // straight runs, for drawing marching ants
+    outline: Vec<[egui::Pos2; 2]>,
+}
+
+impl SelectionMask {
+    pub fn all(width: u32, height: u32) -> Self {
+        Self::from_values(width, height, vec![255; (width * height) as usize])
+    }
+    
+    // Mask with the strength `f` gives each pixel
+    pub fn from_fn<F: Fn(u32, u32) -> u8>(width: u32, height: u32, f: F) -> Self {
+        let values = (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).map(|(x, y)| f(x, y)).collect();
+        Self::from_values(width, height, values)
+    }
+    
+    // Select > Color Range: pixels whose colour (from `sample`) is within
+    // `fuzziness` (0..=1) of `color`. Pixels closer than half the fuzziness
+    // are fully selected, fading out to nothing at the full distance.
+    pub fn from_color_range<F>(width: u32, height: u32, color: Rgba<u8>, fuzziness: f32, sample: F) -> Self
+    where
+        F: Fn(u32, u32) -> Rgba<u8>,
+    {
+        let fuzziness = fuzziness.clamp(0.0, 1.0);
+        Self::from_fn(width, height, |x, y| {
+            let distance = fill::color_distance(sample(x, y), color);
+            if distance <= fuzziness * 0.5 {
+                255
+            } else if distance >= fuzziness {
+                0
+            } else {
+                ((fuzziness - distance) / (fuzziness * 0.5) * 255.0).round() as u8
+            }
+        })
+    }
+    
+    pub fn from_values(width: u32, height: u32, values: Vec<u8>) -> Self {
+        let mut mask = Self {
+            width,
+            height,
+            values,
+            bounds: None,
+            outline: Vec::new(),
+        };
+        mask.update();
+        mask
+    }
+    
+    pub fn from_shape(width: u32, height: u32, shape: &SelectionShape) -> Self {
+        let mut values = vec![0u8; (width * height) as usize];
+        match shape {
+            SelectionShape::Rectangle(rect) => {
+                let corners = vec![rect.left_top(), rect.right_top(), rect.right_bottom(), rect.left_bottom()];
+                rasterize_polygon(width, height, &corners, &mut values);
+            }
+            SelectionShape::Ellipse(rect) => {
+                let ellipse = Shape::Ellipse(*rect);
+                let area = ellipse.bounds();
+                let bounds = PixelRect::from_bounds(
+                    area.min.x.floor() as i64,
+                    area.min.y.floor() as i64,
+                    area.max.x.ceil() as i64,
+                    area.max.y.ceil() as i64,
+                    width,
+                    height,
+                );
+                if let Some(bounds) = bounds {
+                    for y in bounds.y..bounds.bottom() {
+                        for x in bounds.x..bounds.right() {
+                            let distance = ellipse.signed_distance(egui::pos2(x as f32 + 0.5, y as f32 + 0.5));
+                            values[(y * width + x) as usize] = (shapes::distance_coverage(distance) * 255.0).round() as u8;
+                        }
+                    }
+                }
+            }
+            SelectionShape::Polygon(points) => rasterize_polygon(width, height, points, &mut values),
+        }
+        Self::from_values(width, height, values)
+    }
+    
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    
+    pub fn get(&self, x: u32, y: u32) -> u8 {
+        self.values[(y * self.width + x) as usize]
+    }
+    
+    // Selection as a 0..=1 multiplier for paint coverage
+    pub fn strength(&self, x: u32, y: u32) -> f32 {
+        self.get(x, y) as f32 / 255.0
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.bounds.is_none()
+    }
+    
+    // Smallest rect holding every partly selected pixel
+    pub fn bounds(&self) -> Option<PixelRect> {
+        self.bounds
+    }
+    
+    pub fn outline(&self) -> &[[egui::Pos2; 2]] {
+        &self.outline
+    }
+    
+    // This selection combined with `other` of the same size
+    pub fn combine(&self, other: &SelectionMask, mode: SelectionMode) -> SelectionMask {
+        let values = match mode {
+            SelectionMode::Replace => other.values.clone(),
+            SelectionMode::Add => self.zip_values(other, |a, b| a.max(b)),
+            SelectionMode::Subtract => self.zip_values(other, |a, b| ((a as u32 * (255 - b as u32) + 127) / 255) as u8),
+            SelectionMode::Intersect => self.zip_values(other, |a, b| a.min(b)),
+        };
+        Self::from_values(self.width, self.height, values)
+    }
+    
+    fn zip_values<F: Fn(u8, u8) -> u8>(&self, other: &SelectionMask, f: F) -> Vec<u8> {
+        self.values.iter().zip(&other.values).map(|(a, b)| f(*a, *b)).collect()
+    }
+    
+    // Everything that isn't selected
+    pub fn inverted(&self) -> SelectionMask {
+        Self::from_values(self.width, self.height, self.values.iter().map(|value| 255 - value).collect())
+    }
+    
+    // This selection with a Select > Modify command applied. `radius` is
+    // the distance to grow, shrink or feather by, or the border width.
+    pub fn modified(&self, modify: SelectionModify, radius: f32) -> SelectionMask {
+        let radius = radius.max(0.0);
+        match modify {
+            SelectionModify::Grow => self.morphed(radius.round() as u32, false),
+            SelectionModify::Shrink => self.morphed(radius.round() as u32, true),
+            SelectionModify::Feather => self.feathered(radius),
+            SelectionModify::Border => {
+                let outer = self.morphed((radius / 2.0).ceil() as u32, false);
+                let inner = self.morphed((radius / 2.0).floor() as u32, true);
+                outer.combine(&inner, SelectionMode::Subtract)
+            }
+        }
+    }
+    
+    // This selection with the part inside `area` stretched onto `quad`, to
+    // follow pixels being transformed
+    pub fn warped(&self, area: PixelRect, quad: [egui::Pos2; 4]) -> SelectionMask {
+        let mut values = vec![0u8; self.values.len()];
+        let value_at = |x: i64, y: i64| {
+            if x < 0 || y < 0 || x >= area.width as i64 || y >= area.height as i64 {
+                0.0
+            } else {
+                self.get(area.x + x as u32, area.y + y as u32) as f32
+            }
+        };
+        transform::for_each_warped_pixel(quad, area.width, area.height, self.width, self.height, |x, y, sx, sy| {
+            // Bilinear between the four nearest pixel centres
+            let (fx, fy) = (sx - 0.5, sy - 0.5);
+            let (x0, y0) = (fx.floor(), fy.floor());
+            let (tx, ty) = (fx - x0, fy - y0);
+            let (x0, y0) = (x0 as i64, y0 as i64);
+            let top = value_at(x0, y0) * (1.0 - tx) + value_at(x0 + 1, y0) * tx;
+            let bottom = value_at(x0, y0 + 1) * (1.0 - tx) + value_at(x0 + 1, y0 + 1) * tx;
+            values[(y * self.width + x) as usize] = (top * (1.0 - ty) + bottom * ty).round() as u8;
+        });
+        Self::from_values(self.width, self.height, values)
+    }
+    
+    // Bounds widened by `margin` on every side, clipped to the canvas
+    fn padded_bounds(&self, margin: u32) -> Option<PixelRect> {
+        let bounds = self.bounds?;
+        let margin = margin as i64;
+        PixelRect::from_bounds(
+            bounds.x as i64 - margin,
+            bounds.y as i64 - margin,
+            bounds.right() as i64 + margin,
+            bounds.bottom() as i64 + margin,
+            self.width,
+            self.height,
+        )
+    }
+    
+    // Grows (dilates) or shrinks (erodes) the selection by a round brush of
+    // `radius`. Pixels past the canvas edge are ignored, so a selection
+    // touching the edge doesn't shrink away from it.
+    fn morphed(&self, radius: u32, shrink: bool) -> SelectionMask {
+        let area = match self.padded_bounds(radius) {
+            Some(area) if radius > 0 => area,
+            _ => return self.clone(),
+        };
+        
+        // Shrinking is growing the unselected part
+        let (width, height) = (area.width as usize, area.height as usize);
+        let mut source = Vec::with_capacity(width * height);
+        for y in area.y..area.bottom() {
+            for x in area.x..area.right() {
+                let value = self.get(x, y);
+                source.push(if shrink { 255 - value } else { value });
+            }
+        }
+        
+        // The brush is a stack of rows, each a running maximum over the
+        // row `dy` away
+        let mut grown = vec![0u8; width * height];
+        let mut row_max = vec![0u8; width];
+        let radius_squared = (radius * radius) as f32;
+        for dy in -(radius as i64)..=radius as i64 {
+            let half_width = (radius_squared - (dy * dy) as f32).sqrt().floor() as usize;
+            for y in 0..height {
+                let source_y = y as i64 + dy;
+                if source_y < 0 || source_y >= height as i64 {
+                    continue;
+                }
+                let row = &source[source_y as usize * width..][..width];
+                sliding_max(row, half_width, &mut row_max);
+                for (out, max) in grown[y * width..][..width].iter_mut().zip(&row_max) {
+                    *out = (*out).max(*max);
+                }
+            }
+        }
+        
+        let mut values = self.values.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let value = grown[y * width + x];
+                values[((area.y as usize + y) * self.width as usize) + area.x as usize + x] = if shrink { 255 - value } else { value };
+            }
+        }
+        Self::from_values(self.width, self.height, values)
+    }
+    
+    // Gaussian blur of the mask, with `radius` about two deviations
+    fn feathered(&self, radius: f32) -> SelectionMask {
+        let weights = filters::gaussian_kernel(radius / 2.0);
+        let area = match self.padded_bounds(weights.len() as u32 / 2) {
+            Some(area) if radius > 0.0 => area,
+            _ => return self.clone(),
+        };
+        
+        let mut pixels = Vec::with_capacity((area.width * area.height) as usize);
+        for y in area.y..area.bottom() {
+            for x in area.x..area.right() {
+                pixels.push([self.get(x, y) as f32, 0.0, 0.0, 0.0]);
+            }
+        }
+        let image = FloatImage { width: area.width, height: area.height, pixels };
+        // Clamping keeps edges that touch the canvas border sharp
+        let kernel = Kernel::Separable { horizontal: weights.clone(), vertical: weights };
+        let blurred = filters::convolve(&image, &kernel, EdgeMode::Clamp);
+        
+        let mut values = self.values.clone();
+        for y in 0..area.height {
+            for x in 0..area.width {
+                let value = blurred.get(x, y)[0].round().clamp(0.0, 255.0) as u8;
+                values[((area.y + y) * self.width + area.x + x) as usize] = value;
+            }
+        }
+        Self::from_values(self.width, self.height, values)
+    }
+    
+    // Recomputes the bounds and outline after the values change
+    fn update(&mut self) {
+        let (width, height) = (self.width, self.height);
+        let mut min = (u32::MAX, u32::MAX);
+        let mut max = (0, 0);
+        for y in 0..height {
+            for x in 0..width {
+                if self.get(x, y) > 0 {
+                    min = (min.0.min(x), min.1.min(y));
+                    max = (max.0.max(x), max.1.max(y));
+                }
+            }
+        }
+        self.bounds = if min.0 == u32::MAX {
+            None
+        } else {
+            Some(PixelRect::new(min.0, min.1, max.0 - min.0 + 1, max.1 - min.1 + 1))
+        };
+        
+        // Pixels at least half selected count as inside the outline
+        let inside = |x: i64, y: i64| {
+            x >= 0 && y >= 0 && x < width as i64 && y < height as i64 && self.get(x as u32, y as u32) >= 128
+        };
+        let mut outline = Vec::new();
+        
+        // Horizontal edges above each row
+        for y in 0..=height as i64 {
+            let mut run_start = None;
+            for x in 0..=width as i64 {
+                let edge = x < width as i64 && inside(x, y - 1) != inside(x, y);
+                match (edge, run_start) {
+                    (true, None) => run_start = Some(x),
+                    (false, Some(start)) => {
+                        outline.push([egui::pos2(start as f32, y as f32), egui::pos2(x as f32, y as f32)]);
+                        run_start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        
+        // Vertical edges left of each column
+        for x in 0..=width as i64 {
+            let mut run_start = None;
+            for y in 0..=height as i64 {
+                let edge = y < height as i64 && inside(x - 1, y) != inside(x, y);
+                match (edge, run_start) {
+                    (true, None) => run_start = Some(y),
+                    (false, Some(start)) => {
+                        outline.push([egui::pos2(x as f32, start as f32), egui::pos2(x as f32, y as f32)]);
+                        run_start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        
+        self.outline = outline;
+    }
+}
+
+// Selection strength at a pixel, where no selection means everything is
+// selected. Painting multiplies its coverage by this.
+pub fn strength(selection: Option<&SelectionMask>, x: u32, y: u32) -> f32 {
+    selection.map_or(1.0, |selection| selection.strength(x, y))
+}
+
+// `after` where the pixel is selected, `before` where it isn't, and a mix
+// of the two on soft edges. Used to clip whole-layer operations.
+pub fn clip_pixel(selection: Option<&SelectionMask>, x: u32, y: u32, before: Rgba<u8>, after: Rgba<u8>) -> Rgba<u8> {
+    let strength = strength(selection, x, y);
+    if strength >= 1.0 {
+        after
+    } else if strength <= 0.0 {
+        before
+    } else {
+        paint::mix(before, after, strength)
+    }
+}
+
+// Largest value within `half_width` of each entry of `row`, ignoring
+// positions past either end
+fn sliding_max(row: &[u8], half_width: usize, out: &mut [u8]) {
+    // Indices of decreasing values, the front being the window's maximum
+    let mut window: VecDeque<usize> = VecDeque::new();
+    for i in 0..row.len() + half_width {
+        if i < row.len() {
+            while window.back().is_some_and(|&j| row[j] <= row[i]) {
+                window.pop_back();
+            }
+            window.push_back(i);
+        }
+        if i >= half_width {
+            let center = i - half_width;
+            while window.front().is_some_and(|&j| j + half_width < center) {
+                window.pop_front();
+            }
+            out[center] = row[window[0]];
+        }
+    }
+}
+
+// Coverage of a closed polygon, even-odd, with vertical supersampling and
+// exact horizontal coverage at span ends
+fn rasterize_polygon(width: u32, height: u32, points: &[egui::Pos2], values: &mut [u8]) {
+    if points.len() < 3 {
+        return;
+    }
+    
+    let min_y = points.iter().map(|p| p.y).fold(f32::INFINITY, f32::min).floor().max(0.0) as u32;
+    let max_y = (points.iter().map(|p| p.y).fold(f32::NEG_INFINITY, f32::max).ceil().max(0.0) as u32).min(height);
+    let mut row = vec![0.0f32; width as usize];
+    let mut crossings = Vec::new();
+    
+    for y in min_y..max_y {
+        row.iter_mut().for_each(|c| *c = 0.0);
+        
+        for sub in 0..POLYGON_SUBSAMPLES {
+            let sample_y = y as f32 + (sub as f32 + 0.5) / POLYGON_SUBSAMPLES as f32;
+            crossings.clear();
+            for (i, a) in points.iter().enumerate() {
+                let b = points[(i + 1) % points.len()];
+                if (a.y <= sample_y) != (b.y <= sample_y) {
+                    crossings.push(a.x + (sample_y - a.y) / (b.y - a.y) * (b.x - a.x));
+                }
+            }
+            crossings.sort_by(f32::total_cmp);
+            
+            for span in crossings.chunks_exact(2) {
+                let (start, end) = (span[0].clamp(0.0, width as f32), span[1].clamp(0.0, width as f32));
+                if start >= end {
+                    continue;
+                }
+                let weight = 1.0 / POLYGON_SUBSAMPLES as f32;
+                let (first, last) = (start.floor() as usize, (end.ceil() as usize).min(width as usize));
+                for (x, coverage) in row.iter_mut().enumerate().take(last).skip(first) {
+                    let covered = (end.min(x as f32 + 1.0) - start.max(x as f32)).max(0.0);
+                    *coverage += covered * weight;
+                }
+            }
+        }
+        
+        let offset = (y * width) as usize;
+        for (x, coverage) in row.iter().enumerate() {
+            values[offset + x] = (coverage.min(1.0) * 255.0).round() as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn row(values: &[u8]) -> SelectionMask {
+        SelectionMask::from_values(values.len() as u32, 1, values.to_vec())
+    }
+    
+    fn values(mask: &SelectionMask) -> Vec<u8> {
+        (0..mask.height()).flat_map(|y| (0..mask.width()).map(move |x| (x, y))).map(|(x, y)| mask.get(x, y)).collect()
+    }
+    
+    #[test]
+    fn combine_in_every_mode() {
+        let current = row(&[0, 100, 200, 255, 255]);
+        let new = row(&[255, 100, 50, 0, 255]);
+        let expected: [(SelectionMode, [u8; 5]); 4] = [
+            (SelectionMode::Replace, [255, 100, 50, 0, 255]),
+            (SelectionMode::Add, [255, 100, 200, 255, 255]),
+            // a * (255 - b) / 255, rounded: 100 * 155 / 255 = 60.8, 200 * 205 / 255 = 160.8
+            (SelectionMode::Subtract, [0, 61, 161, 255, 0]),
+            (SelectionMode::Intersect, [0, 100, 50, 0, 255]),
+        ];
+        for (mode, expected) in expected {
+            assert_eq!(values(&current.combine(&new, mode)), expected, "{:?}", mode);
+        }
+    }
+    
+    #[test]
+    fn inverted_swaps_selected_and_unselected() {
+        let mask = row(&[0, 1, 128, 255]);
+        assert_eq!(values(&mask.inverted()), [255, 254, 127, 0]);
+        assert_eq!(values(&mask.inverted().inverted()), values(&mask));
+        assert!(SelectionMask::all(3, 2).inverted().is_empty());
+    }
+    
+    #[test]
+    fn bounds_cover_every_partly_selected_pixel() {
+        let mask = SelectionMask::from_fn(8, 6, |x, y| match (x, y) {
+            (2, 1) => 255,
+            (5, 4) => 1,
+            _ => 0,
+        });
+        assert_eq!(mask.bounds(), Some(PixelRect::new(2, 1, 4, 4)));
+        assert!(!mask.is_empty());
+        
+        let empty = SelectionMask::from_fn(8, 6, |_, _| 0);
+        assert_eq!(empty.bounds(), None);
+        assert!(empty.is_empty());
+        assert!(empty.outline().is_empty());
+    }
+    
+    #[test]
+    fn triangle_has_anti_aliased_diagonal() {
+        let shape = SelectionShape::Polygon(vec![egui::pos2(0.0, 0.0), egui::pos2(4.0, 0.0), egui::pos2(0.0, 4.0)]);
+        let mask = SelectionMask::from_shape(4, 4, &shape);
+        for y in 0..4 {
+            for x in 0..4 {
+                // Pixels on the diagonal are cut exactly in half
+                let expected = match x + y {
+                    0..=2 => 255,
+                    3 => 128,
+                    _ => 0,
+                };
+                assert_eq!(mask.get(x, y), expected, "({}, {})", x, y);
+            }
+        }
+    }
+    
+    #[test]
+    fn concave_lasso_leaves_its_notch_unselected() {
+        // An L shape: the top-right 2x2 notch is outside the polygon
+        let points = [(0.0, 0.0), (4.0, 0.0), (4.0, 2.0), (2.0, 2.0), (2.0, 4.0), (0.0, 4.0)];
+        let shape = SelectionShape::Polygon(points.iter().map(|&(x, y)| egui::pos2(x, y)).collect());
+        let mask = SelectionMask::from_shape(5, 5, &shape);
+        for y in 0..5 {
+            for x in 0..5 {
+                let inside = (x < 4 && y < 2) || (x < 2 && y < 4);
+                assert_eq!(mask.get(x, y), if inside { 255 } else { 0 }, "({}, {})", x, y);
+            }
+        }
+        assert_eq!(mask.bounds(), Some(PixelRect::new(0, 0, 4, 4)));
+    }
+    
+    #[test]
+    fn rectangle_edges_are_partly_selected() {
+        let shape = SelectionShape::Rectangle(egui::Rect::from_min_max(egui::pos2(0.5, 0.0), egui::pos2(2.5, 1.0)));
+        let mask = SelectionMask::from_shape(4, 1, &shape);
+        assert_eq!(values(&mask), [128, 255, 128, 0]);
+    }
+    
+    // Selected (255) pixels as rows of '#', everything else '.'
+    fn picture(mask: &SelectionMask) -> Vec<String> {
+        (0..mask.height())
+            .map(|y| (0..mask.width()).map(|x| if mask.get(x, y) == 255 { '#' } else { '.' }).collect())
+            .collect()
+    }
+    
+    fn square(size: u32, start: u32, end: u32) -> SelectionMask {
+        SelectionMask::from_fn(size, size, |x, y| if (start..end).contains(&x) && (start..end).contains(&y) { 255 } else { 0 })
+    }
+    
+    #[test]
+    fn color_range_fades_out_between_half_and_full_fuzziness() {
+        // Greys at distance value / 255 from black
+        let greys = [0u8, 40, 64, 77, 110, 255];
+        let mask = SelectionMask::from_color_range(greys.len() as u32, 1, Rgba([0, 0, 0, 255]), 0.4, |x, _| {
+            let v = greys[x as usize];
+            Rgba([v, v, v, 255])
+        });
+        assert_eq!((mask.get(0, 0), mask.get(1, 0)), (255, 255));
+        // (0.4 - d) / 0.2 of the way: 64 is at 0.75 and 77 at 0.49
+        assert!(mask.get(2, 0).abs_diff(190) <= 1, "{}", mask.get(2, 0));
+        assert!(mask.get(3, 0).abs_diff(125) <= 1, "{}", mask.get(3, 0));
+        assert_eq!((mask.get(4, 0), mask.get(5, 0)), (0, 0));
+        
+        let exact = SelectionMask::from_color_range(greys.len() as u32, 1, Rgba([40, 40, 40, 255]), 0.0, |x, _| {
+            let v = greys[x as usize];
+            Rgba([v, v, v, 255])
+        });
+        assert_eq!(values(&exact), [0, 255, 0, 0, 0, 0]);
+    }
+    
+    #[test]
+    fn sliding_max_spreads_each_value_half_width_each_way() {
+        let row = [0, 0, 9, 0, 0, 0, 5, 0];
+        let mut out = [0; 8];
+        sliding_max(&row, 0, &mut out);
+        assert_eq!(out, row);
+        sliding_max(&row, 1, &mut out);
+        assert_eq!(out, [0, 9, 9, 9, 0, 5, 5, 5]);
+        sliding_max(&row, 2, &mut out);
+        assert_eq!(out, [9, 9, 9, 9, 9, 5, 5, 5]);
+        sliding_max(&row, 10, &mut out);
+        assert_eq!(out, [9; 8]);
+    }
+    
+    #[test]
+    fn grow_by_a_round_brush_and_shrink_back() {
+        let seed = SelectionMask::from_fn(7, 7, |x, y| if (x, y) == (3, 3) { 255 } else { 0 });
+        let grown = seed.modified(SelectionModify::Grow, 2.0);
+        assert_eq!(
+            picture(&grown),
+            [".......", "...#...", "..###..", ".#####.", "..###..", "...#...", "......."]
+        );
+        
+        assert_eq!(grown.modified(SelectionModify::Shrink, 2.0), seed);
+        // Shrinking the unselected part grows the selected part
+        assert_eq!(seed.inverted().modified(SelectionModify::Shrink, 2.0), grown.inverted());
+        
+        let shrunk = square(9, 2, 7).modified(SelectionModify::Shrink, 1.0);
+        assert_eq!(shrunk, square(9, 3, 6));
+    }
+    
+    #[test]
+    fn shrink_keeps_edges_on_the_canvas_border() {
+        let shrunk = square(6, 0, 4).modified(SelectionModify::Shrink, 1.0);
+        assert_eq!(shrunk, square(6, 0, 3));
+    }
+    
+    #[test]
+    fn feather_softens_the_edge_symmetrically() {
+        let feathered = square(31, 8, 23).modified(SelectionModify::Feather, 4.0);
+        let row: Vec<u8> = (0..31).map(|x| feathered.get(x, 15)).collect();
+        
+        assert_eq!(row[15], 255);
+        assert!(row[15..].windows(2).all(|pair| pair[0] >= pair[1]), "{:?}", row);
+        assert!(row[22] > 128 && row[22] < 255, "{:?}", row);
+        assert!(row[23] > 0 && row[23] < 128, "{:?}", row);
+        // Half the mask leaks out past the edge and half is lost inside it
+        assert!((row[22] as i32 + row[23] as i32 - 255).abs() <= 2, "{:?}", row);
+        assert_eq!(&row[..16], row[15..].iter().rev().copied().collect::<Vec<_>>());
+        assert!(row[26] > 0);
+        assert_eq!(feathered.get(0, 0), 0);
+    }
+    
+    #[test]
+    fn border_is_a_ring_of_the_given_width() {
+        let mask = square(21, 5, 16);
+        for width in [2u32, 3, 4] {
+            let border = mask.modified(SelectionModify::Border, width as f32);
+            let row: Vec<u32> = (0..21).filter(|&x| border.get(x, 10) == 255).collect();
+            assert!((0..21).all(|x| [0, 255].contains(&border.get(x, 10))));
+            
+            // Half outside the edge (rounded up) and half inside
+            let outer = width.div_ceil(2);
+            let left: Vec<u32> = (5 - outer..5 - outer + width).collect();
+            let right: Vec<u32> = (16 + outer - width..16 + outer).collect();
+            assert_eq!(row, [left, right].concat(), "width {}", width);
+            assert_eq!(border.get(10, 10), 0);
+        }
+    }
+    
+    #[test]
+    fn warped_selection_follows_the_moved_pixels() {
+        let mask = SelectionMask::from_fn(10, 10, |x, y| if (2..5).contains(&x) && (2..5).contains(&y) { 255 } else { 0 });
+        let area = mask.bounds().unwrap();
+        let moved = [egui::pos2(6.0, 7.0), egui::pos2(9.0, 7.0), egui::pos2(9.0, 10.0), egui::pos2(6.0, 10.0)];
+        let warped = mask.warped(area, moved);
+        assert_eq!(warped.bounds(), Some(PixelRect::new(6, 7, 3, 3)));
+        assert_eq!(warped, SelectionMask::from_fn(10, 10, |x, y| if (6..9).contains(&x) && (7..10).contains(&y) { 255 } else { 0 }));
+        
+        // Doubling the size keeps the inside fully selected, with a soft
+        // bilinear edge either side of the quad's border
+        let scaled = mask.warped(area, [egui::pos2(1.0, 1.0), egui::pos2(7.0, 1.0), egui::pos2(7.0, 7.0), egui::pos2(1.0, 7.0)]);
+        let row: Vec<u8> = (0..10).map(|x| scaled.get(x, 4)).collect();
+        assert_eq!(row, [64, 191, 255, 255, 255, 255, 191, 64, 0, 0]);
+        assert_eq!(scaled.bounds(), Some(PixelRect::new(0, 0, 8, 8)));
+    }
+}
@@ -0,0 +1,82 @@
+use eframe::egui;
+use image::Rgba;
+
+// Geometric shapes in image coordinates, rasterised with anti-aliased
+// edges by `ImageData::draw_shape`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Rectangle(egui::Rect),
+    // Ellipse inscribed in the rect
+    Ellipse(egui::Rect),
+    Line { from: egui::Pos2, to: egui::Pos2, width: f32 },
+}
+
+impl Shape {
+    // Signed distance from `pos` to the shape outline, negative inside
+    pub fn signed_distance(&self, pos: egui::Pos2) -> f32 {
+        match self {
+            Shape::Rectangle(rect) => {
+                let q = (pos - rect.center()).abs() - rect.size() * 0.5;
+                let outside = egui::vec2(q.x.max(0.0), q.y.max(0.0)).length();
+                outside + q.x.max(q.y).min(0.0)
+            }
+            Shape::Ellipse(rect) => {
+                let radius = rect.size() * 0.5;
+                if radius.x <= 0.0 || radius.y <= 0.0 {
+                    return f32::INFINITY;
+                }
+                
+                // First order approximation: the implicit function divided by
+                // its gradient length, exact for circles
+                let d = pos - rect.center();
+                let k = egui::vec2(d.x / radius.x, d.y / radius.y).length();
+                if k == 0.0 {
+                    return -radius.x.min(radius.y);
+                }
+                let gradient = egui::vec2(d.x / (radius.x * radius.x), d.y / (radius.y * radius.y)).length() / k;
+                (k - 1.0) / gradient
+            }
+            Shape::Line { from, to, width } => {
+                let segment = *to - *from;
+                let length_sq = segment.length_sq();
+                let t = if length_sq > 0.0 {
+                    ((pos - *from).dot(segment) / length_sq).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                (pos - (*from + segment * t)).length() - width * 0.5
+            }
+        }
+    }
+    
+    // Area the shape can touch, including its anti-aliased edge
+    pub fn bounds(&self) -> egui::Rect {
+        match self {
+            Shape::Rectangle(rect) | Shape::Ellipse(rect) => rect.expand(1.0),
+            Shape::Line { from, to, width } => egui::Rect::from_two_pos(*from, *to).expand(width * 0.5 + 1.0),
+        }
+    }
+}
+
+// Pixel coverage for a signed distance measured at the pixel centre
+pub fn distance_coverage(distance: f32) -> f32 {
+    (0.5 - distance).clamp(0.0, 1.0)
+}
+
+// Combines fill and border paint covering `fill_weight` and `border_weight`
+// of a pixel into one colour and the total coverage to composite it with
+pub fn mix_paint(fill: Rgba<u8>, fill_weight: f32, border: Rgba<u8>, border_weight: f32) -> (Rgba<u8>, f32) {
+    let fill_alpha = fill[3] as f32 / 255.0 * fill_weight;
+    let border_alpha = border[3] as f32 / 255.0 * border_weight;
+    let alpha = fill_alpha + border_alpha;
+    if alpha <= 0.0 {
+        return (Rgba([0, 0, 0, 0]), 0.0);
+    }
+    
+    let mut color = [0u8, 0, 0, 255];
+    for i in 0..3 {
+        let c = (fill[i] as f32 * fill_alpha + border[i] as f32 * border_alpha) / alpha;
+        color[i] = c.round().clamp(0.0, 255.0) as u8;
+    }
+    (Rgba(color), alpha.min(1.0))
+}
@@ -0,0 +1,325 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+use anyhow::{anyhow, Result};
+use eframe::egui;
+use image::Rgba;
+use serde::{Deserialize, Serialize};
+use crate::image_ops::paint;
+use crate::image_ops::tiles::TiledImage;
+
+// Bundled with egui, used when no font file is chosen or it can't be loaded
+const DEFAULT_FONT: &str = "Ubuntu-Light";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+impl TextAlign {
+    pub const ALL: [TextAlign; 3] = [TextAlign::Left, TextAlign::Center, TextAlign::Right];
+    
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextAlign::Left => "Left",
+            TextAlign::Center => "Center",
+            TextAlign::Right => "Right",
+        }
+    }
+}
+
+// Font file contents shared between text layers and their history copies.
+// Compared by identity, as the bytes never change once loaded.
+#[derive(Clone)]
+pub struct FontData(Arc<Vec<u8>>);
+
+impl FontData {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = std::fs::read(path.as_ref())?;
+        FontRef::try_from_slice(&bytes)
+            .map_err(|_| anyhow!("{} is not a TrueType or OpenType font", path.as_ref().display()))?;
+        Ok(Self(Arc::new(bytes)))
+    }
+}
+
+impl PartialEq for FontData {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for FontData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FontData({} bytes)", self.0.len())
+    }
+}
+
+// Contents and style of a text layer. The layer's pixels are rendered from
+// this and can be regenerated at any time until the layer is rasterized.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TextLayer {
+    pub text: String,
+    // Anchor of the first line: its top-left, top-centre or top-right
+    // corner depending on the alignment
+    pub x: f32,
+    pub y: f32,
+    pub size: f32,
+    pub color: [u8; 4],
+    pub align: TextAlign,
+    // Multiplier for the font's natural line height
+    pub line_spacing: f32,
+    pub font_path: Option<PathBuf>,
+    #[serde(skip)]
+    pub font: Option<FontData>,
+}
+
+impl Default for TextLayer {
+    fn default() -> Self {
+        Self {
+            text: "Text".to_string(),
+            x: 0.0,
+            y: 0.0,
+            size: 32.0,
+            color: [0, 0, 0, 255],
+            align: TextAlign::Left,
+            line_spacing: 1.0,
+            font_path: None,
+            font: None,
+        }
+    }
+}
+
+impl TextLayer {
+    pub fn set_font<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.font = Some(FontData::load(&path)?);
+        self.font_path = Some(path.as_ref().to_path_buf());
+        Ok(())
+    }
+    
+    // Loads the font named by `font_path` again, e.g. after opening a project.
+    // Falls back to the default font if the file is gone.
+    pub fn reload_font(&mut self) {
+        if let Some(path) = &self.font_path {
+            match FontData::load(path) {
+                Ok(font) => self.font = Some(font),
+                Err(e) => log::warn!("Using the default font instead of {}: {}", path.display(), e),
+            }
+        }
+    }
+    
+    pub fn font_name(&self) -> String {
+        match (&self.font, &self.font_path) {
+            (Some(_), Some(path)) => path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default(),
+            _ => DEFAULT_FONT.to_string(),
+        }
+    }
+    
+    // Layer name derived from the first line of text
+    pub fn layer_name(&self) -> String {
+        let first_line = self.text.lines().next().unwrap_or("").trim();
+        let mut name: String = first_line.chars().take(24).collect();
+        if name.is_empty() {
+            name = "Text".to_string();
+        }
+        name
+    }
+    
+    fn with_font<R>(&self, f: impl FnOnce(&FontRef) -> R) -> R {
+        if let Some(FontData(bytes)) = &self.font {
+            if let Ok(font) = FontRef::try_from_slice(bytes) {
+                return f(&font);
+            }
+        }
+        
+        let definitions = egui::FontDefinitions::default();
+        let data = &definitions.font_data[DEFAULT_FONT];
+        let font = FontRef::try_from_slice_and_index(&data.font, data.index)
+            .expect("egui's bundled font should always parse");
+        f(&font)
+    }
+    
+    // Lays out each line, calling `place` with the line and the position of
+    // its left end on the baseline. Returns the area the text occupies.
+    fn layout<F: Font>(&self, font: &F, mut place: impl FnMut(&str, f32, f32)) -> egui::Rect {
+        let scaled = font.as_scaled(PxScale::from(self.size.max(1.0)));
+        let line_height = (scaled.ascent() - scaled.descent() + scaled.line_gap()) * self.line_spacing;
+        
+        let mut bounds = egui::Rect::NOTHING;
+        for (i, line) in self.text.split('\n').enumerate() {
+            let width = line_width(&scaled, line);
+            let left = match self.align {
+                TextAlign::Left => self.x,
+                TextAlign::Center => self.x - width / 2.0,
+                TextAlign::Right => self.x - width,
+            };
+            let top = self.y + i as f32 * line_height;
+            place(line, left, top + scaled.ascent());
+            
+            bounds = bounds.union(egui::Rect::from_min_size(
+                egui::pos2(left, top),
+                egui::vec2(width, scaled.ascent() - scaled.descent()),
+            ));
+        }
+        bounds
+    }
+    
+    // Area covered by the text in image coordinates
+    pub fn bounds(&self) -> egui::Rect {
+        self.with_font(|font| self.layout(font, |_, _, _| {}))
+    }
+    
+    // Renders the text onto a transparent `width`x`height` layer. Only the
+    // font rasteriser is involved, so the output is the same on every machine.
+    pub fn render(&self, width: u32, height: u32) -> TiledImage {
+        let mut image = TiledImage::new(width, height);
+        let color = Rgba(self.color);
+        
+        self.with_font(|font| {
+            let scale = PxScale::from(self.size.max(1.0));
+            let scaled = font.as_scaled(scale);
+            
+            self.layout(font, |line, left, baseline| {
+                let mut caret = left;
+                let mut previous = None;
+                for c in line.chars() {
+                    let id = scaled.glyph_id(c);
+                    if let Some(previous) = previous {
+                        caret += scaled.kern(previous, id);
+                    }
+                    previous = Some(id);
+                    
+                    let glyph = id.with_scale_and_position(scale, point(caret, baseline));
+                    caret += scaled.h_advance(id);
+                    
+                    let outline = match font.outline_glyph(glyph) {
+                        Some(outline) => outline,
+                        None => continue,
+                    };
+                    let glyph_bounds = outline.px_bounds();
+                    outline.draw(|gx, gy, coverage| {
+                        let x = glyph_bounds.min.x as i64 + gx as i64;
+                        let y = glyph_bounds.min.y as i64 + gy as i64;
+                        if x >= 0 && y >= 0 && x < width as i64 && y < height as i64 {
+                            let (x, y) = (x as u32, y as u32);
+                            let pixel = paint::source_over(image.get_pixel(x, y), color, coverage);
+                            image.put_pixel(x, y, pixel);
+                        }
+                    });
+                }
+            });
+        });
+        
+        image
+    }
+}
+
+fn line_width<F: Font, SF: ScaleFont<F>>(scaled: &SF, line: &str) -> f32 {
+    let mut width = 0.0;
+    let mut previous = None;
+    for c in line.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            width += scaled.kern(previous, id);
+        }
+        width += scaled.h_advance(id);
+        previous = Some(id);
+    }
+    width
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    
+    // Set to write the current output as the new references
+    const UPDATE_ENV: &str = "PAINTMATE_UPDATE_REFERENCES";
+    
+    fn reference_path(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("test_images").join("text").join(format!("{name}.png"))
+    }
+    
+    // Renders `text` and compares it with its reference PNG, allowing for
+    // rounding differences of one level per channel
+    fn check_reference(name: &str, text: &TextLayer, width: u32, height: u32) {
+        let rendered = text.render(width, height).to_image();
+        let path = reference_path(name);
+        
+        if std::env::var_os(UPDATE_ENV).is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            rendered.save(&path).unwrap();
+            return;
+        }
+        
+        let reference = image::open(&path)
+            .unwrap_or_else(|e| panic!("{}: {e} (run with {UPDATE_ENV}=1 to create it)", path.display()))
+            .to_rgba8();
+        assert_eq!(rendered.dimensions(), reference.dimensions(), "{name}");
+        
+        let mismatched = rendered
+            .pixels()
+            .zip(reference.pixels())
+            .filter(|(a, b)| a.0.iter().zip(b.0.iter()).any(|(a, b)| a.abs_diff(*b) > 1))
+            .count();
+        assert_eq!(mismatched, 0, "{name}: {mismatched} pixels differ from {}", path.display());
+    }
+    
+    fn text(content: &str, x: f32, align: TextAlign) -> TextLayer {
+        TextLayer {
+            text: content.to_string(),
+            x,
+            y: 4.0,
+            size: 24.0,
+            align,
+            ..TextLayer::default()
+        }
+    }
+    
+    #[test]
+    fn renders_left_aligned_line() {
+        check_reference("left", &text("PaintMate", 4.0, TextAlign::Left), 128, 40);
+    }
+    
+    #[test]
+    fn renders_centered_lines() {
+        check_reference("center", &text("Hello\nworld!", 64.0, TextAlign::Center), 128, 72);
+    }
+    
+    #[test]
+    fn renders_right_aligned_lines_with_spacing() {
+        let mut layer = text("AV Wa\nTo", 124.0, TextAlign::Right);
+        layer.line_spacing = 1.5;
+        check_reference("right_spaced", &layer, 128, 90);
+    }
+    
+    #[test]
+    fn renders_translucent_colour() {
+        let mut layer = text("Ag", 4.0, TextAlign::Left);
+        layer.size = 48.0;
+        layer.color = [200, 30, 60, 128];
+        check_reference("translucent", &layer, 72, 64);
+    }
+    
+    #[test]
+    fn text_outside_the_layer_is_clipped() {
+        let layer = text("Clipped", -20.0, TextAlign::Left);
+        let rendered = layer.render(32, 16);
+        assert_eq!(rendered.width(), 32);
+        assert!(rendered.content_bounds().is_some());
+    }
+    
+    #[test]
+    fn bounds_follow_alignment() {
+        let left = text("Wide text", 50.0, TextAlign::Left).bounds();
+        let center = text("Wide text", 50.0, TextAlign::Center).bounds();
+        let right = text("Wide text", 50.0, TextAlign::Right).bounds();
+        
+        assert_eq!(left.min.x, 50.0);
+        assert!((center.center().x - 50.0).abs() < 1e-3);
+        assert!((right.max.x - 50.0).abs() < 1e-3);
+        assert!((left.width() - right.width()).abs() < 1e-3);
+    }
+}
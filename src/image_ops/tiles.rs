@@ -0,0 +1,390 @@
+use std::sync::Arc;
+use image::{ImageBuffer, Rgba};
+use crate::image_ops::RgbaImage;
+use crate::image_ops::dirty::PixelRect;
+
+pub const TILE_SIZE: u32 = 64;
+pub const TRANSPARENT: Rgba<u8> = Rgba([0, 0, 0, 0]);
+
+pub const TILE_BYTES: usize = (TILE_SIZE * TILE_SIZE * 4) as usize;
+
+// Sparse layer storage: the canvas is split into TILE_SIZE squares and only
+// tiles that have been painted on are allocated. Tiles are reference counted
+// and copied on write, so cloning a layer (e.g. for history) shares every
+// tile until one side modifies it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TiledImage {
+    width: u32,
+    height: u32,
+    tiles_x: u32,
+    tiles_y: u32,
+    tiles: Vec<Option<Arc<RgbaImage>>>,
+}
+
+impl TiledImage {
+    pub fn new(width: u32, height: u32) -> Self {
+        let tiles_x = width.div_ceil(TILE_SIZE);
+        let tiles_y = height.div_ceil(TILE_SIZE);
+        
+        Self {
+            width,
+            height,
+            tiles_x,
+            tiles_y,
+            tiles: vec![None; (tiles_x * tiles_y) as usize],
+        }
+    }
+    
+    pub fn from_image(image: &RgbaImage) -> Self {
+        let mut tiled = Self::new(image.width(), image.height());
+        
+        for ty in 0..tiled.tiles_y {
+            for tx in 0..tiled.tiles_x {
+                let rect = tiled.tile_rect(tx, ty);
+                let mut tile = ImageBuffer::from_pixel(TILE_SIZE, TILE_SIZE, TRANSPARENT);
+                let mut is_empty = true;
+                
+                for y in 0..rect.height {
+                    for x in 0..rect.width {
+                        let pixel = *image.get_pixel(rect.x + x, rect.y + y);
+                        if pixel[3] != 0 {
+                            is_empty = false;
+                        }
+                        tile.put_pixel(x, y, pixel);
+                    }
+                }
+                
+                if !is_empty {
+                    let index = tiled.tile_index(tx, ty);
+                    tiled.tiles[index] = Some(Arc::new(tile));
+                }
+            }
+        }
+        
+        tiled
+    }
+    
+    pub fn to_image(&self) -> RgbaImage {
+        let mut image = ImageBuffer::from_pixel(self.width, self.height, TRANSPARENT);
+        
+        for ty in 0..self.tiles_y {
+            for tx in 0..self.tiles_x {
+                if let Some(tile) = self.tile(tx, ty) {
+                    let rect = self.tile_rect(tx, ty);
+                    for y in 0..rect.height {
+                        for x in 0..rect.width {
+                            image.put_pixel(rect.x + x, rect.y + y, *tile.get_pixel(x, y));
+                        }
+                    }
+                }
+            }
+        }
+        
+        image
+    }
+    
+    // Copy on a `width`x`height` canvas with every pixel moved by the offset.
+    // Pixels that end up outside the new canvas are dropped. Used for
+    // cropping and canvas resizing.
+    pub fn resize_canvas(&self, width: u32, height: u32, offset_x: i64, offset_y: i64) -> Self {
+        self.remap(width, height, |x, y| {
+            let dst_x = x as i64 + offset_x;
+            let dst_y = y as i64 + offset_y;
+            if dst_x >= 0 && dst_y >= 0 && dst_x < width as i64 && dst_y < height as i64 {
+                Some((dst_x as u32, dst_y as u32))
+            } else {
+                None
+            }
+        })
+    }
+    
+    // Copy turned clockwise by `turns` quarter turns. Odd turns swap width and height.
+    pub fn rotate_quarter_turns(&self, turns: u32) -> Self {
+        let (width, height) = (self.width, self.height);
+        match turns % 4 {
+            1 => self.remap(height, width, |x, y| Some((height - 1 - y, x))),
+            2 => self.remap(width, height, |x, y| Some((width - 1 - x, height - 1 - y))),
+            3 => self.remap(height, width, |x, y| Some((y, width - 1 - x))),
+            _ => self.clone(),
+        }
+    }
+    
+    // Mirrored copy, left to right when `horizontal` is set, otherwise top to bottom
+    pub fn flip(&self, horizontal: bool) -> Self {
+        let (width, height) = (self.width, self.height);
+        if horizontal {
+            self.remap(width, height, |x, y| Some((width - 1 - x, y)))
+        } else {
+            self.remap(width, height, |x, y| Some((x, height - 1 - y)))
+        }
+    }
+    
+    // Copy with `f` applied to every pixel of the allocated tiles.
+    // Unallocated tiles stay transparent.
+    pub fn map_pixels<F: Fn(Rgba<u8>) -> Rgba<u8>>(&self, f: F) -> Self {
+        let mut mapped = self.clone();
+        for tile in mapped.tiles.iter_mut().flatten() {
+            let tile = Arc::make_mut(tile);
+            for pixel in tile.pixels_mut() {
+                *pixel = f(*pixel);
+            }
+        }
+        mapped
+    }
+    
+    // Copy on a `width`x`height` canvas with each painted pixel moved to
+    // where `map` puts it. Pixels mapped to None are dropped.
+    fn remap<F>(&self, width: u32, height: u32, map: F) -> Self
+    where
+        F: Fn(u32, u32) -> Option<(u32, u32)>,
+    {
+        let mut remapped = Self::new(width, height);
+        
+        for ty in 0..self.tiles_y {
+            for tx in 0..self.tiles_x {
+                let tile = match self.tile(tx, ty) {
+                    Some(tile) => tile,
+                    None => continue,
+                };
+                let rect = self.tile_rect(tx, ty);
+                
+                for y in 0..rect.height {
+                    for x in 0..rect.width {
+                        if let Some((dst_x, dst_y)) = map(rect.x + x, rect.y + y) {
+                            remapped.put_pixel(dst_x, dst_y, *tile.get_pixel(x, y));
+                        }
+                    }
+                }
+            }
+        }
+        
+        remapped
+    }
+    
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    
+    pub fn get_pixel(&self, x: u32, y: u32) -> Rgba<u8> {
+        match self.tile(x / TILE_SIZE, y / TILE_SIZE) {
+            Some(tile) => *tile.get_pixel(x % TILE_SIZE, y % TILE_SIZE),
+            None => TRANSPARENT,
+        }
+    }
+    
+    pub fn put_pixel(&mut self, x: u32, y: u32, pixel: Rgba<u8>) {
+        let index = self.tile_index(x / TILE_SIZE, y / TILE_SIZE);
+        
+        // Writing transparency into an unallocated tile is a no-op
+        if self.tiles[index].is_none() && pixel[3] == 0 {
+            return;
+        }
+        
+        let tile = self.tiles[index]
+            .get_or_insert_with(|| Arc::new(ImageBuffer::from_pixel(TILE_SIZE, TILE_SIZE, TRANSPARENT)));
+        Arc::make_mut(tile).put_pixel(x % TILE_SIZE, y % TILE_SIZE, pixel);
+    }
+    
+    pub fn tile(&self, tx: u32, ty: u32) -> Option<&RgbaImage> {
+        self.tiles[self.tile_index(tx, ty)].as_deref()
+    }
+    
+    // Canvas-space area covered by a tile, clipped to the image bounds
+    pub fn tile_rect(&self, tx: u32, ty: u32) -> PixelRect {
+        let x = tx * TILE_SIZE;
+        let y = ty * TILE_SIZE;
+        PixelRect::new(x, y, TILE_SIZE.min(self.width - x), TILE_SIZE.min(self.height - y))
+    }
+    
+    // Coordinates of every tile that overlaps `rect`
+    pub fn tiles_in(&self, rect: PixelRect) -> impl Iterator<Item = (u32, u32)> {
+        let (min_tx, min_ty, max_tx, max_ty) = if rect.is_empty() {
+            (0, 0, 0, 0)
+        } else {
+            (
+                rect.x / TILE_SIZE,
+                rect.y / TILE_SIZE,
+                rect.right().div_ceil(TILE_SIZE).min(self.tiles_x),
+                rect.bottom().div_ceil(TILE_SIZE).min(self.tiles_y),
+            )
+        };
+        
+        (min_ty..max_ty).flat_map(move |ty| (min_tx..max_tx).map(move |tx| (tx, ty)))
+    }
+    
+    pub fn tile_count(&self) -> usize {
+        self.tiles.len()
+    }
+    
+    // Shared handle to a tile slot, used by history to keep tiles alive cheaply
+    pub fn tile_slot(&self, index: usize) -> Option<Arc<RgbaImage>> {
+        self.tiles[index].clone()
+    }
+    
+    pub fn set_tile_slot(&mut self, index: usize, tile: Option<Arc<RgbaImage>>) {
+        self.tiles[index] = tile;
+    }
+    
+    // True when both slots hold the same allocation (or are both empty)
+    pub fn same_tile(&self, other: &TiledImage, index: usize) -> bool {
+        match (&self.tiles[index], &other.tiles[index]) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+    
+    pub fn same_size(&self, other: &TiledImage) -> bool {
+        self.width == other.width && self.height == other.height
+    }
+    
+    pub fn tile_rect_at(&self, index: usize) -> PixelRect {
+        let index = index as u32;
+        self.tile_rect(index % self.tiles_x, index / self.tiles_x)
+    }
+    
+    // Smallest rect holding every pixel that isn't fully transparent
+    pub fn content_bounds(&self) -> Option<PixelRect> {
+        let mut bounds: Option<(u32, u32, u32, u32)> = None;
+        for ty in 0..self.tiles_y {
+            for tx in 0..self.tiles_x {
+                let tile = match self.tile(tx, ty) {
+                    Some(tile) => tile,
+                    None => continue,
+                };
+                let rect = self.tile_rect(tx, ty);
+                for y in 0..rect.height {
+                    for x in 0..rect.width {
+                        if tile.get_pixel(x, y)[3] == 0 {
+                            continue;
+                        }
+                        let (x, y) = (rect.x + x, rect.y + y);
+                        bounds = Some(match bounds {
+                            Some((min_x, min_y, max_x, max_y)) => (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)),
+                            None => (x, y, x, y),
+                        });
+                    }
+                }
+            }
+        }
+        bounds.map(|(min_x, min_y, max_x, max_y)| PixelRect::new(min_x, min_y, max_x - min_x + 1, max_y - min_y + 1))
+    }
+    
+    pub fn allocated_tiles(&self) -> usize {
+        self.tiles.iter().filter(|tile| tile.is_some()).count()
+    }
+    
+    pub fn memory_bytes(&self) -> usize {
+        self.allocated_tiles() * TILE_BYTES
+    }
+    
+    // Frees tiles that have been erased back to full transparency
+    pub fn release_empty_tiles(&mut self) {
+        for tile in self.tiles.iter_mut() {
+            if tile.as_ref().is_some_and(|t| t.pixels().all(|p| p[3] == 0)) {
+                *tile = None;
+            }
+        }
+    }
+    
+    fn tile_index(&self, tx: u32, ty: u32) -> usize {
+        (ty * self.tiles_x + tx) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // Three tiles and a ragged 8 pixel column across, two and a ragged
+    // 2 pixel row down
+    const WIDTH: u32 = TILE_SIZE * 3 + 8;
+    const HEIGHT: u32 = TILE_SIZE * 2 + 2;
+    
+    fn gradient() -> RgbaImage {
+        ImageBuffer::from_fn(WIDTH, HEIGHT, |x, y| Rgba([x as u8, y as u8, (x + y) as u8, 255]))
+    }
+    
+    #[test]
+    fn fresh_image_allocates_nothing() {
+        let image = TiledImage::new(WIDTH, HEIGHT);
+        assert_eq!(image.tile_count(), 12);
+        assert_eq!(image.allocated_tiles(), 0);
+        assert_eq!(image.memory_bytes(), 0);
+        assert_eq!(image.get_pixel(WIDTH - 1, HEIGHT - 1), TRANSPARENT);
+    }
+    
+    #[test]
+    fn one_write_allocates_one_tile() {
+        let mut image = TiledImage::new(WIDTH, HEIGHT);
+        image.put_pixel(TILE_SIZE + 5, TILE_SIZE + 7, Rgba([1, 2, 3, 4]));
+        assert_eq!(image.allocated_tiles(), 1);
+        assert_eq!(image.memory_bytes(), TILE_BYTES);
+        assert!(image.tile(1, 1).is_some());
+        
+        // Transparent writes to empty tiles allocate nothing
+        image.put_pixel(0, 0, TRANSPARENT);
+        assert_eq!(image.allocated_tiles(), 1);
+    }
+    
+    #[test]
+    fn cleared_tiles_are_released() {
+        let mut image = TiledImage::new(WIDTH, HEIGHT);
+        image.put_pixel(3, 3, Rgba([255, 0, 0, 255]));
+        image.put_pixel(TILE_SIZE * 3 + 1, 1, Rgba([255, 0, 0, 255]));
+        image.put_pixel(3, 3, TRANSPARENT);
+        assert_eq!(image.allocated_tiles(), 2);
+        
+        image.release_empty_tiles();
+        assert_eq!(image.allocated_tiles(), 1);
+        assert!(image.tile(0, 0).is_none());
+        assert_eq!(image.get_pixel(TILE_SIZE * 3 + 1, 1), Rgba([255, 0, 0, 255]));
+    }
+    
+    #[test]
+    fn clones_share_tiles_until_written() {
+        let original = TiledImage::from_image(&gradient());
+        let mut copy = original.clone();
+        assert!((0..original.tile_count()).all(|index| original.same_tile(&copy, index)));
+        
+        copy.put_pixel(TILE_SIZE + 1, 0, Rgba([9, 9, 9, 255]));
+        for index in 0..original.tile_count() {
+            assert_eq!(original.same_tile(&copy, index), index != 1, "tile {index}");
+        }
+        assert_eq!(original.get_pixel(TILE_SIZE + 1, 0), Rgba([65, 0, 65, 255]));
+        assert_eq!(copy.get_pixel(TILE_SIZE + 1, 0), Rgba([9, 9, 9, 255]));
+    }
+    
+    #[test]
+    fn pixels_read_back_across_tile_boundaries_and_ragged_edges() {
+        let source = gradient();
+        let image = TiledImage::from_image(&source);
+        assert_eq!(image.to_image(), source);
+        
+        for (x, y) in [(TILE_SIZE - 1, 0), (TILE_SIZE, 0), (0, TILE_SIZE - 1), (0, TILE_SIZE), (WIDTH - 1, HEIGHT - 1)] {
+            assert_eq!(image.get_pixel(x, y), *source.get_pixel(x, y), "({x}, {y})");
+        }
+        assert_eq!(image.tile_rect(3, 2), PixelRect::new(TILE_SIZE * 3, TILE_SIZE * 2, 8, 2));
+        assert_eq!(image.tile_rect_at(11), image.tile_rect(3, 2));
+    }
+    
+    #[test]
+    fn quarter_turns_and_flips_match_the_image_crate() {
+        let source = gradient();
+        let image = TiledImage::from_image(&source);
+        
+        let turned = image.rotate_quarter_turns(1);
+        assert_eq!((turned.width(), turned.height()), (HEIGHT, WIDTH));
+        assert_eq!(turned.to_image(), image::imageops::rotate90(&source));
+        assert_eq!(image.rotate_quarter_turns(2).to_image(), image::imageops::rotate180(&source));
+        assert_eq!(image.rotate_quarter_turns(3).to_image(), image::imageops::rotate270(&source));
+        assert_eq!(image.rotate_quarter_turns(4), image);
+        
+        assert_eq!(image.flip(true).to_image(), image::imageops::flip_horizontal(&source));
+        assert_eq!(image.flip(false).to_image(), image::imageops::flip_vertical(&source));
+    }
+}
@@ -0,0 +1,463 @@
+use eframe::egui;
+use image::Rgba;
+use crate::image_ops::paint;
+use crate::image_ops::tiles::{TiledImage, TRANSPARENT};
+
+// How pixels are interpolated when an image is resampled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resampling {
+    Nearest,
+    Bilinear,
+    Bicubic,
+    Lanczos,
+}
+
+impl Resampling {
+    pub const ALL: [Resampling; 4] = [Resampling::Nearest, Resampling::Bilinear, Resampling::Bicubic, Resampling::Lanczos];
+    
+    pub fn name(&self) -> &'static str {
+        match self {
+            Resampling::Nearest => "Nearest Neighbor",
+            Resampling::Bilinear => "Bilinear",
+            Resampling::Bicubic => "Bicubic",
+            Resampling::Lanczos => "Lanczos",
+        }
+    }
+    
+    // Radius of the filter in source pixels when not downscaling
+    fn support(&self) -> f32 {
+        match self {
+            Resampling::Nearest => 0.5,
+            Resampling::Bilinear => 1.0,
+            Resampling::Bicubic => 2.0,
+            Resampling::Lanczos => 3.0,
+        }
+    }
+    
+    // Filter weight at distance `x` from the sample position
+    fn kernel(&self, x: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            Resampling::Nearest => if x < 0.5 { 1.0 } else { 0.0 },
+            Resampling::Bilinear => (1.0 - x).max(0.0),
+            // Catmull-Rom
+            Resampling::Bicubic => {
+                if x < 1.0 {
+                    1.5 * x * x * x - 2.5 * x * x + 1.0
+                } else if x < 2.0 {
+                    -0.5 * x * x * x + 2.5 * x * x - 4.0 * x + 2.0
+                } else {
+                    0.0
+                }
+            }
+            Resampling::Lanczos => {
+                if x < 1e-6 {
+                    1.0
+                } else if x < 3.0 {
+                    let px = std::f32::consts::PI * x;
+                    3.0 * px.sin() * (px / 3.0).sin() / (px * px)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+// Point of the canvas that stays put when its size changes, as a column
+// and row of a 3x3 grid: (0, 0) is the top-left corner, (1, 1) the centre
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Anchor {
+    pub column: u32,
+    pub row: u32,
+}
+
+impl Anchor {
+    pub const CENTER: Anchor = Anchor { column: 1, row: 1 };
+    
+    // How far existing content moves when a `width`x`height` canvas
+    // becomes `new_width`x`new_height`
+    pub fn offset(&self, width: u32, height: u32, new_width: u32, new_height: u32) -> (i64, i64) {
+        let dx = new_width as i64 - width as i64;
+        let dy = new_height as i64 - height as i64;
+        (dx * self.column.min(2) as i64 / 2, dy * self.row.min(2) as i64 / 2)
+    }
+}
+
+// Projective map from the unit square onto a quadrilateral. It is affine
+// when the quad is a parallelogram, and a perspective map otherwise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Homography {
+    // Row-major 3x3 matrix acting on (x, y, 1)
+    m: [f32; 9],
+}
+
+impl Homography {
+    // Sends the unit square's corners (0, 0), (1, 0), (1, 1) and (0, 1) to
+    // the corners of `quad` in the same order. None if the quad is degenerate.
+    pub fn square_to_quad(quad: [egui::Pos2; 4]) -> Option<Self> {
+        let [p0, p1, p2, p3] = quad;
+        let (dx1, dy1) = (p1.x - p2.x, p1.y - p2.y);
+        let (dx2, dy2) = (p3.x - p2.x, p3.y - p2.y);
+        let (dx3, dy3) = (p0.x - p1.x + p2.x - p3.x, p0.y - p1.y + p2.y - p3.y);
+        
+        let det = dx1 * dy2 - dx2 * dy1;
+        if det.abs() < 1e-6 {
+            return None;
+        }
+        let g = (dx3 * dy2 - dx2 * dy3) / det;
+        let h = (dx1 * dy3 - dx3 * dy1) / det;
+        
+        Some(Self {
+            m: [
+                p1.x - p0.x + g * p1.x, p3.x - p0.x + h * p3.x, p0.x,
+                p1.y - p0.y + g * p1.y, p3.y - p0.y + h * p3.y, p0.y,
+                g, h, 1.0,
+            ],
+        })
+    }
+    
+    pub fn inverse(&self) -> Option<Self> {
+        let [a, b, c, d, e, f, g, h, i] = self.m;
+        let adjugate = [
+            e * i - f * h, c * h - b * i, b * f - c * e,
+            f * g - d * i, a * i - c * g, c * d - a * f,
+            d * h - e * g, b * g - a * h, a * e - b * d,
+        ];
+        let det = a * adjugate[0] + b * adjugate[3] + c * adjugate[6];
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        Some(Self { m: adjugate.map(|value| value / det) })
+    }
+    
+    // None for points the map sends to infinity or behind the viewer
+    pub fn apply(&self, x: f32, y: f32) -> Option<(f32, f32)> {
+        let m = &self.m;
+        let w = m[6] * x + m[7] * y + m[8];
+        if w.abs() < 1e-9 {
+            return None;
+        }
+        Some(((m[0] * x + m[1] * y + m[2]) / w, (m[3] * x + m[4] * y + m[5]) / w))
+    }
+}
+
+// Calls `f` for each pixel of a `width`x`height` canvas that a
+// `src_width`x`src_height` source stretched onto `quad` reaches, with the
+// source position that lands on the pixel's centre
+pub fn for_each_warped_pixel<F>(quad: [egui::Pos2; 4], src_width: u32, src_height: u32, width: u32, height: u32, mut f: F)
+where
+    F: FnMut(u32, u32, f32, f32),
+{
+    let inverse = match Homography::square_to_quad(quad).and_then(|map| map.inverse()) {
+        Some(inverse) => inverse,
+        None => return,
+    };
+    
+    // Bounding box of the quad, widened by a pixel for filter support
+    let min_x = quad.iter().map(|p| p.x).fold(f32::INFINITY, f32::min).floor() - 1.0;
+    let max_x = quad.iter().map(|p| p.x).fold(f32::NEG_INFINITY, f32::max).ceil() + 1.0;
+    let min_y = quad.iter().map(|p| p.y).fold(f32::INFINITY, f32::min).floor() - 1.0;
+    let max_y = quad.iter().map(|p| p.y).fold(f32::NEG_INFINITY, f32::max).ceil() + 1.0;
+    let (x0, x1) = (min_x.max(0.0) as u32, (max_x.max(0.0) as u32).min(width));
+    let (y0, y1) = (min_y.max(0.0) as u32, (max_y.max(0.0) as u32).min(height));
+    
+    // Source positions this far outside the source only pick up transparency
+    let margin = 2.0;
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let (u, v) = match inverse.apply(x as f32 + 0.5, y as f32 + 0.5) {
+                Some(uv) => uv,
+                None => continue,
+            };
+            let (sx, sy) = (u * src_width as f32, v * src_height as f32);
+            if sx < -margin || sy < -margin || sx > src_width as f32 + margin || sy > src_height as f32 + margin {
+                continue;
+            }
+            f(x, y, sx, sy);
+        }
+    }
+}
+
+// Draws `src` stretched onto `quad` (top-left, top-right, bottom-right and
+// bottom-left corners in `dst` coordinates) over the pixels of `dst`
+pub fn warp_onto(dst: &mut TiledImage, src: &TiledImage, quad: [egui::Pos2; 4], resampling: Resampling) {
+    let (width, height) = (dst.width(), dst.height());
+    for_each_warped_pixel(quad, src.width(), src.height(), width, height, |x, y, sx, sy| {
+        let pixel = sample(src, sx, sy, resampling);
+        if pixel[3] > 0 {
+            let base = dst.get_pixel(x, y);
+            dst.put_pixel(x, y, paint::source_over(base, pixel, 1.0));
+        }
+    });
+}
+
+// Colour of `image` at the continuous position (x, y), where pixel (i, j)
+// covers i..i+1 and j..j+1. Everything outside the image is transparent.
+// Colours are interpolated premultiplied so transparent neighbours don't
+// bleed into the result.
+pub fn sample(image: &TiledImage, x: f32, y: f32, resampling: Resampling) -> Rgba<u8> {
+    // Position relative to pixel centres
+    let (x, y) = (x - 0.5, y - 0.5);
+    
+    if resampling == Resampling::Nearest {
+        return pixel_at(image, x.round() as i64, y.round() as i64);
+    }
+    
+    let radius = resampling.support() as i64;
+    let (x0, y0) = (x.floor() as i64 - radius + 1, y.floor() as i64 - radius + 1);
+    let taps = (2 * radius) as usize;
+    let weights_x: Vec<f32> = (0..taps).map(|i| resampling.kernel(x - (x0 + i as i64) as f32)).collect();
+    let weights_y: Vec<f32> = (0..taps).map(|i| resampling.kernel(y - (y0 + i as i64) as f32)).collect();
+    
+    let mut sums = [0.0f32; 4];
+    for (j, wy) in weights_y.iter().enumerate() {
+        for (i, wx) in weights_x.iter().enumerate() {
+            let weight = wx * wy;
+            if weight != 0.0 {
+                accumulate(&mut sums, pixel_at(image, x0 + i as i64, y0 + j as i64), weight);
+            }
+        }
+    }
+    
+    let total = weights_x.iter().sum::<f32>() * weights_y.iter().sum::<f32>();
+    unpremultiply(sums, total)
+}
+
+// Builds a `width`x`height` image by sampling `src` at the position that
+// `inverse` maps each destination pixel centre to
+pub fn warp<F>(src: &TiledImage, width: u32, height: u32, resampling: Resampling, inverse: F) -> TiledImage
+where
+    F: Fn(f32, f32) -> (f32, f32),
+{
+    let mut dst = TiledImage::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let (sx, sy) = inverse(x as f32 + 0.5, y as f32 + 0.5);
+            let pixel = sample(src, sx, sy, resampling);
+            dst.put_pixel(x, y, pixel);
+        }
+    }
+    dst
+}
+
+// Canvas size that holds a `width`x`height` image rotated by `degrees`
+pub fn rotated_size(width: u32, height: u32, degrees: f32) -> (u32, u32) {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (width, height) = (width as f32, height as f32);
+    // Shave off float noise so e.g. a 90° turn doesn't gain a pixel
+    let new_width = (width * cos.abs() + height * sin.abs() - 1e-3).ceil().max(1.0);
+    let new_height = (width * sin.abs() + height * cos.abs() - 1e-3).ceil().max(1.0);
+    (new_width as u32, new_height as u32)
+}
+
+// Rotates `src` clockwise by `degrees` about its centre onto a canvas of
+// `rotated_size`, so no corner is cut off
+pub fn rotate(src: &TiledImage, width: u32, height: u32, degrees: f32, resampling: Resampling) -> TiledImage {
+    let (new_width, new_height) = rotated_size(width, height, degrees);
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (src_cx, src_cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    let (dst_cx, dst_cy) = (new_width as f32 / 2.0, new_height as f32 / 2.0);
+    
+    warp(src, new_width, new_height, resampling, |x, y| {
+        // Undo the rotation: y points down, so clockwise on screen is the
+        // usual counter-clockwise matrix
+        let (dx, dy) = (x - dst_cx, y - dst_cy);
+        (src_cx + dx * cos + dy * sin, src_cy - dx * sin + dy * cos)
+    })
+}
+
+// Scales `src` to `width`x`height`. Filters are widened when shrinking so
+// every source pixel contributes, which avoids aliasing. Works in two
+// separable passes on premultiplied colour.
+pub fn resize(src: &TiledImage, width: u32, height: u32, resampling: Resampling) -> TiledImage {
+    let (src_width, src_height) = (src.width(), src.height());
+    let columns = filter_weights(src_width, width, resampling);
+    let rows = filter_weights(src_height, height, resampling);
+    
+    // Horizontal pass into a premultiplied `width`x`src_height` buffer
+    let mut horizontal = vec![[0.0f32; 4]; (width * src_height) as usize];
+    for y in 0..src_height {
+        for (x, (start, weights)) in columns.iter().enumerate() {
+            let mut sums = [0.0f32; 4];
+            for (i, weight) in weights.iter().enumerate() {
+                accumulate(&mut sums, src.get_pixel(*start + i as u32, y), *weight);
+            }
+            horizontal[(y * width) as usize + x] = sums;
+        }
+    }
+    
+    // Vertical pass
+    let mut dst = TiledImage::new(width, height);
+    for (y, (start, weights)) in rows.iter().enumerate() {
+        for x in 0..width {
+            let mut sums = [0.0f32; 4];
+            for (i, weight) in weights.iter().enumerate() {
+                let pixel = horizontal[((*start + i as u32) * width + x) as usize];
+                for c in 0..4 {
+                    sums[c] += pixel[c] * weight;
+                }
+            }
+            dst.put_pixel(x, y as u32, unpremultiply(sums, 1.0));
+        }
+    }
+    dst
+}
+
+// For each destination pixel along one axis, the first source pixel it
+// reads and the normalised weights of it and the following pixels
+fn filter_weights(src_len: u32, dst_len: u32, resampling: Resampling) -> Vec<(u32, Vec<f32>)> {
+    let scale = src_len as f32 / dst_len as f32;
+    let filter_scale = scale.max(1.0);
+    let support = resampling.support() * filter_scale;
+    
+    (0..dst_len)
+        .map(|i| {
+            let center = (i as f32 + 0.5) * scale;
+            if resampling == Resampling::Nearest {
+                return ((center as u32).min(src_len - 1), vec![1.0]);
+            }
+            
+            let start = (center - support).floor().max(0.0) as u32;
+            let end = ((center + support).ceil() as u32).min(src_len);
+            let mut weights: Vec<f32> = (start..end)
+                .map(|j| resampling.kernel((j as f32 + 0.5 - center) / filter_scale))
+                .collect();
+            let total: f32 = weights.iter().sum();
+            if total != 0.0 {
+                for weight in weights.iter_mut() {
+                    *weight /= total;
+                }
+            }
+            (start, weights)
+        })
+        .collect()
+}
+
+fn pixel_at(image: &TiledImage, x: i64, y: i64) -> Rgba<u8> {
+    if x < 0 || y < 0 || x >= image.width() as i64 || y >= image.height() as i64 {
+        TRANSPARENT
+    } else {
+        image.get_pixel(x as u32, y as u32)
+    }
+}
+
+// Adds `pixel`, premultiplied by its alpha, to the running `sums`
+fn accumulate(sums: &mut [f32; 4], pixel: Rgba<u8>, weight: f32) {
+    let alpha = pixel[3] as f32;
+    for c in 0..3 {
+        sums[c] += pixel[c] as f32 * alpha * weight;
+    }
+    sums[3] += alpha * weight;
+}
+
+// Turns premultiplied `sums` with total filter weight `total` back into a pixel
+fn unpremultiply(sums: [f32; 4], total: f32) -> Rgba<u8> {
+    if total == 0.0 {
+        return TRANSPARENT;
+    }
+    
+    // Sharpening filters can overshoot, so clamp before dividing
+    let alpha = (sums[3] / total).clamp(0.0, 255.0);
+    if alpha < 0.5 {
+        return TRANSPARENT;
+    }
+    let mut out = [0u8; 4];
+    for c in 0..3 {
+        out[c] = (sums[c] / sums[3]).round().clamp(0.0, 255.0) as u8;
+    }
+    out[3] = alpha.round() as u8;
+    Rgba(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn anchor_offsets_for_every_position() {
+        for row in 0..3 {
+            for column in 0..3 {
+                let anchor = Anchor { column, row };
+                // 10x6 grown by 4 each way, then shrunk by 4 each way
+                let grow = anchor.offset(10, 6, 14, 10);
+                assert_eq!(grow, (2 * column as i64, 2 * row as i64), "{:?}", anchor);
+                let shrink = anchor.offset(10, 6, 6, 2);
+                assert_eq!(shrink, (-2 * column as i64, -2 * row as i64), "{:?}", anchor);
+            }
+        }
+        // Odd changes split toward the top-left
+        assert_eq!(Anchor::CENTER.offset(10, 6, 13, 9), (1, 1));
+        assert_eq!(Anchor::CENTER.offset(10, 6, 7, 3), (-1, -1));
+    }
+    
+    const QUAD: [egui::Pos2; 4] = [egui::pos2(10.0, 20.0), egui::pos2(50.0, 25.0), egui::pos2(45.0, 70.0), egui::pos2(5.0, 60.0)];
+    const UNIT_SQUARE: [(f32, f32); 4] = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+    
+    fn assert_close((x, y): (f32, f32), (expected_x, expected_y): (f32, f32)) {
+        assert!((x - expected_x).abs() < 1e-3 && (y - expected_y).abs() < 1e-3, "({}, {}) != ({}, {})", x, y, expected_x, expected_y);
+    }
+    
+    #[test]
+    fn homography_maps_the_unit_square_onto_the_quad_and_back() {
+        let map = Homography::square_to_quad(QUAD).unwrap();
+        let inverse = map.inverse().unwrap();
+        for (corner, (u, v)) in QUAD.iter().zip(UNIT_SQUARE) {
+            assert_close(map.apply(u, v).unwrap(), (corner.x, corner.y));
+            assert_close(inverse.apply(corner.x, corner.y).unwrap(), (u, v));
+        }
+        
+        let (x, y) = map.apply(0.3, 0.7).unwrap();
+        assert_close(inverse.apply(x, y).unwrap(), (0.3, 0.7));
+    }
+    
+    #[test]
+    fn parallelograms_map_affinely_and_degenerate_quads_not_at_all() {
+        let rect = [egui::pos2(2.0, 3.0), egui::pos2(10.0, 3.0), egui::pos2(10.0, 7.0), egui::pos2(2.0, 7.0)];
+        let map = Homography::square_to_quad(rect).unwrap();
+        assert_close(map.apply(0.5, 0.5).unwrap(), (6.0, 5.0));
+        assert_close(map.apply(0.25, 0.75).unwrap(), (4.0, 6.0));
+        
+        let line = [egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0), egui::pos2(2.0, 2.0), egui::pos2(3.0, 3.0)];
+        assert_eq!(Homography::square_to_quad(line), None);
+    }
+    
+    fn gradient(width: u32, height: u32) -> TiledImage {
+        let mut image = TiledImage::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                image.put_pixel(x, y, Rgba([(x * 60) as u8, (y * 60) as u8, 90, 255]));
+            }
+        }
+        image
+    }
+    
+    fn rect_quad(x: f32, y: f32, width: f32, height: f32) -> [egui::Pos2; 4] {
+        [egui::pos2(x, y), egui::pos2(x + width, y), egui::pos2(x + width, y + height), egui::pos2(x, y + height)]
+    }
+    
+    #[test]
+    fn warping_onto_the_same_rect_copies_the_pixels() {
+        let src = gradient(4, 4);
+        for resampling in Resampling::ALL {
+            let mut dst = TiledImage::new(4, 4);
+            warp_onto(&mut dst, &src, rect_quad(0.0, 0.0, 4.0, 4.0), resampling);
+            assert_eq!(dst, src, "{:?}", resampling);
+        }
+    }
+    
+    #[test]
+    fn warping_onto_a_translated_rect_moves_the_pixels() {
+        let src = gradient(4, 4);
+        for resampling in [Resampling::Nearest, Resampling::Bilinear] {
+            let mut dst = TiledImage::new(10, 10);
+            warp_onto(&mut dst, &src, rect_quad(3.0, 2.0, 4.0, 4.0), resampling);
+            for y in 0..10 {
+                for x in 0..10 {
+                    let expected = if (3..7).contains(&x) && (2..6).contains(&y) { src.get_pixel(x - 3, y - 2) } else { TRANSPARENT };
+                    assert_eq!(dst.get_pixel(x, y), expected, "({}, {}) {:?}", x, y, resampling);
+                }
+            }
+        }
+    }
+}
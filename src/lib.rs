@@ -0,0 +1,3 @@
+pub mod ui;
+pub mod tools;
+pub mod image_ops;
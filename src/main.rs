@@ -1,10 +1,7 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-mod ui;
-mod tools;
-mod image_ops;
-
 use eframe::egui;
+use paintmate::ui;
 
 fn main() -> Result<(), eframe::Error> {
     env_logger::init();
@@ -32,7 +29,7 @@ fn main() -> Result<(), eframe::Error> {
 }
 
 fn setup_custom_fonts(ctx: &egui::Context) {
-    let mut fonts = egui::FontDefinitions::default();
+    let fonts = egui::FontDefinitions::default();
     
     // We'll use default fonts for now
     // TODO: Add custom fonts when available
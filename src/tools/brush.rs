@@ -3,15 +3,25 @@ use crate::tools::ToolBehavior;
 use crate::tools::BrushSettings;
 use eframe::egui;
 
+// Smallest distance between two dabs, so tiny brushes with low spacing
+// don't stamp hundreds of times per pixel
+const MIN_DAB_SPACING: f32 = 0.5;
+
+#[derive(Default)]
 pub struct BrushTool {
     pub current_stroke: Vec<egui::Pos2>,
+    last_dab: Option<egui::Pos2>,
+    pending_dabs: Vec<egui::Pos2>,
 }
 
-impl Default for BrushTool {
-    fn default() -> Self {
-        Self {
-            current_stroke: Vec::new(),
-        }
+impl BrushTool {
+    // Dab centres produced since the last call, in image coordinates
+    pub fn take_dabs(&mut self) -> Vec<egui::Pos2> {
+        std::mem::take(&mut self.pending_dabs)
+    }
+    
+    fn dab_spacing(settings: &BrushSettings) -> f32 {
+        (settings.spacing * settings.size).max(MIN_DAB_SPACING)
     }
 }
 
@@ -19,14 +29,39 @@ impl ToolBehavior for BrushTool {
     fn start_stroke(&mut self, pos: egui::Pos2, _settings: &BrushSettings) {
         self.current_stroke.clear();
         self.current_stroke.push(pos);
+        self.last_dab = Some(pos);
+        self.pending_dabs.push(pos);
     }
     
-    fn continue_stroke(&mut self, pos: egui::Pos2, _settings: &BrushSettings) {
+    fn continue_stroke(&mut self, pos: egui::Pos2, settings: &BrushSettings) {
         self.current_stroke.push(pos);
+        
+        let mut last = match self.last_dab {
+            Some(last) => last,
+            None => {
+                self.start_stroke(pos, settings);
+                return;
+            }
+        };
+        
+        // Walk from the last stamped dab towards the pointer at fixed spacing.
+        // Leftover distance carries over to the next segment because the walk
+        // always resumes from the last dab rather than the last pointer position.
+        let spacing = Self::dab_spacing(settings);
+        let mut remaining = pos.distance(last);
+        while remaining >= spacing {
+            let direction = (pos - last) / remaining;
+            last += direction * spacing;
+            self.pending_dabs.push(last);
+            remaining -= spacing;
+        }
+        
+        self.last_dab = Some(last);
     }
     
     fn end_stroke(&mut self, _settings: &BrushSettings) {
         self.current_stroke.clear();
+        self.last_dab = None;
     }
     
     fn draw_preview(&self, _ui: &mut egui::Ui, painter: &egui::Painter, pos: egui::Pos2, settings: &BrushSettings) {
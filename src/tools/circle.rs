@@ -1 +1,13 @@
+use eframe::egui;
+use crate::image_ops::shapes::Shape;
+use crate::tools::rectangle::RectangleTool;
+
 pub struct CircleTool;
+
+impl CircleTool {
+    // Ellipse filling the dragged rect. Shift constrains to a circle,
+    // Alt draws from the centre.
+    pub fn shape(start: egui::Pos2, end: egui::Pos2, modifiers: &egui::Modifiers) -> Shape {
+        Shape::Ellipse(RectangleTool::drag_rect(start, end, modifiers.shift, modifiers.alt))
+    }
+}
@@ -1 +1,450 @@
-pub struct CropTool;
+use eframe::egui;
+use crate::image_ops::{ImageData, ImageHistory};
+use crate::image_ops::dirty::PixelRect;
+
+// Screen-space size of the handles and how close the pointer must be to grab one
+const HANDLE_SIZE: f32 = 8.0;
+const HANDLE_GRAB_DISTANCE: f32 = 8.0;
+
+// Which edges of the crop rect follow the pointer. No edges means the
+// whole rect is being moved.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Edges {
+    left: bool,
+    right: bool,
+    top: bool,
+    bottom: bool,
+}
+
+impl Edges {
+    const MOVE: Edges = Edges { left: false, right: false, top: false, bottom: false };
+    
+    fn is_move(&self) -> bool {
+        *self == Self::MOVE
+    }
+}
+
+struct CropDrag {
+    edges: Edges,
+    start_rect: egui::Rect,
+    start_pos: egui::Pos2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CropAction {
+    None,
+    Apply,
+    Cancel,
+}
+
+pub struct CropTool {
+    // Crop area in image coordinates, always on whole pixels
+    pub rect: Option<egui::Rect>,
+    pub lock_aspect: bool,
+    pub aspect_width: u32,
+    pub aspect_height: u32,
+    drag: Option<CropDrag>,
+}
+
+impl Default for CropTool {
+    fn default() -> Self {
+        Self {
+            rect: None,
+            lock_aspect: false,
+            aspect_width: 1,
+            aspect_height: 1,
+            drag: None,
+        }
+    }
+}
+
+impl CropTool {
+    // Pointer handling on the canvas. Dragging a handle resizes the rect,
+    // dragging inside moves it and dragging anywhere else starts a new one.
+    pub fn handle_input(&mut self, response: &egui::Response, origin: egui::Pos2, zoom: f32, image_size: egui::Vec2) {
+        let to_image = |pos: egui::Pos2| egui::pos2((pos.x - origin.x) / zoom, (pos.y - origin.y) / zoom);
+        
+        if response.drag_started_by(egui::PointerButton::Primary) {
+            if let Some(pointer) = response.interact_pointer_pos() {
+                let edges = self.rect.and_then(|rect| Self::hit_test(rect, pointer, origin, zoom));
+                self.drag = Some(match edges {
+                    Some(edges) => CropDrag {
+                        edges,
+                        start_rect: self.rect.unwrap_or(egui::Rect::NOTHING),
+                        start_pos: to_image(pointer),
+                    },
+                    None => {
+                        // A new rect grows from its top-left corner
+                        let start = to_image(pointer).round();
+                        CropDrag {
+                            edges: Edges { left: false, right: true, top: false, bottom: true },
+                            start_rect: egui::Rect::from_min_max(start, start),
+                            start_pos: start,
+                        }
+                    }
+                });
+            }
+        }
+        
+        if let (Some(drag), Some(pointer)) = (&self.drag, response.interact_pointer_pos()) {
+            if response.dragged_by(egui::PointerButton::Primary) {
+                let rect = self.dragged_rect(drag, to_image(pointer), image_size);
+                self.rect = if rect.width() >= 1.0 && rect.height() >= 1.0 { Some(rect) } else { None };
+            }
+        }
+        
+        if response.drag_stopped() {
+            self.drag = None;
+        }
+        
+        if let (Some(rect), Some(hover)) = (self.rect, response.hover_pos()) {
+            let icon = match Self::hit_test(rect, hover, origin, zoom) {
+                Some(edges) if edges.is_move() => egui::CursorIcon::Move,
+                Some(edges) if (edges.left && edges.top) || (edges.right && edges.bottom) => egui::CursorIcon::ResizeNwSe,
+                Some(edges) if (edges.right && edges.top) || (edges.left && edges.bottom) => egui::CursorIcon::ResizeNeSw,
+                Some(edges) if edges.left || edges.right => egui::CursorIcon::ResizeHorizontal,
+                Some(_) => egui::CursorIcon::ResizeVertical,
+                None => egui::CursorIcon::Crosshair,
+            };
+            response.ctx.set_cursor_icon(icon);
+        }
+    }
+    
+    fn dragged_rect(&self, drag: &CropDrag, pos: egui::Pos2, image_size: egui::Vec2) -> egui::Rect {
+        let bounds = egui::Rect::from_min_size(egui::Pos2::ZERO, image_size);
+        let delta = pos - drag.start_pos;
+        let (mut min, mut max) = (drag.start_rect.min, drag.start_rect.max);
+        
+        if drag.edges.is_move() {
+            // Keep the whole rect on the canvas while moving it
+            let delta = egui::vec2(
+                delta.x.clamp(-min.x, image_size.x - max.x),
+                delta.y.clamp(-min.y, image_size.y - max.y),
+            );
+            return snap(egui::Rect::from_min_max(min + delta, max + delta));
+        }
+        
+        if drag.edges.left {
+            min.x += delta.x;
+        }
+        if drag.edges.right {
+            max.x += delta.x;
+        }
+        if drag.edges.top {
+            min.y += delta.y;
+        }
+        if drag.edges.bottom {
+            max.y += delta.y;
+        }
+        
+        let mut rect = egui::Rect::from_two_pos(min, max);
+        if self.lock_aspect {
+            // Shrink towards the edges that stay put so the ratio survives
+            // hitting the canvas border
+            let start = drag.start_rect;
+            let anchor = egui::pos2(
+                if drag.edges.left { start.max.x } else if drag.edges.right { start.min.x } else { start.center().x },
+                if drag.edges.top { start.max.y } else if drag.edges.bottom { start.min.y } else { start.center().y },
+            );
+            rect = fit_within(self.constrain_aspect(rect, drag.edges), anchor, bounds);
+        }
+        snap(rect.intersect(bounds))
+    }
+    
+    fn aspect_ratio(&self) -> f32 {
+        self.aspect_width.max(1) as f32 / self.aspect_height.max(1) as f32
+    }
+    
+    // Size for a numeric edit with the aspect ratio locked: the side that
+    // wasn't edited follows the one that was, then both shrink to fit
+    fn locked_size(&self, width: u32, height: u32, width_edited: bool, max_width: u32, max_height: u32) -> (u32, u32) {
+        let ratio = self.aspect_ratio();
+        let (width, height) = if width_edited {
+            (width as f32, width as f32 / ratio)
+        } else {
+            (height as f32 * ratio, height as f32)
+        };
+        let fit = (max_width as f32 / width).min(max_height as f32 / height).min(1.0);
+        (
+            ((width * fit).round() as u32).clamp(1, max_width),
+            ((height * fit).round() as u32).clamp(1, max_height),
+        )
+    }
+    
+    // Adjusts the side the user isn't dragging so the rect keeps the locked ratio
+    fn constrain_aspect(&self, rect: egui::Rect, edges: Edges) -> egui::Rect {
+        let ratio = self.aspect_ratio();
+        let horizontal = edges.left || edges.right;
+        let vertical = edges.top || edges.bottom;
+        
+        if horizontal && !vertical {
+            let height = rect.width() / ratio;
+            egui::Rect::from_center_size(rect.center(), egui::vec2(rect.width(), height))
+        } else if vertical && !horizontal {
+            let width = rect.height() * ratio;
+            egui::Rect::from_center_size(rect.center(), egui::vec2(width, rect.height()))
+        } else {
+            // Corner: the edge opposite the dragged one stays put
+            let height = rect.width() / ratio;
+            if edges.top {
+                egui::Rect::from_min_max(egui::pos2(rect.min.x, rect.max.y - height), rect.max)
+            } else {
+                egui::Rect::from_min_max(rect.min, egui::pos2(rect.max.x, rect.min.y + height))
+            }
+        }
+    }
+    
+    // Handle or body of `rect` under the screen position `pos`, if any
+    fn hit_test(rect: egui::Rect, pos: egui::Pos2, origin: egui::Pos2, zoom: f32) -> Option<Edges> {
+        let screen = Self::to_screen(rect, origin, zoom);
+        for (handle, edges) in Self::handles(screen) {
+            if handle.distance(pos) <= HANDLE_GRAB_DISTANCE {
+                return Some(edges);
+            }
+        }
+        
+        if screen.contains(pos) {
+            Some(Edges::MOVE)
+        } else {
+            None
+        }
+    }
+    
+    fn handles(screen: egui::Rect) -> [(egui::Pos2, Edges); 8] {
+        let center = screen.center();
+        let edges = |left, right, top, bottom| Edges { left, right, top, bottom };
+        [
+            (screen.left_top(), edges(true, false, true, false)),
+            (screen.right_top(), edges(false, true, true, false)),
+            (screen.left_bottom(), edges(true, false, false, true)),
+            (screen.right_bottom(), edges(false, true, false, true)),
+            (egui::pos2(center.x, screen.top()), edges(false, false, true, false)),
+            (egui::pos2(center.x, screen.bottom()), edges(false, false, false, true)),
+            (egui::pos2(screen.left(), center.y), edges(true, false, false, false)),
+            (egui::pos2(screen.right(), center.y), edges(false, true, false, false)),
+        ]
+    }
+    
+    fn to_screen(rect: egui::Rect, origin: egui::Pos2, zoom: f32) -> egui::Rect {
+        egui::Rect::from_min_max(origin + rect.min.to_vec2() * zoom, origin + rect.max.to_vec2() * zoom)
+    }
+    
+    // Dims everything outside the crop area and draws the frame, a rule of
+    // thirds grid and the resize handles
+    pub fn draw_overlay(&self, painter: &egui::Painter, image_rect: egui::Rect, zoom: f32) {
+        let rect = match self.rect {
+            Some(rect) => Self::to_screen(rect, image_rect.min, zoom),
+            None => return,
+        };
+        
+        let shade = egui::Color32::from_black_alpha(140);
+        painter.rect_filled(egui::Rect::from_min_max(image_rect.min, egui::pos2(image_rect.max.x, rect.min.y)), 0.0, shade);
+        painter.rect_filled(egui::Rect::from_min_max(egui::pos2(image_rect.min.x, rect.max.y), image_rect.max), 0.0, shade);
+        painter.rect_filled(egui::Rect::from_min_max(egui::pos2(image_rect.min.x, rect.min.y), egui::pos2(rect.min.x, rect.max.y)), 0.0, shade);
+        painter.rect_filled(egui::Rect::from_min_max(egui::pos2(rect.max.x, rect.min.y), egui::pos2(image_rect.max.x, rect.max.y)), 0.0, shade);
+        
+        let thirds = egui::Stroke::new(1.0, egui::Color32::from_white_alpha(100));
+        for i in 1..3 {
+            let t = i as f32 / 3.0;
+            let x = rect.min.x + rect.width() * t;
+            let y = rect.min.y + rect.height() * t;
+            painter.line_segment([egui::pos2(x, rect.min.y), egui::pos2(x, rect.max.y)], thirds);
+            painter.line_segment([egui::pos2(rect.min.x, y), egui::pos2(rect.max.x, y)], thirds);
+        }
+        
+        painter.rect_stroke(rect, 0.0, egui::Stroke::new(1.0, egui::Color32::WHITE));
+        for (handle, _) in Self::handles(rect) {
+            let handle = egui::Rect::from_center_size(handle, egui::Vec2::splat(HANDLE_SIZE));
+            painter.rect_filled(handle, 0.0, egui::Color32::WHITE);
+            painter.rect_stroke(handle, 0.0, egui::Stroke::new(1.0, egui::Color32::BLACK));
+        }
+    }
+    
+    // Numeric entry and apply/cancel buttons for the side panel
+    pub fn show_ui(&mut self, ui: &mut egui::Ui, image_width: u32, image_height: u32) -> CropAction {
+        let mut action = CropAction::None;
+        
+        ui.checkbox(&mut self.lock_aspect, "Lock aspect ratio");
+        ui.horizontal(|ui| {
+            ui.add_enabled(self.lock_aspect, egui::DragValue::new(&mut self.aspect_width).clamp_range(1..=1000));
+            ui.label(":");
+            ui.add_enabled(self.lock_aspect, egui::DragValue::new(&mut self.aspect_height).clamp_range(1..=1000));
+        });
+        
+        match self.rect {
+            Some(rect) => {
+                let mut x = rect.min.x as u32;
+                let mut y = rect.min.y as u32;
+                let mut width = rect.width() as u32;
+                let mut height = rect.height() as u32;
+                
+                let mut changed = false;
+                let mut height_edited = false;
+                egui::Grid::new("crop_rect").num_columns(2).show(ui, |ui| {
+                    ui.label("X:");
+                    changed |= ui.add(egui::DragValue::new(&mut x).clamp_range(0..=image_width - 1).suffix(" px")).changed();
+                    ui.end_row();
+                    ui.label("Y:");
+                    changed |= ui.add(egui::DragValue::new(&mut y).clamp_range(0..=image_height - 1).suffix(" px")).changed();
+                    ui.end_row();
+                    ui.label("Width:");
+                    changed |= ui.add(egui::DragValue::new(&mut width).clamp_range(1..=image_width - x).suffix(" px")).changed();
+                    ui.end_row();
+                    ui.label("Height:");
+                    height_edited = ui.add(egui::DragValue::new(&mut height).clamp_range(1..=image_height - y).suffix(" px")).changed();
+                    changed |= height_edited;
+                    ui.end_row();
+                });
+                
+                if changed {
+                    let (max_width, max_height) = (image_width - x, image_height - y);
+                    let (width, height) = if self.lock_aspect {
+                        self.locked_size(width, height, !height_edited, max_width, max_height)
+                    } else {
+                        (width.min(max_width), height.min(max_height))
+                    };
+                    self.rect = Some(egui::Rect::from_min_size(
+                        egui::pos2(x as f32, y as f32),
+                        egui::vec2(width as f32, height as f32),
+                    ));
+                }
+                
+                ui.horizontal(|ui| {
+                    if ui.button("Apply (Enter)").clicked() {
+                        action = CropAction::Apply;
+                    }
+                    if ui.button("Cancel (Esc)").clicked() {
+                        action = CropAction::Cancel;
+                    }
+                });
+            }
+            None => {
+                ui.label("Drag on the image to choose the area to keep");
+                if ui.button("Select All").clicked() {
+                    self.rect = Some(egui::Rect::from_min_size(
+                        egui::Pos2::ZERO,
+                        egui::vec2(image_width as f32, image_height as f32),
+                    ));
+                }
+            }
+        }
+        
+        action
+    }
+    
+    // Crops the document to the current rect as one undo step.
+    // Returns true if the document changed.
+    pub fn apply(&mut self, image_data: &mut ImageData, image_history: &mut ImageHistory) -> bool {
+        let rect = match self.rect.take() {
+            Some(rect) => rect,
+            None => return false,
+        };
+        self.drag = None;
+        
+        let area = PixelRect::from_bounds(
+            rect.min.x as i64,
+            rect.min.y as i64,
+            rect.max.x as i64,
+            rect.max.y as i64,
+            image_data.width(),
+            image_data.height(),
+        );
+        match area {
+            Some(area) => {
+                image_history.record("Crop", image_data, |image_data| image_data.crop(area));
+                true
+            }
+            None => false,
+        }
+    }
+    
+    pub fn cancel(&mut self) {
+        self.rect = None;
+        self.drag = None;
+    }
+}
+
+fn snap(rect: egui::Rect) -> egui::Rect {
+    egui::Rect::from_min_max(rect.min.round(), rect.max.round())
+}
+
+// `rect` scaled about `anchor` just enough to lie within `bounds`, which
+// keeps its aspect ratio. `anchor` must be inside `bounds`.
+fn fit_within(rect: egui::Rect, anchor: egui::Pos2, bounds: egui::Rect) -> egui::Rect {
+    let mut scale: f32 = 1.0;
+    for (min, max, anchor, low, high) in [
+        (rect.min.x, rect.max.x, anchor.x, bounds.min.x, bounds.max.x),
+        (rect.min.y, rect.max.y, anchor.y, bounds.min.y, bounds.max.y),
+    ] {
+        if min < low && min < anchor {
+            scale = scale.min((anchor - low) / (anchor - min));
+        }
+        if max > high && max > anchor {
+            scale = scale.min((high - anchor) / (max - anchor));
+        }
+    }
+    let scale = scale.max(0.0);
+    egui::Rect::from_min_max(anchor + (rect.min - anchor) * scale, anchor + (rect.max - anchor) * scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const IMAGE_SIZE: egui::Vec2 = egui::vec2(200.0, 100.0);
+    
+    fn locked(aspect_width: u32, aspect_height: u32) -> CropTool {
+        CropTool { lock_aspect: true, aspect_width, aspect_height, ..CropTool::default() }
+    }
+    
+    fn drag(edges: Edges, start_rect: egui::Rect, start_pos: egui::Pos2) -> CropDrag {
+        CropDrag { edges, start_rect, start_pos }
+    }
+    
+    fn ratio(rect: egui::Rect) -> f32 {
+        rect.width() / rect.height()
+    }
+    
+    #[test]
+    fn locked_corner_drag_past_the_canvas_keeps_the_ratio() {
+        let tool = locked(1, 1);
+        let start = egui::Rect::from_min_max(egui::pos2(20.0, 20.0), egui::pos2(40.0, 40.0));
+        let edges = Edges { left: false, right: true, top: false, bottom: true };
+        let rect = tool.dragged_rect(&drag(edges, start, start.max), egui::pos2(190.0, 190.0), IMAGE_SIZE);
+        
+        assert_eq!(rect, egui::Rect::from_min_max(egui::pos2(20.0, 20.0), egui::pos2(100.0, 100.0)));
+    }
+    
+    #[test]
+    fn locked_edge_drag_past_the_canvas_keeps_the_ratio() {
+        let tool = locked(2, 1);
+        let start = egui::Rect::from_min_max(egui::pos2(100.0, 40.0), egui::pos2(140.0, 60.0));
+        let edges = Edges { left: true, right: false, top: false, bottom: false };
+        let rect = tool.dragged_rect(&drag(edges, start, start.min), egui::pos2(-50.0, 40.0), IMAGE_SIZE);
+        
+        // The right edge stays put and the height around the centre hits
+        // the canvas first
+        assert_eq!(rect.max.x, 140.0);
+        assert!(rect.min.x >= 0.0 && rect.min.y >= 0.0 && rect.max.y <= 100.0);
+        assert!((ratio(rect) - 2.0).abs() < 0.05, "{:?}", rect);
+    }
+    
+    #[test]
+    fn unlocked_drag_is_clipped_to_the_canvas() {
+        let tool = CropTool::default();
+        let start = egui::Rect::from_min_max(egui::pos2(20.0, 20.0), egui::pos2(40.0, 40.0));
+        let edges = Edges { left: false, right: true, top: false, bottom: true };
+        let rect = tool.dragged_rect(&drag(edges, start, start.max), egui::pos2(300.0, 60.0), IMAGE_SIZE);
+        
+        assert_eq!(rect, egui::Rect::from_min_max(egui::pos2(20.0, 20.0), egui::pos2(200.0, 60.0)));
+    }
+    
+    #[test]
+    fn locked_numeric_edits_follow_the_ratio() {
+        let tool = locked(4, 3);
+        assert_eq!(tool.locked_size(80, 10, true, 200, 100), (80, 60));
+        assert_eq!(tool.locked_size(10, 30, false, 200, 100), (40, 30));
+        // Too tall for the space left, so both sides shrink
+        assert_eq!(tool.locked_size(160, 10, true, 200, 90), (120, 90));
+    }
+}
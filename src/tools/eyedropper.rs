@@ -1 +1,115 @@
+use eframe::egui;
+use crate::image_ops::ImageData;
+
+// Pixels shown across the loupe, and the on-screen size of each
+const LOUPE_PIXELS: i64 = 11;
+const LOUPE_CELL: f32 = 8.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleSize {
+    Point,
+    Average3,
+    Average5,
+    Average11,
+}
+
+impl SampleSize {
+    pub const ALL: [SampleSize; 4] = [
+        SampleSize::Point,
+        SampleSize::Average3,
+        SampleSize::Average5,
+        SampleSize::Average11,
+    ];
+    
+    pub fn name(&self) -> &'static str {
+        match self {
+            SampleSize::Point => "Point sample",
+            SampleSize::Average3 => "3 x 3 average",
+            SampleSize::Average5 => "5 x 5 average",
+            SampleSize::Average11 => "11 x 11 average",
+        }
+    }
+    
+    // Distance from the centre pixel to the edge of the sampled square
+    pub fn radius(&self) -> u32 {
+        match self {
+            SampleSize::Point => 0,
+            SampleSize::Average3 => 1,
+            SampleSize::Average5 => 2,
+            SampleSize::Average11 => 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EyedropperSettings {
+    pub size: SampleSize,
+    // Sample the flattened image instead of the active layer
+    pub sample_merged: bool,
+}
+
+impl Default for EyedropperSettings {
+    fn default() -> Self {
+        Self {
+            size: SampleSize::Point,
+            sample_merged: true,
+        }
+    }
+}
+
+// A colour picked from the canvas, waiting to be applied to the colour picker
+#[derive(Debug, Clone, Copy)]
+pub struct PickedColor {
+    pub color: egui::Color32,
+    pub secondary: bool,
+}
+
 pub struct EyedropperTool;
+
+impl EyedropperTool {
+    pub fn sample(image_data: &ImageData, x: u32, y: u32, settings: &EyedropperSettings) -> Option<egui::Color32> {
+        let color = image_data.sample_color(x, y, settings.size.radius(), settings.sample_merged)?;
+        Some(egui::Color32::from_rgba_unmultiplied(color[0], color[1], color[2], color[3]))
+    }
+    
+    // Magnified view of the composite around pixel (x, y), drawn next to the
+    // cursor at `pointer`, with a swatch of the colour being picked
+    pub fn draw_loupe(painter: &egui::Painter, image_data: &ImageData, x: u32, y: u32, pointer: egui::Pos2, picked: egui::Color32) {
+        let size = LOUPE_PIXELS as f32 * LOUPE_CELL;
+        let origin = pointer + egui::vec2(20.0, 20.0);
+        let frame = egui::Rect::from_min_size(origin, egui::vec2(size, size));
+        painter.rect_filled(frame.expand(2.0), 2.0, egui::Color32::from_gray(40));
+        
+        let half = LOUPE_PIXELS / 2;
+        for row in 0..LOUPE_PIXELS {
+            for column in 0..LOUPE_PIXELS {
+                let px = x as i64 + column - half;
+                let py = y as i64 + row - half;
+                let color = if px >= 0 && py >= 0 && px < image_data.width() as i64 && py < image_data.height() as i64 {
+                    let pixel = image_data.composite_pixel(px as u32, py as u32);
+                    egui::Color32::from_rgba_unmultiplied(pixel[0], pixel[1], pixel[2], pixel[3])
+                } else {
+                    egui::Color32::TRANSPARENT
+                };
+                
+                let cell = egui::Rect::from_min_size(
+                    origin + egui::vec2(column as f32, row as f32) * LOUPE_CELL,
+                    egui::Vec2::splat(LOUPE_CELL),
+                );
+                painter.rect_filled(cell, 0.0, color);
+            }
+        }
+        
+        // Outline the pixel under the cursor
+        let center = egui::Rect::from_min_size(
+            origin + egui::Vec2::splat(half as f32 * LOUPE_CELL),
+            egui::Vec2::splat(LOUPE_CELL),
+        );
+        painter.rect_stroke(center, 0.0, egui::Stroke::new(1.0, egui::Color32::WHITE));
+        painter.rect_stroke(frame, 0.0, egui::Stroke::new(1.0, egui::Color32::BLACK));
+        
+        let swatch = egui::Rect::from_min_size(frame.left_bottom() + egui::vec2(0.0, 4.0), egui::vec2(size, 12.0));
+        painter.rect_filled(swatch, 0.0, picked);
+        painter.rect_stroke(swatch, 0.0, egui::Stroke::new(1.0, egui::Color32::BLACK));
+    }
+}
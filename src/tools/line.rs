@@ -1 +1,30 @@
+use std::f32::consts::FRAC_PI_4;
+use eframe::egui;
+use crate::image_ops::shapes::Shape;
+
 pub struct LineTool;
+
+impl LineTool {
+    // Endpoints of a line dragged from `start` to `end`. `snap` rounds the
+    // angle to a multiple of 45 degrees and `from_center` mirrors the line
+    // around `start`.
+    pub fn drag_line(start: egui::Pos2, end: egui::Pos2, snap: bool, from_center: bool) -> (egui::Pos2, egui::Pos2) {
+        let mut delta = end - start;
+        if snap && delta != egui::Vec2::ZERO {
+            let angle = (delta.angle() / FRAC_PI_4).round() * FRAC_PI_4;
+            delta = egui::Vec2::angled(angle) * delta.length();
+        }
+        
+        if from_center {
+            (start - delta, start + delta)
+        } else {
+            (start, start + delta)
+        }
+    }
+    
+    // Shift snaps to 45 degrees, Alt draws from the centre
+    pub fn shape(start: egui::Pos2, end: egui::Pos2, modifiers: &egui::Modifiers, width: f32) -> Shape {
+        let (from, to) = Self::drag_line(start, end, modifiers.shift, modifiers.alt);
+        Shape::Line { from, to, width }
+    }
+}
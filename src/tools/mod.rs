@@ -8,11 +8,16 @@ pub mod line;
 pub mod text;
 pub mod crop;
 pub mod eyedropper;
+pub mod selection;
+pub mod transform;
 
 use eframe::egui;
+use crate::image_ops::fill::FillOptions;
+use crate::tools::eyedropper::{EyedropperSettings, SampleSize};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub enum ToolType {
+    #[default]
     Brush,
     Pencil,
     Eraser,
@@ -23,12 +28,12 @@ pub enum ToolType {
     Text,
     Crop,
     Eyedropper,
-}
-
-impl Default for ToolType {
-    fn default() -> Self {
-        ToolType::Brush
-    }
+    RectSelect,
+    EllipseSelect,
+    Lasso,
+    PolygonLasso,
+    MagicWand,
+    Move,
 }
 
 #[derive(Debug, Clone)]
@@ -50,6 +55,7 @@ impl Default for Tool {
 pub struct BrushSettings {
     pub size: f32,
     pub opacity: f32,
+    pub flow: f32,
     pub hardness: f32,
     pub spacing: f32,
     pub primary_color: egui::Color32,
@@ -57,6 +63,8 @@ pub struct BrushSettings {
     pub border_width: f32,
     pub fill_enabled: bool,
     pub border_enabled: bool,
+    pub fill: FillOptions,
+    pub eyedropper: EyedropperSettings,
 }
 
 impl Default for BrushSettings {
@@ -64,6 +72,7 @@ impl Default for BrushSettings {
         Self {
             size: 10.0,
             opacity: 1.0,
+            flow: 1.0,
             hardness: 1.0,
             spacing: 0.1,
             primary_color: egui::Color32::BLACK,
@@ -71,6 +80,8 @@ impl Default for BrushSettings {
             border_width: 2.0,
             fill_enabled: true,
             border_enabled: true,
+            fill: FillOptions::default(),
+            eyedropper: EyedropperSettings::default(),
         }
     }
 }
@@ -88,6 +99,11 @@ impl BrushSettings {
                 .text("Opacity"),
         );
         
+        ui.add(
+            egui::Slider::new(&mut self.flow, 0.0..=1.0)
+                .text("Flow"),
+        );
+        
         ui.add(
             egui::Slider::new(&mut self.hardness, 0.0..=1.0)
                 .text("Hardness"),
@@ -122,6 +138,37 @@ impl BrushSettings {
                     .suffix("px"),
             );
         }
+        
+        ui.separator();
+        
+        // Paint bucket
+        let mut tolerance = self.fill.tolerance * 100.0;
+        if ui.add(
+            egui::Slider::new(&mut tolerance, 0.0..=100.0)
+                .text("Tolerance")
+                .suffix("%"),
+        ).changed() {
+            self.fill.tolerance = tolerance / 100.0;
+        }
+        ui.checkbox(&mut self.fill.contiguous, "Contiguous");
+        ui.checkbox(&mut self.fill.anti_alias, "Anti-alias");
+        ui.checkbox(&mut self.fill.sample_merged, "Sample all layers");
+        
+        ui.separator();
+        
+        // Eyedropper
+        egui::ComboBox::from_label("Sample size")
+            .selected_text(self.eyedropper.size.name())
+            .show_ui(ui, |ui| {
+                for size in SampleSize::ALL {
+                    ui.selectable_value(&mut self.eyedropper.size, size, size.name());
+                }
+            });
+        ui.horizontal(|ui| {
+            ui.label("Sample:");
+            ui.radio_value(&mut self.eyedropper.sample_merged, false, "Current layer");
+            ui.radio_value(&mut self.eyedropper.sample_merged, true, "All layers");
+        });
     }
 }
 
@@ -133,6 +180,16 @@ pub trait ToolBehavior {
 }
 
 impl ToolType {
+    // Tools that drag out a shape and rasterise it on release
+    pub fn is_shape(&self) -> bool {
+        matches!(self, ToolType::Rectangle | ToolType::Circle | ToolType::Line)
+    }
+    
+    // Marquee, lasso and magic wand tools, which edit the selection instead of pixels
+    pub fn is_selection(&self) -> bool {
+        matches!(self, ToolType::RectSelect | ToolType::EllipseSelect | ToolType::Lasso | ToolType::PolygonLasso | ToolType::MagicWand)
+    }
+    
     pub fn name(&self) -> &'static str {
         match self {
             ToolType::Brush => "Brush",
@@ -145,6 +202,12 @@ impl ToolType {
             ToolType::Text => "Text",
             ToolType::Crop => "Crop",
             ToolType::Eyedropper => "Eyedropper",
+            ToolType::RectSelect => "Rectangle Select",
+            ToolType::EllipseSelect => "Ellipse Select",
+            ToolType::Lasso => "Lasso",
+            ToolType::PolygonLasso => "Polygonal Lasso",
+            ToolType::MagicWand => "Magic Wand",
+            ToolType::Move => "Move",
         }
     }
     
@@ -160,6 +223,12 @@ impl ToolType {
             ToolType::Text => "🔤",
             ToolType::Crop => "✂",
             ToolType::Eyedropper => "💧",
+            ToolType::RectSelect => "⬚",
+            ToolType::EllipseSelect => "◌",
+            ToolType::Lasso => "➰",
+            ToolType::PolygonLasso => "⬟",
+            ToolType::MagicWand => "✨",
+            ToolType::Move => "✋",
         }
     }
 }
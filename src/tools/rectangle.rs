@@ -1 +1,27 @@
+use eframe::egui;
+use crate::image_ops::shapes::Shape;
+
 pub struct RectangleTool;
+
+impl RectangleTool {
+    // Rect dragged out from `start` to `end`. `square` forces equal sides and
+    // `from_center` grows the rect out from `start` instead of from a corner.
+    pub fn drag_rect(start: egui::Pos2, end: egui::Pos2, square: bool, from_center: bool) -> egui::Rect {
+        let mut delta = end - start;
+        if square {
+            let side = delta.x.abs().max(delta.y.abs());
+            delta = egui::vec2(side * delta.x.signum(), side * delta.y.signum());
+        }
+        
+        if from_center {
+            egui::Rect::from_two_pos(start - delta, start + delta)
+        } else {
+            egui::Rect::from_two_pos(start, start + delta)
+        }
+    }
+    
+    // Shift constrains to a square, Alt draws from the centre
+    pub fn shape(start: egui::Pos2, end: egui::Pos2, modifiers: &egui::Modifiers) -> Shape {
+        Shape::Rectangle(Self::drag_rect(start, end, modifiers.shift, modifiers.alt))
+    }
+}
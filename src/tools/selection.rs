@@ -0,0 +1,313 @@
+use eframe::egui;
+use crate::image_ops::ImageData;
+use crate::image_ops::fill::FillOptions;
+use crate::image_ops::selection::{SelectionMask, SelectionMode, SelectionShape};
+use crate::tools::ToolType;
+
+// How close in screen pixels a click must be to the first point to close
+// a polygonal lasso
+const CLOSE_DISTANCE: f32 = 8.0;
+// Length of each dash of the marching ants, in screen pixels
+const ANT_LENGTH: f32 = 4.0;
+
+// Marquee, lasso and magic wand tools. The outline being drawn is kept
+// here and turned into a selection mask on the document when it is finished.
+pub struct SelectionTool {
+    // Used when no modifier key is held
+    pub mode: SelectionMode,
+    // Magic wand options, kept apart from the paint bucket's
+    pub wand: FillOptions,
+    // Image-space points of the outline in progress. Marquees use the
+    // first and last, lassos all of them.
+    points: Vec<egui::Pos2>,
+    // Mode picked when the outline was started
+    active_mode: SelectionMode,
+    // Pointer position, for the polygonal lasso's rubber band
+    hover: Option<egui::Pos2>,
+}
+
+impl Default for SelectionTool {
+    fn default() -> Self {
+        Self {
+            mode: SelectionMode::Replace,
+            wand: FillOptions::default(),
+            points: Vec::new(),
+            active_mode: SelectionMode::Replace,
+            hover: None,
+        }
+    }
+}
+
+impl SelectionTool {
+    pub fn is_drawing(&self) -> bool {
+        !self.points.is_empty()
+    }
+    
+    pub fn cancel(&mut self) {
+        self.points.clear();
+    }
+    
+    // Pointer handling on the canvas. Shift adds to the selection, Alt
+    // subtracts and both intersect. Returns true if the selection changed.
+    pub fn handle_input(
+        &mut self,
+        tool_type: &ToolType,
+        response: &egui::Response,
+        origin: egui::Pos2,
+        zoom: f32,
+        image_data: &mut ImageData,
+    ) -> bool {
+        let to_image = |pos: egui::Pos2| egui::pos2((pos.x - origin.x) / zoom, (pos.y - origin.y) / zoom);
+        let modifiers = response.ctx.input(|i| i.modifiers);
+        let pointer = response.interact_pointer_pos().map(to_image);
+        self.hover = response.hover_pos().map(to_image);
+        response.ctx.set_cursor_icon(egui::CursorIcon::Crosshair);
+        
+        if *tool_type == ToolType::PolygonLasso {
+            return self.handle_polygon_input(response, pointer, origin, zoom, &modifiers, image_data);
+        }
+        
+        if *tool_type == ToolType::MagicWand {
+            let pos = match pointer.filter(|_| response.clicked_by(egui::PointerButton::Primary)) {
+                Some(pos) => pos,
+                None => return false,
+            };
+            self.active_mode = SelectionMode::from_modifiers(&modifiers).unwrap_or(self.mode);
+            let size = egui::vec2(image_data.width() as f32, image_data.height() as f32);
+            if pos.x < 0.0 || pos.y < 0.0 || pos.x >= size.x || pos.y >= size.y {
+                return self.click(image_data);
+            }
+            image_data.magic_wand(pos.x as u32, pos.y as u32, &self.wand, self.active_mode);
+            return true;
+        }
+        
+        if response.drag_started_by(egui::PointerButton::Primary) {
+            if let Some(pos) = pointer {
+                self.start(pos, &modifiers);
+            }
+        }
+        
+        if let Some(pos) = pointer.filter(|_| response.dragged_by(egui::PointerButton::Primary) && self.is_drawing()) {
+            if *tool_type == ToolType::Lasso {
+                // Skip points closer than a screen pixel to the last one
+                if self.points.last().is_none_or(|last| last.distance(pos) * zoom >= 1.0) {
+                    self.points.push(pos);
+                }
+            } else {
+                self.points.truncate(1);
+                self.points.push(pos);
+            }
+        }
+        
+        if response.drag_stopped() && self.is_drawing() {
+            return match self.current_shape(tool_type) {
+                Some(shape) => {
+                    image_data.select(&shape, self.active_mode);
+                    self.points.clear();
+                    true
+                }
+                None => self.click(image_data),
+            };
+        }
+        
+        if response.clicked_by(egui::PointerButton::Primary) {
+            self.active_mode = SelectionMode::from_modifiers(&modifiers).unwrap_or(self.mode);
+            return self.click(image_data);
+        }
+        
+        false
+    }
+    
+    // Polygonal lasso: each click adds a corner. Clicking the first corner,
+    // double-clicking or Enter closes the outline and Escape abandons it.
+    fn handle_polygon_input(
+        &mut self,
+        response: &egui::Response,
+        pointer: Option<egui::Pos2>,
+        origin: egui::Pos2,
+        zoom: f32,
+        modifiers: &egui::Modifiers,
+        image_data: &mut ImageData,
+    ) -> bool {
+        let keys = if response.ctx.wants_keyboard_input() {
+            (false, false)
+        } else {
+            response.ctx.input(|i| (i.key_pressed(egui::Key::Enter), i.key_pressed(egui::Key::Escape)))
+        };
+        if keys.1 {
+            self.cancel();
+            return false;
+        }
+        
+        let mut close = keys.0 || (response.double_clicked() && self.is_drawing());
+        if !close && response.clicked_by(egui::PointerButton::Primary) {
+            if let Some(pos) = pointer {
+                let first = self.points.first().map(|first| origin + first.to_vec2() * zoom);
+                let screen = origin + pos.to_vec2() * zoom;
+                if self.points.len() >= 3 && first.is_some_and(|first| first.distance(screen) <= CLOSE_DISTANCE) {
+                    close = true;
+                } else if self.is_drawing() {
+                    self.points.push(pos);
+                } else {
+                    self.start(pos, modifiers);
+                }
+            }
+        }
+        
+        if close && self.is_drawing() {
+            let shape = self.current_shape(&ToolType::PolygonLasso);
+            self.points.clear();
+            if let Some(shape) = shape {
+                image_data.select(&shape, self.active_mode);
+                return true;
+            }
+        }
+        false
+    }
+    
+    fn start(&mut self, pos: egui::Pos2, modifiers: &egui::Modifiers) {
+        self.active_mode = SelectionMode::from_modifiers(modifiers).unwrap_or(self.mode);
+        self.points.clear();
+        self.points.push(pos);
+    }
+    
+    // A click without dragging out a shape drops the selection, unless a
+    // modifier asked to combine with it
+    fn click(&mut self, image_data: &mut ImageData) -> bool {
+        self.points.clear();
+        if self.active_mode == SelectionMode::Replace && image_data.selection().is_some() {
+            image_data.deselect();
+            return true;
+        }
+        false
+    }
+    
+    // Outline drawn so far, if it is big enough to select anything
+    fn current_shape(&self, tool_type: &ToolType) -> Option<SelectionShape> {
+        match tool_type {
+            ToolType::RectSelect | ToolType::EllipseSelect => {
+                let (start, end) = (*self.points.first()?, *self.points.last()?);
+                // Marquees snap to whole pixels
+                let rect = egui::Rect::from_two_pos(start.round(), end.round());
+                if rect.width() < 1.0 || rect.height() < 1.0 {
+                    return None;
+                }
+                Some(if *tool_type == ToolType::RectSelect {
+                    SelectionShape::Rectangle(rect)
+                } else {
+                    SelectionShape::Ellipse(rect)
+                })
+            }
+            ToolType::Lasso | ToolType::PolygonLasso if self.points.len() >= 3 => {
+                Some(SelectionShape::Polygon(self.points.clone()))
+            }
+            _ => None,
+        }
+    }
+    
+    // Outline being drawn, as a dashed line over the image
+    pub fn draw_overlay(&self, painter: &egui::Painter, tool_type: &ToolType, origin: egui::Pos2, zoom: f32, time: f64) {
+        let to_screen = |pos: egui::Pos2| origin + pos.to_vec2() * zoom;
+        
+        let path: Vec<egui::Pos2> = match (tool_type, self.current_shape(tool_type)) {
+            (_, Some(SelectionShape::Rectangle(rect))) => {
+                let rect = egui::Rect::from_min_max(to_screen(rect.min), to_screen(rect.max));
+                vec![rect.left_top(), rect.right_top(), rect.right_bottom(), rect.left_bottom(), rect.left_top()]
+            }
+            (_, Some(SelectionShape::Ellipse(rect))) => {
+                let center = to_screen(rect.center());
+                let radius = rect.size() * zoom * 0.5;
+                (0..=64)
+                    .map(|i| {
+                        let angle = i as f32 / 64.0 * std::f32::consts::TAU;
+                        center + egui::vec2(angle.cos() * radius.x, angle.sin() * radius.y)
+                    })
+                    .collect()
+            }
+            (ToolType::Lasso | ToolType::PolygonLasso, _) => {
+                let mut path: Vec<egui::Pos2> = self.points.iter().map(|pos| to_screen(*pos)).collect();
+                if *tool_type == ToolType::PolygonLasso && !path.is_empty() {
+                    if let Some(hover) = self.hover {
+                        path.push(to_screen(hover));
+                    }
+                }
+                path
+            }
+            _ => return,
+        };
+        
+        if path.len() >= 2 {
+            draw_ants(painter, &path, time);
+        }
+        // Corner handles for the polygonal lasso, the first one marking where to close
+        if *tool_type == ToolType::PolygonLasso {
+            for (i, point) in self.points.iter().enumerate() {
+                let size = if i == 0 { 7.0 } else { 5.0 };
+                let handle = egui::Rect::from_center_size(to_screen(*point), egui::Vec2::splat(size));
+                painter.rect_filled(handle, 0.0, egui::Color32::WHITE);
+                painter.rect_stroke(handle, 0.0, egui::Stroke::new(1.0, egui::Color32::BLACK));
+            }
+        }
+    }
+    
+    pub fn show_ui(&mut self, ui: &mut egui::Ui, tool_type: &ToolType, image_data: Option<&mut ImageData>) {
+        ui.horizontal_wrapped(|ui| {
+            for mode in SelectionMode::ALL {
+                ui.selectable_value(&mut self.mode, mode, mode.name());
+            }
+        });
+        ui.label("Shift: add, Alt: subtract, Shift+Alt: intersect");
+        if *tool_type == ToolType::PolygonLasso {
+            ui.label("Click to add corners, click the first corner, double-click or press Enter to close");
+        }
+        
+        if *tool_type == ToolType::MagicWand {
+            let mut tolerance = self.wand.tolerance * 100.0;
+            if ui.add(
+                egui::Slider::new(&mut tolerance, 0.0..=100.0)
+                    .text("Tolerance")
+                    .suffix("%"),
+            ).changed() {
+                self.wand.tolerance = tolerance / 100.0;
+            }
+            ui.checkbox(&mut self.wand.contiguous, "Contiguous");
+            ui.checkbox(&mut self.wand.anti_alias, "Anti-alias");
+            ui.checkbox(&mut self.wand.sample_merged, "Sample all layers");
+        }
+        
+        if let Some(image_data) = image_data {
+            if let Some(bounds) = image_data.selection().and_then(|selection| selection.bounds()) {
+                ui.label(format!("Selection: {} x {} px at {}, {}", bounds.width, bounds.height, bounds.x, bounds.y));
+                if ui.button("Deselect (Ctrl+D)").clicked() {
+                    image_data.deselect();
+                }
+            }
+        }
+    }
+}
+
+// Animated black and white dashes along the selection edge. `origin` is
+// the screen position of the image's top-left corner.
+pub fn draw_marching_ants(painter: &egui::Painter, selection: &SelectionMask, origin: egui::Pos2, zoom: f32, time: f64) {
+    let clip = painter.clip_rect();
+    for [from, to] in selection.outline() {
+        let segment = [origin + from.to_vec2() * zoom, origin + to.to_vec2() * zoom];
+        if clip.intersects(egui::Rect::from_two_pos(segment[0], segment[1])) {
+            draw_ants(painter, &segment, time);
+        }
+    }
+}
+
+fn draw_ants(painter: &egui::Painter, path: &[egui::Pos2], time: f64) {
+    painter.add(egui::Shape::line(path.to_vec(), egui::Stroke::new(1.0, egui::Color32::WHITE)));
+    // Dashes line up across segments because the offset follows the position
+    let start = path[0];
+    let offset = ((time * 8.0) as f32 + start.x + start.y).rem_euclid(ANT_LENGTH * 2.0);
+    painter.extend(egui::Shape::dashed_line_with_offset(
+        path,
+        egui::Stroke::new(1.0, egui::Color32::BLACK),
+        &[ANT_LENGTH],
+        &[ANT_LENGTH],
+        offset,
+    ));
+}
@@ -1 +1,205 @@
-pub struct TextTool;
+use std::path::Path;
+use eframe::egui;
+use crate::image_ops::{ImageData, ImageHistory};
+use crate::image_ops::image_data::LayerId;
+use crate::image_ops::text::{TextAlign, TextLayer};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextAction {
+    None,
+    Changed,
+    LoadFont,
+}
+
+#[derive(Default)]
+pub struct TextTool {
+    // Style for new text layers, following whatever was last edited
+    pub template: TextLayer,
+    pub error: Option<String>,
+    // Text layer with an "Edit text" step open in the history. Typing and
+    // slider drags all go into that one step, which is committed once the
+    // editor loses focus or the drag ends.
+    editing: Option<LayerId>,
+}
+
+impl TextTool {
+    // Selects the text layer under `pos`, or adds a new one there with the
+    // current style. Returns true if the document changed.
+    pub fn handle_click(&mut self, pos: egui::Pos2, color: egui::Color32, image_data: &mut ImageData, image_history: &mut ImageHistory) -> bool {
+        self.finish_edit(image_data, image_history);
+        
+        let hit = (0..image_data.layers.len())
+            .rev()
+            .find(|&i| image_data.layers[i].text().is_some_and(|text| text.bounds().contains(pos)));
+        if let Some(index) = hit {
+            image_data.active_layer = index;
+            return false;
+        }
+        
+        let mut text = self.template.clone();
+        text.x = pos.x;
+        text.y = pos.y;
+        text.color = color.to_srgba_unmultiplied();
+        image_history.record("Add text layer", image_data, |image_data| image_data.add_text_layer(text));
+        true
+    }
+    
+    // Outlines the active text layer's box
+    pub fn draw_overlay(&self, painter: &egui::Painter, image_data: &ImageData, origin: egui::Pos2, zoom: f32) {
+        if let Some(text) = image_data.get_active_layer().text() {
+            let bounds = text.bounds();
+            if bounds.is_positive() {
+                let rect = egui::Rect::from_min_max(origin + bounds.min.to_vec2() * zoom, origin + bounds.max.to_vec2() * zoom);
+                painter.rect_stroke(rect.expand(2.0), 0.0, egui::Stroke::new(1.0, egui::Color32::from_rgb(0, 120, 215)));
+            }
+        }
+    }
+    
+    // Editor for the active text layer, or for the style of new ones when
+    // the active layer isn't text
+    pub fn show_ui(&mut self, ui: &mut egui::Ui, image_data: Option<&mut ImageData>, image_history: &mut ImageHistory) -> TextAction {
+        let mut action = TextAction::None;
+        
+        let mut image_data = image_data;
+        if let Some(image_data) = image_data.as_deref_mut() {
+            if self.editing.is_some_and(|id| id != image_data.get_active_layer().id()) {
+                self.finish_edit(image_data, image_history);
+            }
+        }
+        
+        let image_data = image_data.filter(|image_data| image_data.get_active_layer().text().is_some());
+        match image_data {
+            Some(image_data) => {
+                let index = image_data.active_layer;
+                let mut text = match image_data.get_active_layer().text() {
+                    Some(text) => text.clone(),
+                    None => return action,
+                };
+                
+                let editor = ui.add(
+                    egui::TextEdit::multiline(&mut text.text)
+                        .desired_rows(3)
+                        .desired_width(f32::INFINITY),
+                );
+                if Self::style_ui(ui, &mut text, true) {
+                    action = TextAction::LoadFont;
+                }
+                
+                if Some(&text) != image_data.get_active_layer().text() {
+                    self.template.size = text.size;
+                    self.template.align = text.align;
+                    self.template.line_spacing = text.line_spacing;
+                    if self.editing.is_none() {
+                        image_history.begin("Edit text", image_data);
+                        self.editing = Some(image_data.get_active_layer().id());
+                    }
+                    image_data.set_text(index, text);
+                    action = TextAction::Changed;
+                }
+                
+                if !editor.has_focus() && ui.ctx().dragged_id().is_none() {
+                    self.finish_edit(image_data, image_history);
+                }
+                
+                if ui.button("Rasterize Layer").clicked() {
+                    self.finish_edit(image_data, image_history);
+                    image_history.record("Rasterize layer", image_data, |image_data| image_data.rasterize_layer(index));
+                    action = TextAction::Changed;
+                }
+            }
+            None => {
+                ui.label("Click on the image to add text");
+                if Self::style_ui(ui, &mut self.template, false) {
+                    action = TextAction::LoadFont;
+                }
+            }
+        }
+        
+        if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+        
+        action
+    }
+    
+    // Commits the open text edit as one undo step
+    pub fn finish_edit(&mut self, image_data: &ImageData, image_history: &mut ImageHistory) {
+        if self.editing.take().is_some() {
+            image_history.commit(image_data);
+        }
+    }
+    
+    // Forgets the open text edit, for when its history has been reset
+    pub fn close(&mut self) {
+        self.editing = None;
+    }
+    
+    // Returns true if the user asked to load a font file
+    fn style_ui(ui: &mut egui::Ui, text: &mut TextLayer, show_color: bool) -> bool {
+        let mut load_font = false;
+        
+        ui.horizontal(|ui| {
+            ui.label(format!("Font: {}", text.font_name()));
+            if ui.button("Load Font...").clicked() {
+                load_font = true;
+            }
+        });
+        
+        ui.add(
+            egui::Slider::new(&mut text.size, 4.0..=400.0)
+                .text("Size")
+                .suffix("px")
+                .logarithmic(true),
+        );
+        
+        ui.add(
+            egui::Slider::new(&mut text.line_spacing, 0.5..=3.0)
+                .text("Line Spacing"),
+        );
+        
+        ui.horizontal(|ui| {
+            for align in TextAlign::ALL {
+                ui.selectable_value(&mut text.align, align, align.name());
+            }
+        });
+        
+        if show_color {
+            ui.horizontal(|ui| {
+                ui.label("Color:");
+                let [r, g, b, a] = text.color;
+                let mut color = egui::Color32::from_rgba_unmultiplied(r, g, b, a);
+                if ui.color_edit_button_srgba(&mut color).changed() {
+                    text.color = color.to_srgba_unmultiplied();
+                }
+            });
+        }
+        
+        load_font
+    }
+    
+    // Uses the font at `path` for new text and for the active text layer
+    pub fn load_font(&mut self, path: &Path, image_data: Option<&mut ImageData>, image_history: &mut ImageHistory) -> bool {
+        if let Err(e) = self.template.set_font(path) {
+            log::error!("Failed to load font: {}", e);
+            self.error = Some(e.to_string());
+            return false;
+        }
+        self.error = None;
+        
+        let image_data = match image_data {
+            Some(image_data) => image_data,
+            None => return false,
+        };
+        self.finish_edit(image_data, image_history);
+        let index = image_data.active_layer;
+        let mut text = match image_data.get_active_layer().text() {
+            Some(text) => text.clone(),
+            None => return false,
+        };
+        
+        text.font = self.template.font.clone();
+        text.font_path = self.template.font_path.clone();
+        image_history.record("Change font", image_data, |image_data| image_data.set_text(index, text));
+        true
+    }
+}
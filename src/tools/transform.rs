@@ -0,0 +1,603 @@
+use eframe::egui;
+use crate::image_ops::{ImageData, ImageHistory};
+use crate::image_ops::dirty::PixelRect;
+use crate::image_ops::selection::SelectionMask;
+use crate::image_ops::tiles::TiledImage;
+use crate::image_ops::transform::{self, Resampling};
+
+// Screen-space size of the handles and how close the pointer must be to grab one
+const HANDLE_SIZE: f32 = 8.0;
+const HANDLE_GRAB_DISTANCE: f32 = 8.0;
+// Shift snaps rotation to multiples of this many degrees
+const ROTATION_SNAP: f32 = 15.0;
+
+// What dragging the handles of a free transform does
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransformMode {
+    // Corners and edges scale, Shift keeps the proportions
+    Free,
+    // Handles slide along the edge they sit on
+    Skew,
+    // Corners and edges move anywhere
+    Distort,
+    // Moving a corner moves its neighbour the opposite way
+    Perspective,
+}
+
+impl TransformMode {
+    pub const ALL: [TransformMode; 4] = [TransformMode::Free, TransformMode::Skew, TransformMode::Distort, TransformMode::Perspective];
+    
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransformMode::Free => "Scale",
+            TransformMode::Skew => "Skew",
+            TransformMode::Distort => "Distort",
+            TransformMode::Perspective => "Perspective",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransformAction {
+    None,
+    Commit,
+    Cancel,
+}
+
+// Part of the transform box under the pointer. Edge `i` runs from corner
+// `i` to the next one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Handle {
+    Corner(usize),
+    Edge(usize),
+    Move,
+    Rotate,
+}
+
+struct TransformDrag {
+    handle: Handle,
+    start_pos: egui::Pos2,
+    start_corners: [egui::Pos2; 4],
+}
+
+// Pixels lifted off a layer while they are being transformed
+struct Floating {
+    layer: usize,
+    pixels: TiledImage,
+    // Where the pixels were lifted from
+    area: PixelRect,
+    // The layer without them, which each render starts from
+    background: TiledImage,
+    selection: Option<SelectionMask>,
+}
+
+// Free Transform and the Move tool. The selected pixels, or the whole
+// layer when nothing is selected, are lifted off the active layer and
+// drawn stretched onto a box whose corners follow the handles. The whole
+// transform is one undo step.
+pub struct TransformTool {
+    pub mode: TransformMode,
+    pub resampling: Resampling,
+    floating: Option<Floating>,
+    // Image-space corners of the box: top-left, top-right, bottom-right, bottom-left
+    corners: [egui::Pos2; 4],
+    drag: Option<TransformDrag>,
+    // Corners and resampling last drawn onto the layer
+    rendered: Option<([egui::Pos2; 4], Resampling)>,
+    // Move tool drags only translate, and commit on release
+    move_only: bool,
+    // Layer being dragged by the Move tool without a selection, and the
+    // image-space position the drag started from
+    layer_drag: Option<(usize, egui::Pos2)>,
+}
+
+impl Default for TransformTool {
+    fn default() -> Self {
+        Self {
+            mode: TransformMode::Free,
+            resampling: Resampling::Bilinear,
+            floating: None,
+            corners: [egui::Pos2::ZERO; 4],
+            drag: None,
+            rendered: None,
+            move_only: false,
+            layer_drag: None,
+        }
+    }
+}
+
+impl TransformTool {
+    pub fn is_active(&self) -> bool {
+        self.floating.is_some()
+    }
+    
+    // Lifts the selected pixels of the active layer, or all of it, and puts
+    // a transform box around them. Returns false if there was nothing to lift.
+    pub fn begin(&mut self, image_data: &mut ImageData, image_history: &mut ImageHistory, label: &str) -> bool {
+        if self.is_active() {
+            return true;
+        }
+        let (area, pixels) = match image_data.lift_selection() {
+            Some(lifted) => lifted,
+            None => return false,
+        };
+        
+        image_history.begin(label, image_data);
+        // Transformed text can't stay editable
+        image_data.rasterize_layer(image_data.active_layer);
+        image_data.clear_selected_pixels();
+        let selection = image_data.selection().cloned();
+        // The box stands in for the marching ants until the transform is done
+        image_data.set_selection(None);
+        
+        let rect = egui::Rect::from_min_size(
+            egui::pos2(area.x as f32, area.y as f32),
+            egui::vec2(area.width as f32, area.height as f32),
+        );
+        self.corners = [rect.left_top(), rect.right_top(), rect.right_bottom(), rect.left_bottom()];
+        self.floating = Some(Floating {
+            layer: image_data.active_layer,
+            pixels: TiledImage::from_image(&pixels),
+            area,
+            background: image_data.get_active_layer().data.clone(),
+            selection,
+        });
+        self.drag = None;
+        self.rendered = None;
+        self.move_only = false;
+        true
+    }
+    
+    // Move tool: dragging moves the selected pixels, or the whole active
+    // layer when nothing is selected. Returns true if the document changed.
+    pub fn handle_move_input(
+        &mut self,
+        response: &egui::Response,
+        origin: egui::Pos2,
+        zoom: f32,
+        image_data: &mut ImageData,
+        image_history: &mut ImageHistory,
+    ) -> bool {
+        let to_image = |pos: egui::Pos2| egui::pos2((pos.x - origin.x) / zoom, (pos.y - origin.y) / zoom);
+        let pointer = response.interact_pointer_pos().map(to_image);
+        response.ctx.set_cursor_icon(egui::CursorIcon::Move);
+        
+        if response.drag_started_by(egui::PointerButton::Primary) {
+            if let Some(pos) = pointer {
+                if image_data.selection().is_some() {
+                    if self.begin(image_data, image_history, "Move") {
+                        self.move_only = true;
+                        return self.handle_input(response, origin, zoom, image_data, image_history);
+                    }
+                } else {
+                    image_history.begin("Move", image_data);
+                    self.layer_drag = Some((image_data.active_layer, pos));
+                }
+            }
+        }
+        
+        let (index, start) = match self.layer_drag {
+            Some(layer_drag) => layer_drag,
+            None => return false,
+        };
+        if response.ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
+            self.cancel(image_data, image_history);
+            return false;
+        }
+        if let Some(pos) = pointer.filter(|_| response.dragged_by(egui::PointerButton::Primary)) {
+            let delta = (pos - start).round();
+            if let Some(layer) = image_data.layers.get_mut(index) {
+                layer.offset = (delta.x as i32, delta.y as i32);
+                image_data.mark_dirty();
+            }
+        }
+        if response.drag_stopped() {
+            self.layer_drag = None;
+            image_data.apply_layer_offset(index);
+            if let Some(layer) = image_data.layers.get_mut(index) {
+                layer.data.release_empty_tiles();
+            }
+            image_history.commit(image_data);
+            return true;
+        }
+        false
+    }
+    
+    // Pointer handling while the transform box is up. Returns true if the
+    // transform was committed, which a Move tool drag does on release.
+    pub fn handle_input(
+        &mut self,
+        response: &egui::Response,
+        origin: egui::Pos2,
+        zoom: f32,
+        image_data: &mut ImageData,
+        image_history: &mut ImageHistory,
+    ) -> bool {
+        let to_image = |pos: egui::Pos2| egui::pos2((pos.x - origin.x) / zoom, (pos.y - origin.y) / zoom);
+        let modifiers = response.ctx.input(|i| i.modifiers);
+        
+        if response.drag_started_by(egui::PointerButton::Primary) {
+            if let Some(pointer) = response.interact_pointer_pos() {
+                let handle = if self.move_only { Handle::Move } else { self.hit_test(pointer, origin, zoom) };
+                self.drag = Some(TransformDrag {
+                    handle,
+                    start_pos: to_image(pointer),
+                    start_corners: self.corners,
+                });
+            }
+        }
+        
+        if let (Some(drag), Some(pointer)) = (&self.drag, response.interact_pointer_pos()) {
+            if response.dragged_by(egui::PointerButton::Primary) {
+                if let Some(corners) = self.dragged_corners(drag, to_image(pointer), &modifiers) {
+                    self.corners = corners;
+                }
+            }
+        }
+        
+        if response.drag_stopped() && self.drag.take().is_some() && self.move_only {
+            return self.commit(image_data, image_history);
+        }
+        
+        let hover = self.drag.as_ref().map(|drag| drag.handle)
+            .or_else(|| response.hover_pos().map(|pos| self.hit_test(pos, origin, zoom)));
+        let icon = match hover {
+            _ if self.move_only => egui::CursorIcon::Move,
+            Some(Handle::Corner(_)) | Some(Handle::Edge(_)) => egui::CursorIcon::Crosshair,
+            Some(Handle::Move) => egui::CursorIcon::Move,
+            Some(Handle::Rotate) => egui::CursorIcon::Grab,
+            None => egui::CursorIcon::Default,
+        };
+        response.ctx.set_cursor_icon(icon);
+        false
+    }
+    
+    // Box corners for the pointer at `pos`, or None if they can't change
+    fn dragged_corners(&self, drag: &TransformDrag, pos: egui::Pos2, modifiers: &egui::Modifiers) -> Option<[egui::Pos2; 4]> {
+        let start = drag.start_corners;
+        let mut delta = pos - drag.start_pos;
+        let mut corners = start;
+        
+        match (drag.handle, self.mode) {
+            (Handle::Move, _) => {
+                if self.move_only {
+                    // Whole pixels keep moved pixels sharp
+                    delta = delta.round();
+                }
+                return Some(start.map(|corner| corner + delta));
+            }
+            (Handle::Rotate, _) => {
+                let center = quad_center(&start);
+                let from = drag.start_pos - center;
+                let to = pos - center;
+                let mut angle = (to.y.atan2(to.x) - from.y.atan2(from.x)).to_degrees();
+                if modifiers.shift {
+                    angle = (angle / ROTATION_SNAP).round() * ROTATION_SNAP;
+                }
+                let rotation = egui::emath::Rot2::from_angle(angle.to_radians());
+                return Some(start.map(|corner| center + rotation * (corner - center)));
+            }
+            (Handle::Corner(i), TransformMode::Free) => {
+                // Scale in the box's own frame, about the opposite corner
+                let frame = Frame::new(&start)?;
+                let anchor = frame.to_local(start[(i + 2) % 4]);
+                let from = frame.to_local(start[i]) - anchor;
+                let to = frame.to_local(start[i] + delta) - anchor;
+                let mut scale = egui::vec2(ratio(to.x, from.x), ratio(to.y, from.y));
+                if modifiers.shift {
+                    // Project onto the diagonal so both sides scale alike
+                    let uniform = to.dot(from) / from.length_sq().max(f32::EPSILON);
+                    scale = egui::Vec2::splat(uniform);
+                }
+                for corner in corners.iter_mut() {
+                    let local = frame.to_local(*corner) - anchor;
+                    *corner = frame.to_image(anchor + local * scale);
+                }
+            }
+            (Handle::Edge(i), TransformMode::Free) => {
+                // Scale across the edge, about the opposite one
+                let frame = Frame::new(&start)?;
+                let edge = frame.to_local(edge_midpoint(&start, i));
+                let anchor = frame.to_local(edge_midpoint(&start, (i + 2) % 4));
+                let to = frame.to_local(edge_midpoint(&start, i) + delta);
+                let scale = if i % 2 == 0 {
+                    egui::vec2(1.0, ratio(to.y - anchor.y, edge.y - anchor.y))
+                } else {
+                    egui::vec2(ratio(to.x - anchor.x, edge.x - anchor.x), 1.0)
+                };
+                for corner in corners.iter_mut() {
+                    let local = frame.to_local(*corner) - anchor;
+                    *corner = frame.to_image(anchor + local * scale);
+                }
+            }
+            (Handle::Edge(i), TransformMode::Skew | TransformMode::Perspective) => {
+                let direction = (start[(i + 1) % 4] - start[i]).normalized();
+                let slide = direction * delta.dot(direction);
+                corners[i] += slide;
+                corners[(i + 1) % 4] += slide;
+            }
+            (Handle::Edge(i), TransformMode::Distort) => {
+                corners[i] += delta;
+                corners[(i + 1) % 4] += delta;
+            }
+            (Handle::Corner(i), TransformMode::Skew | TransformMode::Perspective) => {
+                // Follow whichever of the corner's two edges the drag is closer to
+                let neighbour = [(i + 1) % 4, (i + 3) % 4]
+                    .into_iter()
+                    .max_by(|a, b| {
+                        let along = |n: usize| delta.dot((start[i] - start[n]).normalized()).abs();
+                        along(*a).total_cmp(&along(*b))
+                    })?;
+                let direction = (start[i] - start[neighbour]).normalized();
+                let slide = direction * delta.dot(direction);
+                corners[i] += slide;
+                if self.mode == TransformMode::Perspective {
+                    corners[neighbour] -= slide;
+                }
+            }
+            (Handle::Corner(i), TransformMode::Distort) => {
+                corners[i] += delta;
+            }
+        }
+        
+        transform::Homography::square_to_quad(corners).map(|_| corners)
+    }
+    
+    fn hit_test(&self, pos: egui::Pos2, origin: egui::Pos2, zoom: f32) -> Handle {
+        let screen = self.corners.map(|corner| origin + corner.to_vec2() * zoom);
+        for (i, corner) in screen.iter().enumerate() {
+            if corner.distance(pos) <= HANDLE_GRAB_DISTANCE {
+                return Handle::Corner(i);
+            }
+        }
+        for i in 0..4 {
+            if edge_midpoint(&screen, i).distance(pos) <= HANDLE_GRAB_DISTANCE {
+                return Handle::Edge(i);
+            }
+        }
+        if contains(&screen, pos) {
+            Handle::Move
+        } else {
+            Handle::Rotate
+        }
+    }
+    
+    // Draws the transformed pixels onto the layer if the box changed since
+    // last time. Drags use nearest neighbour to keep up with the pointer.
+    pub fn render(&mut self, image_data: &mut ImageData) {
+        let resampling = if self.drag.is_some() { Resampling::Nearest } else { self.resampling };
+        self.render_with(image_data, resampling);
+    }
+    
+    fn render_with(&mut self, image_data: &mut ImageData, resampling: Resampling) {
+        let floating = match &self.floating {
+            Some(floating) => floating,
+            None => return,
+        };
+        if self.rendered == Some((self.corners, resampling)) {
+            return;
+        }
+        let layer = match image_data.layers.get_mut(floating.layer) {
+            Some(layer) => layer,
+            None => return,
+        };
+        
+        layer.data = floating.background.clone();
+        transform::warp_onto(&mut layer.data, &floating.pixels, self.corners, resampling);
+        
+        // Only the area under the old and new box needs compositing again
+        let previous = self.rendered.map_or(self.corners, |(corners, _)| corners);
+        let points = self.corners.iter().chain(previous.iter());
+        let (min_x, min_y, max_x, max_y) = points.fold(
+            (f32::INFINITY, f32::INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+            |(min_x, min_y, max_x, max_y), p| (min_x.min(p.x), min_y.min(p.y), max_x.max(p.x), max_y.max(p.y)),
+        );
+        let area = PixelRect::from_bounds(
+            min_x.floor() as i64 - 1,
+            min_y.floor() as i64 - 1,
+            max_x.ceil() as i64 + 1,
+            max_y.ceil() as i64 + 1,
+            image_data.width(),
+            image_data.height(),
+        );
+        match self.rendered {
+            // The lifted pixels were cleared when the transform began
+            None => image_data.mark_dirty(),
+            Some(_) => {
+                if let Some(area) = area {
+                    image_data.mark_region_dirty(area);
+                }
+            }
+        }
+        self.rendered = Some((self.corners, resampling));
+    }
+    
+    // Finishes the transform as one undo step. The selection follows the
+    // pixels. Returns true if the document changed.
+    pub fn commit(&mut self, image_data: &mut ImageData, image_history: &mut ImageHistory) -> bool {
+        let resampling = if self.move_only { Resampling::Nearest } else { self.resampling };
+        self.render_with(image_data, resampling);
+        let floating = match self.floating.take() {
+            Some(floating) => floating,
+            None => return false,
+        };
+        
+        if let Some(selection) = &floating.selection {
+            image_data.set_selection(Some(selection.warped(floating.area, self.corners)));
+        }
+        if let Some(layer) = image_data.layers.get_mut(floating.layer) {
+            layer.data.release_empty_tiles();
+        }
+        image_history.commit(image_data);
+        self.close();
+        true
+    }
+    
+    // Drops the transform, or a Move tool layer drag, and puts the pixels
+    // back where they were
+    pub fn cancel(&mut self, image_data: &mut ImageData, image_history: &mut ImageHistory) {
+        if let Some((index, _)) = self.layer_drag {
+            if let Some(layer) = image_data.layers.get_mut(index) {
+                layer.offset = (0, 0);
+                image_data.mark_dirty();
+            }
+        }
+        if self.floating.is_some() || self.layer_drag.is_some() {
+            image_history.cancel(image_data);
+        }
+        self.close();
+    }
+    
+    // Forgets the transform without touching the document, for when the
+    // document itself is being replaced. Use `cancel` otherwise.
+    pub fn close(&mut self) {
+        self.floating = None;
+        self.drag = None;
+        self.rendered = None;
+        self.move_only = false;
+        self.layer_drag = None;
+    }
+    
+    // Outline of the box with its handles
+    pub fn draw_overlay(&self, painter: &egui::Painter, origin: egui::Pos2, zoom: f32) {
+        if !self.is_active() {
+            return;
+        }
+        let screen = self.corners.map(|corner| origin + corner.to_vec2() * zoom);
+        let mut outline = screen.to_vec();
+        outline.push(screen[0]);
+        painter.add(egui::Shape::line(outline.clone(), egui::Stroke::new(3.0, egui::Color32::from_black_alpha(160))));
+        painter.add(egui::Shape::line(outline, egui::Stroke::new(1.0, egui::Color32::WHITE)));
+        
+        if self.move_only {
+            return;
+        }
+        let handles = screen.iter().copied().chain((0..4).map(|i| edge_midpoint(&screen, i)));
+        for handle in handles {
+            let handle = egui::Rect::from_center_size(handle, egui::Vec2::splat(HANDLE_SIZE));
+            painter.rect_filled(handle, 0.0, egui::Color32::WHITE);
+            painter.rect_stroke(handle, 0.0, egui::Stroke::new(1.0, egui::Color32::BLACK));
+        }
+        let center = quad_center(&screen);
+        painter.circle_stroke(center, 3.0, egui::Stroke::new(1.0, egui::Color32::WHITE));
+    }
+    
+    // Mode, resampling and commit/cancel buttons for the side panel
+    pub fn show_ui(&mut self, ui: &mut egui::Ui) -> TransformAction {
+        let mut action = TransformAction::None;
+        
+        ui.horizontal_wrapped(|ui| {
+            for mode in TransformMode::ALL {
+                ui.selectable_value(&mut self.mode, mode, mode.name());
+            }
+        });
+        egui::ComboBox::from_label("Resampling")
+            .selected_text(self.resampling.name())
+            .show_ui(ui, |ui| {
+                for resampling in Resampling::ALL {
+                    ui.selectable_value(&mut self.resampling, resampling, resampling.name());
+                }
+            });
+        
+        if self.is_active() && !self.move_only {
+            ui.label(match self.mode {
+                TransformMode::Free => "Drag corners or edges to scale, Shift keeps proportions",
+                TransformMode::Skew => "Drag corners or edges along the box to skew",
+                TransformMode::Distort => "Drag corners or edges anywhere",
+                TransformMode::Perspective => "Drag corners to taper the box",
+            });
+            ui.label("Drag inside to move, outside to rotate, Shift snaps to 15°");
+            ui.horizontal(|ui| {
+                if ui.button("Apply (Enter)").clicked() {
+                    action = TransformAction::Commit;
+                }
+                if ui.button("Cancel (Esc)").clicked() {
+                    action = TransformAction::Cancel;
+                }
+            });
+        } else {
+            ui.label("Edit > Free Transform (Ctrl+T) puts a box around the selection or layer");
+        }
+        
+        action
+    }
+}
+
+// Axes of a transform box, from its top-left corner along its top and left
+// edges, so scaling follows the box when it is rotated
+struct Frame {
+    origin: egui::Pos2,
+    x_axis: egui::Vec2,
+    y_axis: egui::Vec2,
+}
+
+impl Frame {
+    fn new(corners: &[egui::Pos2; 4]) -> Option<Self> {
+        let x_axis = corners[1] - corners[0];
+        let y_axis = corners[3] - corners[0];
+        if (x_axis.x * y_axis.y - x_axis.y * y_axis.x).abs() < f32::EPSILON {
+            return None;
+        }
+        Some(Self { origin: corners[0], x_axis, y_axis })
+    }
+    
+    fn to_local(&self, pos: egui::Pos2) -> egui::Vec2 {
+        let d = pos - self.origin;
+        let det = self.x_axis.x * self.y_axis.y - self.x_axis.y * self.y_axis.x;
+        egui::vec2(
+            (d.x * self.y_axis.y - d.y * self.y_axis.x) / det,
+            (self.x_axis.x * d.y - self.x_axis.y * d.x) / det,
+        )
+    }
+    
+    fn to_image(&self, local: egui::Vec2) -> egui::Pos2 {
+        self.origin + self.x_axis * local.x + self.y_axis * local.y
+    }
+}
+
+// `to / from`, or 1 when `from` is too small to divide by
+fn ratio(to: f32, from: f32) -> f32 {
+    if from.abs() < f32::EPSILON { 1.0 } else { to / from }
+}
+
+fn edge_midpoint(corners: &[egui::Pos2; 4], edge: usize) -> egui::Pos2 {
+    corners[edge].lerp(corners[(edge + 1) % 4], 0.5)
+}
+
+fn quad_center(corners: &[egui::Pos2; 4]) -> egui::Pos2 {
+    let sum = corners.iter().fold(egui::Vec2::ZERO, |sum, corner| sum + corner.to_vec2());
+    (sum / 4.0).to_pos2()
+}
+
+// Even-odd test, so it also works for boxes distorted out of convexity
+fn contains(corners: &[egui::Pos2; 4], pos: egui::Pos2) -> bool {
+    let mut inside = false;
+    for i in 0..4 {
+        let (a, b) = (corners[i], corners[(i + 1) % 4]);
+        if (a.y > pos.y) != (b.y > pos.y) && pos.x < a.x + (pos.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn cancelling_a_layer_drag_puts_the_layer_back() {
+        let mut image_data = ImageData::new(8, 8);
+        let mut image_history = ImageHistory::new();
+        let mut tool = TransformTool::default();
+        
+        image_history.begin("Move", &image_data);
+        tool.layer_drag = Some((0, egui::pos2(1.0, 1.0)));
+        image_data.layers[0].offset = (3, 2);
+        
+        tool.cancel(&mut image_data, &mut image_history);
+        assert_eq!(image_data.layers[0].offset, (0, 0));
+        assert!(tool.layer_drag.is_none());
+        assert!(!image_history.in_transaction());
+    }
+}
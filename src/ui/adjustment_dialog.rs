@@ -0,0 +1,494 @@
+use std::path::Path;
+use eframe::egui;
+use crate::image_ops::{ImageData, ImageHistory};
+use crate::image_ops::adjustments::{Adjustment, Channel, ColorBalance, Curves, Levels};
+use crate::image_ops::histogram::Histogram;
+use crate::image_ops::tiles::TiledImage;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdjustmentAction {
+    None,
+    Committed,
+    SavePreset,
+    LoadPreset,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ToneRange {
+    Shadows,
+    Midtones,
+    Highlights,
+}
+
+// Dialog for Image > Adjustments. The active layer shows the adjustment
+// live while the dialog is open. OK records it as one undo step and Cancel
+// puts the layer back. The app locks the canvas and layers meanwhile, so
+// nothing else ends up in that step.
+pub struct AdjustmentDialog {
+    adjustment: Option<Adjustment>,
+    // Layer being adjusted and its pixels before the dialog opened
+    target: Option<(usize, TiledImage)>,
+    preview: bool,
+    // What the layer currently shows, to only recompute on changes
+    previewed: Option<(Adjustment, bool)>,
+    tone_range: ToneRange,
+    // Channel being edited in Levels and Curves
+    channel: Channel,
+    // Histogram of the layer before adjusting, for Levels and Curves
+    histogram: Option<Histogram>,
+    // Curve point being dragged
+    dragged_point: Option<usize>,
+    pub error: Option<String>,
+}
+
+impl Default for AdjustmentDialog {
+    fn default() -> Self {
+        Self {
+            adjustment: None,
+            target: None,
+            preview: true,
+            previewed: None,
+            tone_range: ToneRange::Midtones,
+            channel: Channel::Rgb,
+            histogram: None,
+            dragged_point: None,
+            error: None,
+        }
+    }
+}
+
+impl AdjustmentDialog {
+    // Starts adjusting the active layer, abandoning any adjustment in progress
+    pub fn open(&mut self, adjustment: Adjustment, image_data: &mut ImageData, image_history: &mut ImageHistory) {
+        if self.is_open() {
+            self.cancel(image_data, image_history);
+        }
+        
+        image_history.begin(adjustment.name(), image_data);
+        let index = image_data.active_layer;
+        let source = image_data.layers[index].data.clone();
+        self.histogram = match adjustment {
+            Adjustment::Levels(_) | Adjustment::Curves(_) => Some(Histogram::of(&source)),
+            _ => None,
+        };
+        self.target = Some((index, source));
+        self.channel = Channel::Rgb;
+        self.dragged_point = None;
+        self.error = None;
+        self.previewed = Some((adjustment.identity(), self.preview));
+        self.adjustment = Some(adjustment);
+    }
+    
+    pub fn is_open(&self) -> bool {
+        self.adjustment.is_some()
+    }
+    
+    // Forgets the dialog without touching the document, e.g. when another
+    // document replaces it
+    pub fn close(&mut self) {
+        self.adjustment = None;
+        self.target = None;
+        self.previewed = None;
+        self.histogram = None;
+    }
+    
+    // File extension of presets for the open adjustment, if it has them
+    pub fn preset_extension(&self) -> Option<&'static str> {
+        match self.adjustment {
+            Some(Adjustment::Levels(_)) => Some(Levels::PRESET_EXTENSION),
+            Some(Adjustment::Curves(_)) => Some(Curves::PRESET_EXTENSION),
+            _ => None,
+        }
+    }
+    
+    pub fn save_preset(&mut self, path: &Path) {
+        let text = match &self.adjustment {
+            Some(Adjustment::Levels(levels)) => levels.to_preset(),
+            Some(Adjustment::Curves(curves)) => curves.to_preset(),
+            _ => return,
+        };
+        
+        let mut path = path.to_path_buf();
+        if path.extension().is_none() {
+            path.set_extension(self.preset_extension().unwrap_or_default());
+        }
+        self.error = std::fs::write(&path, text)
+            .err()
+            .map(|e| format!("Failed to save preset: {}", e));
+    }
+    
+    pub fn load_preset(&mut self, path: &Path) {
+        let loaded = std::fs::read_to_string(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|text| match &self.adjustment {
+                Some(Adjustment::Levels(_)) => Levels::from_preset(&text).map(Adjustment::Levels),
+                Some(Adjustment::Curves(_)) => Curves::from_preset(&text).map(Adjustment::Curves),
+                _ => Err(anyhow::anyhow!("This adjustment has no presets")),
+            });
+        
+        match loaded {
+            Ok(adjustment) => {
+                self.adjustment = Some(adjustment);
+                self.dragged_point = None;
+                self.error = None;
+            }
+            Err(e) => self.error = Some(format!("Failed to load preset: {}", e)),
+        }
+    }
+    
+    pub fn show(&mut self, ctx: &egui::Context, image_data: &mut ImageData, image_history: &mut ImageHistory) -> AdjustmentAction {
+        let mut adjustment = match self.adjustment.clone() {
+            Some(adjustment) => adjustment,
+            None => return AdjustmentAction::None,
+        };
+        
+        let mut action = AdjustmentAction::None;
+        let mut window_open = true;
+        let mut ok = false;
+        let mut cancel = false;
+        let mut reset = false;
+        
+        egui::Window::new(adjustment.name())
+            .open(&mut window_open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                match &mut adjustment {
+                    Adjustment::BrightnessContrast { brightness, contrast } => {
+                        percent_slider(ui, brightness, -100.0..=100.0, "Brightness");
+                        percent_slider(ui, contrast, -100.0..=100.0, "Contrast");
+                    }
+                    Adjustment::HueSaturation { hue_shift, saturation } => {
+                        ui.add(egui::Slider::new(hue_shift, -180.0..=180.0).text("Hue").suffix("°"));
+                        // Shown as a change relative to the current saturation
+                        let mut change = (*saturation - 1.0) * 100.0;
+                        if ui.add(egui::Slider::new(&mut change, -100.0..=100.0).text("Saturation").suffix("%")).changed() {
+                            *saturation = 1.0 + change / 100.0;
+                        }
+                    }
+                    Adjustment::ColorBalance(balance) => self.color_balance_ui(ui, balance),
+                    Adjustment::Levels(levels) => self.levels_ui(ui, levels),
+                    Adjustment::Curves(curves) => self.curves_ui(ui, curves),
+                }
+                
+                if self.preset_extension().is_some() {
+                    ui.horizontal(|ui| {
+                        if ui.button("Load Preset...").clicked() {
+                            action = AdjustmentAction::LoadPreset;
+                        }
+                        if ui.button("Save Preset...").clicked() {
+                            action = AdjustmentAction::SavePreset;
+                        }
+                    });
+                }
+                if let Some(error) = &self.error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+                
+                ui.separator();
+                
+                ui.horizontal(|ui| {
+                    if ui.button("OK").clicked() {
+                        ok = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        cancel = true;
+                    }
+                    if ui.button("Reset").clicked() {
+                        reset = true;
+                    }
+                    ui.checkbox(&mut self.preview, "Preview");
+                });
+            });
+        
+        if reset {
+            adjustment = adjustment.identity();
+        }
+        self.adjustment = Some(adjustment);
+        
+        if ok {
+            self.commit(image_data, image_history);
+            return AdjustmentAction::Committed;
+        }
+        if cancel || !window_open {
+            self.cancel(image_data, image_history);
+            return AdjustmentAction::None;
+        }
+        
+        self.update_preview(image_data);
+        action
+    }
+    
+    fn color_balance_ui(&mut self, ui: &mut egui::Ui, balance: &mut ColorBalance) {
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.tone_range, ToneRange::Shadows, "Shadows");
+            ui.radio_value(&mut self.tone_range, ToneRange::Midtones, "Midtones");
+            ui.radio_value(&mut self.tone_range, ToneRange::Highlights, "Highlights");
+        });
+        
+        let values = match self.tone_range {
+            ToneRange::Shadows => &mut balance.shadows,
+            ToneRange::Midtones => &mut balance.midtones,
+            ToneRange::Highlights => &mut balance.highlights,
+        };
+        for (value, (low, high)) in values.iter_mut().zip([("Cyan", "Red"), ("Magenta", "Green"), ("Yellow", "Blue")]) {
+            ui.horizontal(|ui| {
+                ui.label(low);
+                percent_slider(ui, value, -100.0..=100.0, "");
+                ui.label(high);
+            });
+        }
+        
+        ui.checkbox(&mut balance.preserve_luminosity, "Preserve luminosity");
+    }
+    
+    fn channel_ui(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_label("Channel")
+            .selected_text(self.channel.name())
+            .show_ui(ui, |ui| {
+                for channel in Channel::ALL {
+                    ui.selectable_value(&mut self.channel, channel, channel.name());
+                }
+            });
+    }
+    
+    fn levels_ui(&mut self, ui: &mut egui::Ui, levels: &mut Levels) {
+        self.channel_ui(ui);
+        
+        let channel = &mut levels.channels[self.channel.index()];
+        
+        let (rect, _) = ui.allocate_exact_size(egui::vec2(GRAPH_SIZE, 100.0), egui::Sense::hover());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+        if let Some(histogram) = &self.histogram {
+            draw_histogram(&painter, rect, histogram.counts(self.channel), channel_color(self.channel));
+        }
+        // Input black, midtone and white points
+        let midtone = channel.input_black as f32
+            + (channel.input_white as f32 - channel.input_black as f32) * 0.5f32.powf(channel.gamma);
+        for (value, color) in [
+            (channel.input_black as f32, egui::Color32::BLACK),
+            (midtone, egui::Color32::GRAY),
+            (channel.input_white as f32, egui::Color32::WHITE),
+        ] {
+            let x = rect.left() + value / 255.0 * rect.width();
+            painter.line_segment([egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())], egui::Stroke::new(1.5, color));
+        }
+        
+        egui::Grid::new("levels").num_columns(4).show(ui, |ui| {
+            ui.label("Input:");
+            ui.add(egui::DragValue::new(&mut channel.input_black).clamp_range(0..=channel.input_white.saturating_sub(2)));
+            ui.add(egui::DragValue::new(&mut channel.gamma).clamp_range(0.1..=9.99).speed(0.01).fixed_decimals(2));
+            ui.add(egui::DragValue::new(&mut channel.input_white).clamp_range(channel.input_black.saturating_add(2)..=255));
+            ui.end_row();
+            ui.label("Output:");
+            ui.add(egui::DragValue::new(&mut channel.output_black).clamp_range(0..=255));
+            ui.label("");
+            ui.add(egui::DragValue::new(&mut channel.output_white).clamp_range(0..=255));
+            ui.end_row();
+        });
+        
+        self.output_histogram_ui(ui, &Adjustment::Levels(levels.clone()));
+    }
+    
+    fn curves_ui(&mut self, ui: &mut egui::Ui, curves: &mut Curves) {
+        self.channel_ui(ui);
+        
+        let (rect, response) = ui.allocate_exact_size(egui::vec2(GRAPH_SIZE, GRAPH_SIZE), egui::Sense::click_and_drag());
+        let to_screen = |(x, y): (f32, f32)| egui::pos2(rect.left() + x / 255.0 * rect.width(), rect.bottom() - y / 255.0 * rect.height());
+        let from_screen = |pos: egui::Pos2| {
+            let x = (pos.x - rect.left()) / rect.width() * 255.0;
+            let y = (rect.bottom() - pos.y) / rect.height() * 255.0;
+            (x.round().clamp(0.0, 255.0), y.round().clamp(0.0, 255.0))
+        };
+        
+        let points = &mut curves.channels[self.channel.index()];
+        let nearest = |points: &[(f32, f32)], pos: egui::Pos2| {
+            points
+                .iter()
+                .position(|point| to_screen(*point).distance(pos) <= POINT_GRAB_RADIUS)
+        };
+        
+        // Dragging a point moves it between its neighbours; dragging
+        // elsewhere adds a point there
+        if response.drag_started() {
+            if let Some(pos) = response.interact_pointer_pos() {
+                self.dragged_point = nearest(points, pos).or_else(|| {
+                    let (x, y) = from_screen(pos);
+                    if points.iter().any(|point| point.0 == x) {
+                        return None;
+                    }
+                    let index = points.partition_point(|point| point.0 < x);
+                    points.insert(index, (x, y));
+                    Some(index)
+                });
+            }
+        }
+        if let (Some(index), Some(pos)) = (self.dragged_point, response.interact_pointer_pos()) {
+            if response.dragged() && index < points.len() {
+                let (x, y) = from_screen(pos);
+                let min_x = if index > 0 { points[index - 1].0 + 1.0 } else { 0.0 };
+                let max_x = points.get(index + 1).map_or(255.0, |point| point.0 - 1.0);
+                // Presets can pack neighbours closer than a step apart,
+                // leaving the point no room to move sideways
+                let x = if min_x <= max_x { x.clamp(min_x, max_x) } else { points[index].0 };
+                points[index] = (x, y);
+            }
+        }
+        if response.drag_stopped() {
+            self.dragged_point = None;
+        }
+        // Right-click removes a point, keeping at least two
+        if response.secondary_clicked() && points.len() > 2 {
+            if let Some(index) = response.interact_pointer_pos().and_then(|pos| nearest(points, pos)) {
+                points.remove(index);
+            }
+        }
+        
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+        if let Some(histogram) = &self.histogram {
+            draw_histogram(&painter, rect, histogram.counts(self.channel), channel_color(self.channel).gamma_multiply(0.5));
+        }
+        let grid = egui::Stroke::new(1.0, ui.visuals().widgets.noninteractive.bg_stroke.color);
+        for i in 1..4 {
+            let t = i as f32 * 255.0 / 4.0;
+            painter.line_segment([to_screen((t, 0.0)), to_screen((t, 255.0))], grid);
+            painter.line_segment([to_screen((0.0, t)), to_screen((255.0, t))], grid);
+        }
+        painter.line_segment([to_screen((0.0, 0.0)), to_screen((255.0, 255.0))], grid);
+        
+        let lut = curves.luts().tables[self.channel.index()];
+        let curve: Vec<egui::Pos2> = lut.iter().enumerate().map(|(x, y)| to_screen((x as f32, *y as f32))).collect();
+        let curve_color = match self.channel {
+            Channel::Rgb | Channel::Alpha => ui.visuals().strong_text_color(),
+            channel => channel_color(channel),
+        };
+        painter.add(egui::Shape::line(curve, egui::Stroke::new(1.5, curve_color)));
+        
+        let points = &curves.channels[self.channel.index()];
+        for (index, point) in points.iter().enumerate() {
+            let filled = self.dragged_point == Some(index);
+            if filled {
+                painter.circle_filled(to_screen(*point), 4.0, curve_color);
+            } else {
+                painter.circle_stroke(to_screen(*point), 4.0, egui::Stroke::new(1.5, curve_color));
+            }
+        }
+        
+        let readout = self.dragged_point
+            .and_then(|index| points.get(index).copied())
+            .or_else(|| response.hover_pos().map(|pos| {
+                let (x, _) = from_screen(pos);
+                (x, lut[x as usize] as f32)
+            }));
+        match readout {
+            Some((x, y)) => ui.label(format!("Input: {}   Output: {}", x, y)),
+            None => ui.label("Drag to add or move points, right-click to remove"),
+        };
+        
+        self.output_histogram_ui(ui, &Adjustment::Curves(curves.clone()));
+    }
+    
+    // Histogram of the layer as it looks with `adjustment` applied
+    fn output_histogram_ui(&self, ui: &mut egui::Ui, adjustment: &Adjustment) {
+        let luts = match adjustment {
+            Adjustment::Levels(levels) => levels.luts(),
+            Adjustment::Curves(curves) => curves.luts(),
+            _ => return,
+        };
+        let histogram = match &self.histogram {
+            Some(histogram) => histogram.remapped(&luts),
+            None => return,
+        };
+        
+        ui.label("Output histogram:");
+        let (rect, _) = ui.allocate_exact_size(egui::vec2(GRAPH_SIZE, 60.0), egui::Sense::hover());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+        draw_histogram(&painter, rect, histogram.counts(self.channel), channel_color(self.channel));
+    }
+    
+    // Shows the current settings on the layer if they changed since last frame
+    fn update_preview(&mut self, image_data: &mut ImageData) {
+        let (adjustment, (index, source)) = match (&self.adjustment, &self.target) {
+            (Some(adjustment), Some(target)) => (adjustment, target),
+            _ => return,
+        };
+        
+        let state = (adjustment.clone(), self.preview);
+        if self.previewed.as_ref() == Some(&state) {
+            return;
+        }
+        
+        if self.preview && !adjustment.is_identity() {
+            image_data.apply_adjustment(*index, source, adjustment);
+        } else if let Some(layer) = image_data.layers.get_mut(*index) {
+            layer.data = source.clone();
+            image_data.mark_dirty();
+        }
+        self.previewed = Some(state);
+    }
+    
+    fn commit(&mut self, image_data: &mut ImageData, image_history: &mut ImageHistory) {
+        match (&self.adjustment, &self.target) {
+            (Some(adjustment), Some((index, source))) if !adjustment.is_identity() => {
+                if self.previewed.as_ref() != Some(&(adjustment.clone(), true)) {
+                    image_data.apply_adjustment(*index, source, adjustment);
+                }
+                image_history.commit(image_data);
+            }
+            _ => image_history.cancel(image_data),
+        }
+        self.close();
+    }
+    
+    pub fn cancel(&mut self, image_data: &mut ImageData, image_history: &mut ImageHistory) {
+        image_history.cancel(image_data);
+        self.close();
+    }
+}
+
+// Width of the histogram and curve graphs
+const GRAPH_SIZE: f32 = 256.0;
+// How close in screen pixels a click must be to pick up a curve point
+const POINT_GRAB_RADIUS: f32 = 8.0;
+
+fn channel_color(channel: Channel) -> egui::Color32 {
+    match channel {
+        Channel::Rgb | Channel::Alpha => egui::Color32::GRAY,
+        Channel::Red => egui::Color32::from_rgb(220, 60, 60),
+        Channel::Green => egui::Color32::from_rgb(60, 190, 60),
+        Channel::Blue => egui::Color32::from_rgb(70, 110, 230),
+    }
+}
+
+// One bar per value, scaled so the tallest fills `rect`
+fn draw_histogram(painter: &egui::Painter, rect: egui::Rect, counts: &[u32; 256], color: egui::Color32) {
+    let max = counts.iter().copied().max().unwrap_or(0);
+    if max == 0 {
+        return;
+    }
+    
+    let bar_width = rect.width() / 256.0;
+    for (value, count) in counts.iter().enumerate() {
+        if *count == 0 {
+            continue;
+        }
+        let height = *count as f32 / max as f32 * rect.height();
+        let x = rect.left() + value as f32 * bar_width;
+        painter.rect_filled(
+            egui::Rect::from_min_max(egui::pos2(x, rect.bottom() - height), egui::pos2(x + bar_width, rect.bottom())),
+            0.0,
+            color,
+        );
+    }
+}
+
+// Slider showing a -1..=1 value as a percentage
+fn percent_slider(ui: &mut egui::Ui, value: &mut f32, range: std::ops::RangeInclusive<f32>, text: &str) {
+    let mut percent = *value * 100.0;
+    if ui.add(egui::Slider::new(&mut percent, range).text(text).suffix("%")).changed() {
+        *value = percent / 100.0;
+    }
+}
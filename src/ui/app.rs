@@ -1,7 +1,15 @@
 use eframe::egui;
 use crate::tools::{Tool, ToolType, BrushSettings};
-use crate::image_ops::{ImageData, ImageHistory};
-use crate::ui::{canvas::CanvasState, toolbar::Toolbar, menubar::MenuBar, color_picker::ColorPicker, layer_panel::LayerPanel};
+use crate::image_ops::{ImageData, ImageHistory, RgbaImage};
+use crate::image_ops::clipboard_ops::{self, Clipboard};
+use crate::image_ops::transform::Resampling;
+use crate::image_ops::adjustments::{Adjustment, ColorBalance, Curves, Levels};
+use crate::image_ops::filters::{EdgeOperator, Filter};
+use crate::image_ops::selection::SelectionModify;
+use crate::tools::crop::CropAction;
+use crate::tools::text::TextAction;
+use crate::tools::transform::TransformAction;
+use crate::ui::{canvas::{CanvasState, CanvasDocument}, toolbar::Toolbar, menubar::MenuBar, color_picker::ColorPicker, layer_panel::LayerPanel, history_panel::HistoryPanel, size_dialogs::{ImageSizeDialog, CanvasSizeDialog}, adjustment_dialog::{AdjustmentDialog, AdjustmentAction}, filter_dialog::FilterDialog, selection_dialogs::{ColorRangeDialog, ModifySelectionDialog}};
 use std::path::PathBuf;
 use std::sync::mpsc::{self, Receiver, Sender};
 use anyhow::Result;
@@ -10,6 +18,9 @@ use anyhow::Result;
 pub enum FileOperation {
     Open(PathBuf),
     Save(PathBuf),
+    LoadFont(PathBuf),
+    SavePreset(PathBuf),
+    LoadPreset(PathBuf),
 }
 
 pub struct PaintMateApp {
@@ -20,8 +31,16 @@ pub struct PaintMateApp {
     pub menubar: MenuBar,
     pub color_picker: ColorPicker,
     pub layer_panel: LayerPanel,
+    pub history_panel: HistoryPanel,
+    pub image_size_dialog: ImageSizeDialog,
+    pub canvas_size_dialog: CanvasSizeDialog,
+    pub adjustment_dialog: AdjustmentDialog,
+    pub filter_dialog: FilterDialog,
+    pub color_range_dialog: ColorRangeDialog,
+    pub modify_selection_dialog: ModifySelectionDialog,
     pub image_data: Option<ImageData>,
     pub image_history: ImageHistory,
+    pub clipboard: Box<dyn Clipboard>,
     pub current_file: Option<PathBuf>,
     pub is_fullscreen: bool,
     pub fullscreen_background: FullscreenBackground,
@@ -47,12 +66,20 @@ impl Default for PaintMateApp {
             canvas_state: CanvasState::default(),
             current_tool: Tool::default(),
             brush_settings: BrushSettings::default(),
-            toolbar: Toolbar::default(),
+            toolbar: Toolbar,
             menubar: MenuBar::default(),
             color_picker: ColorPicker::default(),
-            layer_panel: LayerPanel::default(),
+            layer_panel: LayerPanel,
+            history_panel: HistoryPanel,
+            image_size_dialog: ImageSizeDialog::default(),
+            canvas_size_dialog: CanvasSizeDialog::default(),
+            adjustment_dialog: AdjustmentDialog::default(),
+            filter_dialog: FilterDialog::default(),
+            color_range_dialog: ColorRangeDialog::default(),
+            modify_selection_dialog: ModifySelectionDialog::default(),
             image_data: None,
             image_history: ImageHistory::new(),
+            clipboard: clipboard_ops::default_clipboard(),
             current_file: None,
             is_fullscreen: false,
             fullscreen_background: FullscreenBackground::Black,
@@ -67,28 +94,38 @@ impl Default for PaintMateApp {
 }
 
 impl PaintMateApp {
-    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+    pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
         Self::default()
     }
 
     pub fn new_image(&mut self, width: u32, height: u32) {
-        self.image_data = Some(ImageData::new(width, height));
-        self.image_history.clear();
-        self.current_file = None;
-        self.is_modified = false;
-        self.zoom_level = 1.0;
-        self.pan_offset = egui::Vec2::ZERO;
+        self.set_document(ImageData::new(width, height), "New image", None);
     }
 
     pub fn open_image(&mut self, path: PathBuf) -> Result<()> {
         let image_data = ImageData::from_file(&path)?;
+        self.set_document(image_data, "Open", Some(path));
+        Ok(())
+    }
+
+    // Swaps in another document, dropping everything tied to the old one
+    fn set_document(&mut self, image_data: ImageData, label: &str, file: Option<PathBuf>) {
         self.image_data = Some(image_data);
-        self.image_history.clear();
-        self.current_file = Some(path);
+        self.image_history.reset(label);
+        self.adjustment_dialog.close();
+        self.filter_dialog.close();
+        self.color_range_dialog.close();
+        self.modify_selection_dialog.open = false;
+        // Both size dialogs hold the old document's dimensions
+        self.image_size_dialog.open = false;
+        self.canvas_size_dialog.open = false;
+        self.canvas_state.crop_tool.cancel();
+        self.canvas_state.transform_tool.close();
+        self.canvas_state.text_tool.close();
+        self.current_file = file;
         self.is_modified = false;
         self.zoom_level = 1.0;
         self.pan_offset = egui::Vec2::ZERO;
-        Ok(())
     }
 
     pub fn save_image(&mut self, path: Option<PathBuf>) -> Result<()> {
@@ -113,8 +150,172 @@ impl PaintMateApp {
         Ok(())
     }
 
-    pub fn get_file_operation_sender(&self) -> Sender<FileOperation> {
-        self.file_op_sender.clone()
+    pub fn undo(&mut self) {
+        if let Some(ref mut image_data) = self.image_data {
+            self.canvas_state.text_tool.finish_edit(image_data, &mut self.image_history);
+            if self.image_history.undo(image_data) {
+                self.is_modified = true;
+            }
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(ref mut image_data) = self.image_data {
+            self.canvas_state.text_tool.finish_edit(image_data, &mut self.image_history);
+            if self.image_history.redo(image_data) {
+                self.is_modified = true;
+            }
+        }
+    }
+
+    // Rotates the whole document clockwise as one undo step
+    pub fn rotate_image(&mut self, degrees: f32, resampling: Resampling) {
+        self.commit_transform();
+        if let Some(ref mut image_data) = self.image_data {
+            let label = match degrees.rem_euclid(360.0) {
+                90.0 => "Rotate 90° CW".to_string(),
+                180.0 => "Rotate 180°".to_string(),
+                270.0 => "Rotate 90° CCW".to_string(),
+                d => format!("Rotate {:.1}°", d),
+            };
+            self.image_history.record(&label, image_data, |image_data| image_data.rotate(degrees, resampling));
+            self.canvas_state.crop_tool.cancel();
+            self.is_modified = true;
+        }
+    }
+
+    pub fn flip_image(&mut self, horizontal: bool) {
+        self.commit_transform();
+        if let Some(ref mut image_data) = self.image_data {
+            let label = if horizontal { "Flip horizontal" } else { "Flip vertical" };
+            self.image_history.record(label, image_data, |image_data| image_data.flip(horizontal));
+            self.canvas_state.crop_tool.cancel();
+            self.is_modified = true;
+        }
+    }
+
+    // Dialogs that preview an edit on the document inside an open undo step.
+    // Anything else that edits the document waits until they close, so the
+    // step only ever holds the dialog's own change.
+    fn has_modal_dialog(&self) -> bool {
+        self.adjustment_dialog.is_open() || self.filter_dialog.is_open()
+    }
+
+    // Opens the dialog for an Image > Adjustments entry on the active layer
+    pub fn open_adjustment(&mut self, adjustment: Adjustment) {
+        self.commit_transform();
+        if let Some(ref mut image_data) = self.image_data {
+            if self.filter_dialog.is_open() {
+                self.filter_dialog.cancel(image_data, &mut self.image_history);
+            }
+            self.adjustment_dialog.open(adjustment, image_data, &mut self.image_history);
+        }
+    }
+
+    // Opens the dialog for a Filters menu entry on the active layer
+    pub fn open_filter(&mut self, filter: Filter) {
+        self.commit_transform();
+        if let Some(ref mut image_data) = self.image_data {
+            if self.adjustment_dialog.is_open() {
+                self.adjustment_dialog.cancel(image_data, &mut self.image_history);
+            }
+            self.filter_dialog.open(filter, image_data, &mut self.image_history);
+        }
+    }
+
+    // Copies the selected pixels of the active layer, or of all visible
+    // layers when `merged` is set. Returns true if anything was copied.
+    pub fn copy(&mut self, merged: bool) -> bool {
+        let image = match self.image_data.as_ref().and_then(|image_data| image_data.copy_selection(merged)) {
+            Some(image) => image,
+            None => return false,
+        };
+        match self.clipboard.set_image(&image) {
+            Ok(()) => true,
+            Err(e) => {
+                log::error!("Failed to copy to clipboard: {}", e);
+                false
+            }
+        }
+    }
+
+    // Copies the selected pixels of the active layer, then erases them
+    pub fn cut(&mut self) {
+        self.commit_transform();
+        if !self.copy(false) {
+            return;
+        }
+        if let Some(ref mut image_data) = self.image_data {
+            self.image_history.record("Cut", image_data, |image_data| image_data.clear_selected_pixels());
+            self.is_modified = true;
+        }
+    }
+
+    // Pastes the clipboard as a new layer in the middle of the view, or as a
+    // new document when none is open
+    pub fn paste(&mut self) {
+        let image = match self.clipboard_image() {
+            Some(image) => image,
+            None => return,
+        };
+        self.commit_transform();
+        if let Some(ref mut image_data) = self.image_data {
+            // Point of the image under the centre of the canvas area
+            let center = egui::pos2(
+                image_data.width() as f32 / 2.0 - self.pan_offset.x / self.zoom_level,
+                image_data.height() as f32 / 2.0 - self.pan_offset.y / self.zoom_level,
+            );
+            self.image_history.record("Paste", image_data, |image_data| image_data.paste_as_layer(&image, center));
+            self.is_modified = true;
+        } else {
+            self.set_document(ImageData::from_image(&image), "Paste", None);
+        }
+    }
+
+    pub fn paste_as_new_image(&mut self) {
+        if let Some(image) = self.clipboard_image() {
+            self.set_document(ImageData::from_image(&image), "Paste", None);
+        }
+    }
+
+    fn clipboard_image(&mut self) -> Option<RgbaImage> {
+        match self.clipboard.get_image() {
+            Ok(image) => image,
+            Err(e) => {
+                log::error!("Failed to paste from clipboard: {}", e);
+                None
+            }
+        }
+    }
+
+    // Opens Select > Color Range, sampling the primary colour
+    pub fn open_color_range(&mut self) {
+        if let Some(image_data) = &self.image_data {
+            self.color_range_dialog.show_for(image_data, self.color_picker.primary_color);
+        }
+    }
+    
+    // Edit > Free Transform: puts a transform box around the selection, or
+    // the whole active layer when nothing is selected
+    pub fn free_transform(&mut self) {
+        if let Some(ref mut image_data) = self.image_data {
+            if self.adjustment_dialog.is_open() {
+                self.adjustment_dialog.cancel(image_data, &mut self.image_history);
+            }
+            if self.filter_dialog.is_open() {
+                self.filter_dialog.cancel(image_data, &mut self.image_history);
+            }
+            self.canvas_state.transform_tool.begin(image_data, &mut self.image_history, "Free Transform");
+        }
+    }
+
+    // Applies a pending free transform before another edit takes over
+    pub fn commit_transform(&mut self) {
+        if let Some(ref mut image_data) = self.image_data {
+            if self.canvas_state.transform_tool.commit(image_data, &mut self.image_history) {
+                self.is_modified = true;
+            }
+        }
     }
 
     pub fn process_file_operations(&mut self) {
@@ -130,6 +331,13 @@ impl PaintMateApp {
                         log::error!("Failed to save image: {}", e);
                     }
                 }
+                FileOperation::LoadFont(path) => {
+                    if self.canvas_state.text_tool.load_font(&path, self.image_data.as_mut(), &mut self.image_history) {
+                        self.is_modified = true;
+                    }
+                }
+                FileOperation::SavePreset(path) => self.adjustment_dialog.save_preset(&path),
+                FileOperation::LoadPreset(path) => self.adjustment_dialog.load_preset(&path),
             }
         }
     }
@@ -175,6 +383,59 @@ impl PaintMateApp {
             self.menubar.request_save_as(self.file_op_sender.clone());
         }
         
+        // Leave undo/redo keys to text fields while one is focused, and the
+        // document to a dialog previewing an edit on it
+        if !ctx.wants_keyboard_input() && !self.has_modal_dialog() {
+            if ctx.input(|i| i.modifiers.ctrl && !i.modifiers.shift && i.key_pressed(egui::Key::Z)) {
+                self.undo();
+            }
+            
+            if ctx.input(|i| i.modifiers.ctrl && (i.key_pressed(egui::Key::Y) || (i.modifiers.shift && i.key_pressed(egui::Key::Z)))) {
+                self.redo();
+            }
+            
+            if ctx.input(|i| i.modifiers.ctrl && i.key_pressed(egui::Key::D)) {
+                if let Some(image_data) = &mut self.image_data {
+                    image_data.deselect();
+                }
+            }
+            
+            // Copy and cut arrive as events rather than key presses. Paste
+            // only does when the clipboard holds text, so go by the V key's
+            // release instead.
+            let (copy, cut, paste) = ctx.input(|i| (
+                i.events.contains(&egui::Event::Copy),
+                i.events.contains(&egui::Event::Cut),
+                i.modifiers.command && i.key_released(egui::Key::V),
+            ));
+            if copy {
+                let merged = ctx.input(|i| i.modifiers.shift);
+                self.copy(merged);
+            }
+            if cut {
+                self.cut();
+            }
+            if paste {
+                self.paste();
+            }
+            
+            if ctx.input(|i| i.modifiers.ctrl && i.key_pressed(egui::Key::A)) {
+                if let Some(image_data) = &mut self.image_data {
+                    image_data.select_all();
+                }
+            }
+            
+            if ctx.input(|i| i.modifiers.ctrl && i.modifiers.shift && i.key_pressed(egui::Key::I)) {
+                if let Some(image_data) = &mut self.image_data {
+                    image_data.invert_selection();
+                }
+            }
+            
+            if ctx.input(|i| i.modifiers.ctrl && i.key_pressed(egui::Key::T)) {
+                self.free_transform();
+            }
+        }
+        
         if self.is_fullscreen {
             if ctx.input(|i| i.key_pressed(egui::Key::Tab)) {
                 self.show_ui = !self.show_ui;
@@ -199,13 +460,29 @@ impl eframe::App for PaintMateApp {
         } else {
             self.update_windowed(ctx);
         }
+        
+        self.apply_picked_color();
     }
 }
 
 impl PaintMateApp {
+    // The colour picker owns the colours, so eyedropper picks go through it
+    fn apply_picked_color(&mut self) {
+        if let Some(picked) = self.canvas_state.take_picked_color() {
+            if picked.secondary {
+                self.color_picker.secondary_color = picked.color;
+                self.brush_settings.secondary_color = picked.color;
+            } else {
+                self.color_picker.primary_color = picked.color;
+                self.brush_settings.primary_color = picked.color;
+            }
+        }
+    }
+    
     fn update_windowed(&mut self, ctx: &egui::Context) {
         // Get a copy of the sender to avoid borrowing issues
         let file_sender = self.file_op_sender.clone();
+        let modal = self.has_modal_dialog();
         
         // Menu bar
         egui::TopBottomPanel::top("menubar").show(ctx, |ui| {
@@ -256,15 +533,14 @@ impl PaintMateApp {
                 });
                 
                 ui.menu_button("Edit", |ui| {
+                    ui.set_enabled(!modal);
                     let has_image = self.image_data.is_some();
                     
                     if ui.add_enabled(
                         has_image && self.image_history.can_undo(),
                         egui::Button::new("Undo (Ctrl+Z)")
                     ).clicked() {
-                        if let Some(previous_state) = self.image_history.undo() {
-                            self.image_data = Some(previous_state.clone());
-                        }
+                        self.undo();
                         ui.close_menu();
                     }
                     
@@ -272,21 +548,215 @@ impl PaintMateApp {
                         has_image && self.image_history.can_redo(),
                         egui::Button::new("Redo (Ctrl+Y)")
                     ).clicked() {
-                        if let Some(next_state) = self.image_history.redo() {
-                            self.image_data = Some(next_state.clone());
-                        }
+                        self.redo();
                         ui.close_menu();
                     }
                     
                     ui.separator();
                     
+                    if ui.add_enabled(has_image, egui::Button::new("Cut (Ctrl+X)")).clicked() {
+                        self.cut();
+                        ui.close_menu();
+                    }
+                    
                     if ui.add_enabled(has_image, egui::Button::new("Copy (Ctrl+C)")).clicked() {
-                        // TODO: Implement clipboard copy
+                        self.copy(false);
+                        ui.close_menu();
+                    }
+                    
+                    if ui.add_enabled(has_image, egui::Button::new("Copy Merged (Ctrl+Shift+C)")).clicked() {
+                        self.copy(true);
+                        ui.close_menu();
+                    }
+                    
+                    if ui.button("Paste (Ctrl+V)").clicked() {
+                        self.paste();
+                        ui.close_menu();
+                    }
+                    
+                    if ui.button("Paste as New Image").clicked() {
+                        self.paste_as_new_image();
                         ui.close_menu();
                     }
                     
-                    if ui.add_enabled(has_image, egui::Button::new("Paste (Ctrl+V)")).clicked() {
-                        // TODO: Implement clipboard paste
+                    ui.separator();
+                    
+                    let transforming = self.canvas_state.transform_tool.is_active();
+                    if ui.add_enabled(has_image && !transforming, egui::Button::new("Free Transform (Ctrl+T)")).clicked() {
+                        self.free_transform();
+                        ui.close_menu();
+                    }
+                });
+                
+                ui.menu_button("Image", |ui| {
+                    ui.set_enabled(!modal);
+                    let has_image = self.image_data.is_some();
+                    
+                    if ui.add_enabled(has_image, egui::Button::new("Image Size...")).clicked() {
+                        self.commit_transform();
+                        if let Some(img_data) = &self.image_data {
+                            self.image_size_dialog.show_for(img_data);
+                        }
+                        ui.close_menu();
+                    }
+                    
+                    if ui.add_enabled(has_image, egui::Button::new("Canvas Size...")).clicked() {
+                        self.commit_transform();
+                        if let Some(img_data) = &self.image_data {
+                            self.canvas_size_dialog.show_for(img_data);
+                        }
+                        ui.close_menu();
+                    }
+                    
+                    // Applies the pending crop, otherwise switches to the crop tool to make one
+                    if ui.add_enabled(has_image, egui::Button::new("Crop")).clicked() {
+                        self.commit_transform();
+                        if self.canvas_state.crop_tool.rect.is_some() {
+                            if let Some(img_data) = &mut self.image_data {
+                                if self.canvas_state.crop_tool.apply(img_data, &mut self.image_history) {
+                                    self.is_modified = true;
+                                }
+                            }
+                        } else {
+                            self.current_tool.tool_type = ToolType::Crop;
+                        }
+                        ui.close_menu();
+                    }
+                    
+                    ui.separator();
+                    
+                    ui.menu_button("Rotate", |ui| {
+                        if ui.add_enabled(has_image, egui::Button::new("90° CW")).clicked() {
+                            self.rotate_image(90.0, Resampling::Nearest);
+                            ui.close_menu();
+                        }
+                        
+                        if ui.add_enabled(has_image, egui::Button::new("180°")).clicked() {
+                            self.rotate_image(180.0, Resampling::Nearest);
+                            ui.close_menu();
+                        }
+                        
+                        if ui.add_enabled(has_image, egui::Button::new("90° CCW")).clicked() {
+                            self.rotate_image(270.0, Resampling::Nearest);
+                            ui.close_menu();
+                        }
+                        
+                        if ui.add_enabled(has_image, egui::Button::new("Arbitrary...")).clicked() {
+                            self.menubar.show_rotate_dialog = true;
+                            ui.close_menu();
+                        }
+                    });
+                    
+                    ui.menu_button("Flip", |ui| {
+                        if ui.add_enabled(has_image, egui::Button::new("Horizontal")).clicked() {
+                            self.flip_image(true);
+                            ui.close_menu();
+                        }
+                        
+                        if ui.add_enabled(has_image, egui::Button::new("Vertical")).clicked() {
+                            self.flip_image(false);
+                            ui.close_menu();
+                        }
+                    });
+                    
+                    ui.separator();
+                    
+                    ui.menu_button("Adjustments", |ui| {
+                        if ui.add_enabled(has_image, egui::Button::new("Brightness/Contrast...")).clicked() {
+                            self.open_adjustment(Adjustment::BrightnessContrast { brightness: 0.0, contrast: 0.0 });
+                            ui.close_menu();
+                        }
+                        
+                        if ui.add_enabled(has_image, egui::Button::new("Hue/Saturation...")).clicked() {
+                            self.open_adjustment(Adjustment::HueSaturation { hue_shift: 0.0, saturation: 1.0 });
+                            ui.close_menu();
+                        }
+                        
+                        if ui.add_enabled(has_image, egui::Button::new("Color Balance...")).clicked() {
+                            self.open_adjustment(Adjustment::ColorBalance(ColorBalance::default()));
+                            ui.close_menu();
+                        }
+                        
+                        ui.separator();
+                        
+                        if ui.add_enabled(has_image, egui::Button::new("Levels...")).clicked() {
+                            self.open_adjustment(Adjustment::Levels(Levels::default()));
+                            ui.close_menu();
+                        }
+                        
+                        if ui.add_enabled(has_image, egui::Button::new("Curves...")).clicked() {
+                            self.open_adjustment(Adjustment::Curves(Curves::default()));
+                            ui.close_menu();
+                        }
+                    });
+                });
+                
+                ui.menu_button("Select", |ui| {
+                    ui.set_enabled(!modal);
+                    let has_image = self.image_data.is_some();
+                    let has_selection = self.image_data.as_ref().is_some_and(|img_data| img_data.selection().is_some());
+                    
+                    if ui.add_enabled(has_image, egui::Button::new("All (Ctrl+A)")).clicked() {
+                        if let Some(img_data) = &mut self.image_data {
+                            img_data.select_all();
+                        }
+                        ui.close_menu();
+                    }
+                    
+                    if ui.add_enabled(has_selection, egui::Button::new("Deselect (Ctrl+D)")).clicked() {
+                        if let Some(img_data) = &mut self.image_data {
+                            img_data.deselect();
+                        }
+                        ui.close_menu();
+                    }
+                    
+                    if ui.add_enabled(has_selection, egui::Button::new("Inverse (Ctrl+Shift+I)")).clicked() {
+                        if let Some(img_data) = &mut self.image_data {
+                            img_data.invert_selection();
+                        }
+                        ui.close_menu();
+                    }
+                    
+                    ui.separator();
+                    
+                    if ui.add_enabled(has_image, egui::Button::new("Color Range...")).clicked() {
+                        self.open_color_range();
+                        ui.close_menu();
+                    }
+                    
+                    ui.menu_button("Modify", |ui| {
+                        for modify in SelectionModify::ALL {
+                            if ui.add_enabled(has_selection, egui::Button::new(format!("{}...", modify.name()))).clicked() {
+                                self.modify_selection_dialog.show_for(modify);
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                });
+                
+                ui.menu_button("Filters", |ui| {
+                    ui.set_enabled(!modal);
+                    let has_image = self.image_data.is_some();
+                    
+                    if ui.add_enabled(has_image, egui::Button::new("Gaussian Blur...")).clicked() {
+                        self.open_filter(Filter::GaussianBlur { radius: 2.0 });
+                        ui.close_menu();
+                    }
+                    
+                    if ui.add_enabled(has_image, egui::Button::new("Unsharp Mask...")).clicked() {
+                        self.open_filter(Filter::UnsharpMask { amount: 1.0, radius: 1.0, threshold: 0 });
+                        ui.close_menu();
+                    }
+                    
+                    ui.separator();
+                    
+                    if ui.add_enabled(has_image, egui::Button::new("Edge Detect...")).clicked() {
+                        self.open_filter(Filter::EdgeDetect(EdgeOperator::Sobel));
+                        ui.close_menu();
+                    }
+                    
+                    if ui.add_enabled(has_image, egui::Button::new("Emboss...")).clicked() {
+                        self.open_filter(Filter::Emboss { angle: 135.0, height: 1, amount: 1.0 });
                         ui.close_menu();
                     }
                 });
@@ -328,10 +798,82 @@ impl PaintMateApp {
                 ui.heading("Brush Settings");
                 self.brush_settings.show_ui(ui);
                 
+                // Tool options edit the document, so they wait for a dialog previewing
+                // an edit to close
+                ui.add_enabled_ui(!modal, |ui| {
+                    if self.current_tool.tool_type == ToolType::Text {
+                        ui.separator();
+                        
+                        ui.heading("Text");
+                        match self.canvas_state.text_tool.show_ui(ui, self.image_data.as_mut(), &mut self.image_history) {
+                            TextAction::Changed => self.is_modified = true,
+                            TextAction::LoadFont => self.menubar.request_load_font(self.file_op_sender.clone()),
+                            TextAction::None => {}
+                        }
+                    } else if let Some(img_data) = &self.image_data {
+                        self.canvas_state.text_tool.finish_edit(img_data, &mut self.image_history);
+                    }
+                    
+                    if self.current_tool.tool_type.is_selection() {
+                        ui.separator();
+                        
+                        ui.heading("Selection");
+                        self.canvas_state.selection_tool.show_ui(ui, &self.current_tool.tool_type, self.image_data.as_mut());
+                    }
+                    
+                    if self.current_tool.tool_type == ToolType::Move || self.canvas_state.transform_tool.is_active() {
+                        ui.separator();
+                        
+                        ui.heading("Transform");
+                        let action = self.canvas_state.transform_tool.show_ui(ui);
+                        if let Some(img_data) = &mut self.image_data {
+                            match action {
+                                TransformAction::Commit => {
+                                    if self.canvas_state.transform_tool.commit(img_data, &mut self.image_history) {
+                                        self.is_modified = true;
+                                    }
+                                }
+                                TransformAction::Cancel => self.canvas_state.transform_tool.cancel(img_data, &mut self.image_history),
+                                TransformAction::None => {}
+                            }
+                        }
+                    }
+                    
+                    if self.current_tool.tool_type == ToolType::Crop {
+                        if let Some(img_data) = &mut self.image_data {
+                            ui.separator();
+                            
+                            ui.heading("Crop");
+                            match self.canvas_state.crop_tool.show_ui(ui, img_data.width(), img_data.height()) {
+                                CropAction::Apply => {
+                                    if self.canvas_state.crop_tool.apply(img_data, &mut self.image_history) {
+                                        self.is_modified = true;
+                                    }
+                                }
+                                CropAction::Cancel => self.canvas_state.crop_tool.cancel(),
+                                CropAction::None => {}
+                            }
+                        }
+                    }
+                });
+                
                 ui.separator();
                 
                 ui.heading("Layers");
-                self.layer_panel.show(ui, &mut self.image_data);
+                // Layers stay put while pixels float over one of them or a dialog
+                // previews an edit
+                ui.add_enabled_ui(!modal && !self.canvas_state.transform_tool.is_active(), |ui| {
+                    if self.layer_panel.show(ui, &mut self.image_data, &mut self.image_history) {
+                        self.is_modified = true;
+                    }
+                });
+                
+                ui.separator();
+                
+                ui.heading("History");
+                if self.history_panel.show(ui, &mut self.image_data, &mut self.image_history) {
+                    self.is_modified = true;
+                }
             });
 
         // Status bar
@@ -359,15 +901,20 @@ impl PaintMateApp {
         });
 
         // Main canvas area
+        self.canvas_state.locked = self.has_modal_dialog();
         egui::CentralPanel::default().show(ctx, |ui| {
+            let document = CanvasDocument {
+                image_data: &mut self.image_data,
+                image_history: &mut self.image_history,
+                is_modified: &mut self.is_modified,
+            };
             self.canvas_state.show(
                 ui,
-                &mut self.image_data,
+                document,
                 &mut self.current_tool,
                 &self.brush_settings,
                 &mut self.zoom_level,
                 &mut self.pan_offset,
-                &mut self.is_modified,
             );
         });
     }
@@ -415,17 +962,22 @@ impl PaintMateApp {
         }
 
         // Main canvas area
+        self.canvas_state.locked = self.has_modal_dialog();
         egui::CentralPanel::default()
             .frame(egui::Frame::none().fill(bg_color))
             .show(ctx, |ui| {
+                let document = CanvasDocument {
+                    image_data: &mut self.image_data,
+                    image_history: &mut self.image_history,
+                    is_modified: &mut self.is_modified,
+                };
                 self.canvas_state.show(
                     ui,
-                    &mut self.image_data,
+                    document,
                     &mut self.current_tool,
                     &self.brush_settings,
                     &mut self.zoom_level,
                     &mut self.pan_offset,
-                    &mut self.is_modified,
                 );
             });
     }
@@ -465,6 +1017,66 @@ impl PaintMateApp {
                 });
         }
         
+        if let Some(img_data) = &mut self.image_data {
+            let mut resized = self.image_size_dialog.show(ctx, img_data, &mut self.image_history);
+            resized |= self.canvas_size_dialog.show(ctx, img_data, &mut self.image_history, self.color_picker.secondary_color);
+            if resized {
+                self.canvas_state.crop_tool.cancel();
+                self.is_modified = true;
+            }
+            
+            let extension = self.adjustment_dialog.preset_extension().unwrap_or_default();
+            match self.adjustment_dialog.show(ctx, img_data, &mut self.image_history) {
+                AdjustmentAction::Committed => self.is_modified = true,
+                AdjustmentAction::SavePreset => self.menubar.request_save_preset(self.file_op_sender.clone(), extension),
+                AdjustmentAction::LoadPreset => self.menubar.request_load_preset(self.file_op_sender.clone(), extension),
+                AdjustmentAction::None => {}
+            }
+            
+            if self.filter_dialog.show(ctx, img_data, &mut self.image_history) {
+                self.is_modified = true;
+            }
+            
+            self.color_range_dialog.show(ctx, img_data, self.color_picker.primary_color);
+            self.modify_selection_dialog.show(ctx, img_data);
+        }
+        
+        // Show arbitrary rotation dialog
+        if self.menubar.show_rotate_dialog {
+            egui::Window::new("Rotate Canvas")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Angle:");
+                        ui.add(egui::DragValue::new(&mut self.menubar.rotate_degrees)
+                            .clamp_range(-360.0..=360.0)
+                            .speed(0.5)
+                            .suffix("°"));
+                        ui.label("clockwise");
+                    });
+                    
+                    egui::ComboBox::from_label("Resampling")
+                        .selected_text(self.menubar.rotate_resampling.name())
+                        .show_ui(ui, |ui| {
+                            for resampling in Resampling::ALL {
+                                ui.selectable_value(&mut self.menubar.rotate_resampling, resampling, resampling.name());
+                            }
+                        });
+                    
+                    ui.horizontal(|ui| {
+                        if ui.button("Rotate").clicked() {
+                            self.rotate_image(self.menubar.rotate_degrees, self.menubar.rotate_resampling);
+                            self.menubar.show_rotate_dialog = false;
+                        }
+                        
+                        if ui.button("Cancel").clicked() {
+                            self.menubar.show_rotate_dialog = false;
+                        }
+                    });
+                });
+        }
+        
         // Show about dialog
         if self.menubar.show_about {
             egui::Window::new("About PaintMate")
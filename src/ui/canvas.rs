@@ -1,34 +1,51 @@
 use eframe::egui;
-use crate::image_ops::ImageData;
-use crate::tools::{Tool, BrushSettings};
+use crate::image_ops::{ImageData, ImageHistory};
+use crate::tools::{Tool, ToolType, BrushSettings, ToolBehavior};
+use crate::tools::brush::BrushTool;
+use crate::tools::rectangle::RectangleTool;
+use crate::tools::circle::CircleTool;
+use crate::tools::line::LineTool;
+use crate::tools::eyedropper::{EyedropperTool, PickedColor};
+use crate::tools::crop::CropTool;
+use crate::tools::text::TextTool;
+use crate::tools::selection::{self, SelectionTool};
+use crate::tools::transform::TransformTool;
+use crate::image_ops::shapes::Shape;
 
+#[derive(Default)]
 pub struct CanvasState {
     pub is_drawing: bool,
     pub last_pos: Option<egui::Pos2>,
     pub current_stroke: Vec<egui::Pos2>,
+    pub brush_tool: BrushTool,
+    pub picked_color: Option<PickedColor>,
+    pub crop_tool: CropTool,
+    pub text_tool: TextTool,
+    pub selection_tool: SelectionTool,
+    pub transform_tool: TransformTool,
+    // Set while a dialog previews an edit on the document. The view still
+    // pans and zooms but the tools leave the document alone.
+    pub locked: bool,
 }
 
-impl Default for CanvasState {
-    fn default() -> Self {
-        Self {
-            is_drawing: false,
-            last_pos: None,
-            current_stroke: Vec::new(),
-        }
-    }
+// The open document and its edit state, as the canvas tools change them
+pub struct CanvasDocument<'a> {
+    pub image_data: &'a mut Option<ImageData>,
+    pub image_history: &'a mut ImageHistory,
+    pub is_modified: &'a mut bool,
 }
 
 impl CanvasState {
     pub fn show(
         &mut self,
         ui: &mut egui::Ui,
-        image_data: &mut Option<ImageData>,
+        document: CanvasDocument,
         current_tool: &mut Tool,
         brush_settings: &BrushSettings,
         zoom_level: &mut f32,
         pan_offset: &mut egui::Vec2,
-        is_modified: &mut bool,
     ) {
+        let CanvasDocument { image_data, image_history, is_modified } = document;
         let available_rect = ui.available_rect_before_wrap();
         
         if let Some(ref mut img_data) = image_data {
@@ -68,36 +85,119 @@ impl CanvasState {
             }
             
             // Handle drawing
-            if response.hovered() && ui.input(|i| i.pointer.primary_down()) {
+            let modifiers = ui.input(|i| i.modifiers);
+            let is_shape = current_tool.tool_type.is_shape();
+            if !current_tool.tool_type.is_selection() && self.selection_tool.is_drawing() {
+                self.selection_tool.cancel();
+            }
+            if self.locked {
+                // Nothing to do until the dialog closes
+            } else if self.transform_tool.is_active() {
+                // The transform box takes the pointer whichever tool is picked
+                if self.transform_tool.handle_input(&response, image_rect.min, *zoom_level, img_data, image_history) {
+                    *is_modified = true;
+                }
+                
+                if self.transform_tool.is_active() && !ui.ctx().wants_keyboard_input() {
+                    if ui.input(|i| i.key_pressed(egui::Key::Enter)) && self.transform_tool.commit(img_data, image_history) {
+                        *is_modified = true;
+                    }
+                    if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                        self.transform_tool.cancel(img_data, image_history);
+                    }
+                }
+            } else if current_tool.tool_type == ToolType::Move {
+                if self.transform_tool.handle_move_input(&response, image_rect.min, *zoom_level, img_data, image_history) {
+                    *is_modified = true;
+                }
+            } else if current_tool.tool_type.is_selection() {
+                self.selection_tool.handle_input(&current_tool.tool_type, &response, image_rect.min, *zoom_level, img_data);
+            } else if current_tool.tool_type == ToolType::Crop {
+                self.crop_tool.handle_input(&response, image_rect.min, *zoom_level, image_size);
+                
+                if !ui.ctx().wants_keyboard_input() {
+                    if ui.input(|i| i.key_pressed(egui::Key::Enter)) && self.crop_tool.apply(img_data, image_history) {
+                        *is_modified = true;
+                    }
+                    if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                        self.crop_tool.cancel();
+                    }
+                }
+            } else if current_tool.tool_type == ToolType::Text {
+                if response.clicked_by(egui::PointerButton::Primary) {
+                    if let Some(pointer) = response.interact_pointer_pos() {
+                        let pos = egui::pos2(
+                            (pointer.x - image_rect.min.x) / *zoom_level,
+                            (pointer.y - image_rect.min.y) / *zoom_level,
+                        );
+                        if self.text_tool.handle_click(pos, brush_settings.primary_color, img_data, image_history) {
+                            *is_modified = true;
+                        }
+                    }
+                }
+            } else if response.hovered() && ui.input(|i| i.pointer.primary_down()) {
                 if let Some(hover_pos) = response.hover_pos() {
-                    if image_rect.contains(hover_pos) {
+                    // Shapes keep following the pointer when it leaves the image
+                    if image_rect.contains(hover_pos) || (self.is_drawing && is_shape) {
                         let relative_pos = hover_pos - image_rect.min;
                         let image_pos = relative_pos / *zoom_level;
                         let image_pos_point = egui::Pos2::new(image_pos.x, image_pos.y);
-                        let pixel_x = image_pos.x as u32;
-                        let pixel_y = image_pos.y as u32;
                         
                         if !self.is_drawing {
+                            // The whole stroke becomes one undo step
+                            image_history.begin(&history_label(&current_tool.tool_type), img_data);
+                            if current_tool.tool_type != ToolType::Eyedropper {
+                                // Painting on text turns it into plain pixels first
+                                img_data.rasterize_layer(img_data.active_layer);
+                            }
+                            Self::begin_paint_stroke(img_data, brush_settings, current_tool);
+                            if current_tool.tool_type == ToolType::Fill {
+                                // The bucket fills once per click rather than per dab
+                                let color = primary_rgba_with_opacity(brush_settings);
+                                img_data.fill(image_pos.x as u32, image_pos.y as u32, color, &brush_settings.fill);
+                            }
                             self.is_drawing = true;
                             self.current_stroke.clear();
                             self.current_stroke.push(image_pos_point);
+                            self.brush_tool.start_stroke(image_pos_point, brush_settings);
                         } else {
                             self.current_stroke.push(image_pos_point);
+                            self.brush_tool.continue_stroke(image_pos_point, brush_settings);
                         }
                         
-                        // Draw based on current tool
-                        self.apply_tool(img_data, pixel_x, pixel_y, brush_settings, current_tool);
-                        *is_modified = true;
+                        if current_tool.tool_type == ToolType::Eyedropper {
+                            // Keep sampling while the button is held, Alt picks the secondary colour
+                            let picked = EyedropperTool::sample(img_data, image_pos.x as u32, image_pos.y as u32, &brush_settings.eyedropper);
+                            if let Some(color) = picked {
+                                self.picked_color = Some(PickedColor { color, secondary: modifiers.alt });
+                            }
+                        } else {
+                            // Draw based on current tool, one dab per spacing step
+                            for dab in self.brush_tool.take_dabs() {
+                                self.apply_tool(img_data, dab, brush_settings, current_tool);
+                            }
+                            *is_modified = true;
+                        }
                         
                         self.last_pos = Some(image_pos_point);
                     }
                 }
             } else if self.is_drawing {
+                if let Some(shape) = self.current_shape(&current_tool.tool_type, &modifiers, brush_settings) {
+                    draw_shape(img_data, &shape, brush_settings);
+                }
+                img_data.end_stroke();
+                // Erased areas may have left whole tiles transparent
+                img_data.get_active_layer_mut().data.release_empty_tiles();
+                image_history.commit(img_data);
+                self.brush_tool.end_stroke(brush_settings);
                 self.is_drawing = false;
                 self.last_pos = None;
                 self.current_stroke.clear();
             }
             
+            self.transform_tool.render(img_data);
+            
             // Draw the image
             let texture = img_data.get_texture(ui.ctx());
             ui.painter().image(
@@ -114,10 +214,47 @@ impl CanvasState {
                 egui::Stroke::new(1.0, egui::Color32::GRAY),
             );
             
+            // Marching ants around the selection, shown with every tool
+            let time = ui.input(|i| i.time);
+            if let Some(selection) = img_data.selection() {
+                selection::draw_marching_ants(ui.painter(), selection, image_rect.min, *zoom_level, time);
+                ui.ctx().request_repaint_after(std::time::Duration::from_millis(100));
+            }
+            if current_tool.tool_type.is_selection() {
+                self.selection_tool.draw_overlay(ui.painter(), &current_tool.tool_type, image_rect.min, *zoom_level, time);
+                if self.selection_tool.is_drawing() {
+                    ui.ctx().request_repaint_after(std::time::Duration::from_millis(100));
+                }
+            }
+            
+            if current_tool.tool_type == ToolType::Crop {
+                self.crop_tool.draw_overlay(ui.painter(), image_rect, *zoom_level);
+            }
+            if current_tool.tool_type == ToolType::Text {
+                self.text_tool.draw_overlay(ui.painter(), img_data, image_rect.min, *zoom_level);
+            }
+            self.transform_tool.draw_overlay(ui.painter(), image_rect.min, *zoom_level);
+            
+            // Shape being dragged out, drawn over the image until release
+            if self.is_drawing {
+                if let Some(shape) = self.current_shape(&current_tool.tool_type, &modifiers, brush_settings) {
+                    draw_shape_preview(ui.painter(), &shape, image_rect.min, *zoom_level, brush_settings);
+                }
+            }
+            
             // Draw cursor preview
             if let Some(hover_pos) = response.hover_pos() {
-                if image_rect.contains(hover_pos) {
+                let has_brush_cursor = !matches!(current_tool.tool_type, ToolType::Crop | ToolType::Text | ToolType::Move)
+                    && !current_tool.tool_type.is_selection()
+                    && !self.transform_tool.is_active();
+                if image_rect.contains(hover_pos) && has_brush_cursor {
                     self.draw_cursor_preview(ui, hover_pos, brush_settings);
+                    
+                    if self.is_drawing && current_tool.tool_type == ToolType::Eyedropper {
+                        if let (Some(pos), Some(picked)) = (self.last_pos, self.picked_color) {
+                            EyedropperTool::draw_loupe(ui.painter(), img_data, pos.x as u32, pos.y as u32, hover_pos, picked.color);
+                        }
+                    }
                 }
             }
         } else {
@@ -141,31 +278,54 @@ impl CanvasState {
         }
     }
     
-    fn apply_tool(&mut self, image_data: &mut ImageData, x: u32, y: u32, brush_settings: &BrushSettings, current_tool: &Tool) {
-        let color = image::Rgba([
-            brush_settings.primary_color.r(),
-            brush_settings.primary_color.g(),
-            brush_settings.primary_color.b(),
-            (brush_settings.primary_color.a() as f32 * brush_settings.opacity) as u8,
-        ]);
-        
+    // Opacity applies to the stroke as a whole, flow to each dab
+    fn begin_paint_stroke(image_data: &mut ImageData, brush_settings: &BrushSettings, current_tool: &Tool) {
+        let color = primary_rgba(brush_settings);
+        let erase = current_tool.tool_type == ToolType::Eraser;
+        image_data.begin_stroke(color, brush_settings.opacity, brush_settings.flow, erase);
+    }
+    
+    fn apply_tool(&mut self, image_data: &mut ImageData, pos: egui::Pos2, brush_settings: &BrushSettings, current_tool: &Tool) {
+        let radius = brush_settings.size / 2.0;
         match current_tool.tool_type {
-            crate::tools::ToolType::Brush | crate::tools::ToolType::Pencil => {
-                // Draw a circle for brush/pencil
-                image_data.draw_circle(x, y, brush_settings.size / 2.0, color);
+            ToolType::Brush | ToolType::Eraser => {
+                image_data.draw_dab(pos.x, pos.y, radius, brush_settings.hardness);
             }
-            crate::tools::ToolType::Eraser => {
-                // Draw with transparent color
-                let transparent = image::Rgba([0, 0, 0, 0]);
-                image_data.draw_circle(x, y, brush_settings.size / 2.0, transparent);
+            ToolType::Pencil => {
+                // Pencil always paints hard-edged dabs
+                image_data.draw_dab(pos.x, pos.y, radius, 1.0);
             }
+            ToolType::Fill | ToolType::Rectangle | ToolType::Circle | ToolType::Line => {}
             _ => {
+                let color = primary_rgba_with_opacity(brush_settings);
                 // For other tools, just draw a pixel for now
-                image_data.draw_pixel(x, y, color);
+                image_data.draw_pixel(pos.x as u32, pos.y as u32, color);
             }
         }
     }
     
+    // Shape from the press position to the current pointer, if the current
+    // tool draws shapes and the drag is long enough to see
+    fn current_shape(&self, tool_type: &ToolType, modifiers: &egui::Modifiers, brush_settings: &BrushSettings) -> Option<Shape> {
+        let start = *self.current_stroke.first()?;
+        let end = self.last_pos?;
+        if start.distance(end) < 1.0 {
+            return None;
+        }
+        
+        match tool_type {
+            ToolType::Rectangle => Some(RectangleTool::shape(start, end, modifiers)),
+            ToolType::Circle => Some(CircleTool::shape(start, end, modifiers)),
+            ToolType::Line => Some(LineTool::shape(start, end, modifiers, brush_settings.border_width)),
+            _ => None,
+        }
+    }
+    
+    // Colour picked by the eyedropper since the last call
+    pub fn take_picked_color(&mut self) -> Option<PickedColor> {
+        self.picked_color.take()
+    }
+    
     fn draw_cursor_preview(&self, ui: &mut egui::Ui, pos: egui::Pos2, brush_settings: &BrushSettings) {
         let painter = ui.painter();
         let radius = brush_settings.size / 2.0;
@@ -183,3 +343,87 @@ impl CanvasState {
         );
     }
 }
+
+fn primary_rgba(brush_settings: &BrushSettings) -> image::Rgba<u8> {
+    image::Rgba(brush_settings.primary_color.to_srgba_unmultiplied())
+}
+
+// Primary colour with the brush opacity folded into its alpha, for tools
+// that paint in a single pass
+fn primary_rgba_with_opacity(brush_settings: &BrushSettings) -> image::Rgba<u8> {
+    let mut color = primary_rgba(brush_settings);
+    color[3] = (color[3] as f32 * brush_settings.opacity) as u8;
+    color
+}
+
+fn history_label(tool_type: &ToolType) -> String {
+    match tool_type {
+        ToolType::Brush | ToolType::Pencil | ToolType::Eraser => format!("{} stroke", tool_type.name()),
+        _ => tool_type.name().to_string(),
+    }
+}
+
+fn secondary_rgba_with_opacity(brush_settings: &BrushSettings) -> image::Rgba<u8> {
+    let mut color = image::Rgba(brush_settings.secondary_color.to_srgba_unmultiplied());
+    color[3] = (color[3] as f32 * brush_settings.opacity) as u8;
+    color
+}
+
+// Lines are stroked with the primary colour. Other shapes get a primary
+// border and a secondary fill, each if enabled in the brush settings.
+fn draw_shape(image_data: &mut ImageData, shape: &Shape, brush_settings: &BrushSettings) {
+    let primary = primary_rgba_with_opacity(brush_settings);
+    if let Shape::Line { .. } = shape {
+        image_data.draw_shape(shape, None, Some(primary));
+        return;
+    }
+    
+    let border = if brush_settings.border_enabled {
+        Some((primary, brush_settings.border_width))
+    } else {
+        None
+    };
+    let fill = if brush_settings.fill_enabled {
+        Some(secondary_rgba_with_opacity(brush_settings))
+    } else {
+        None
+    };
+    image_data.draw_shape(shape, border, fill);
+}
+
+// Screen-space preview of `draw_shape`. `origin` is the screen position of
+// the image's top-left corner.
+fn draw_shape_preview(painter: &egui::Painter, shape: &Shape, origin: egui::Pos2, zoom: f32, brush_settings: &BrushSettings) {
+    let to_screen = |pos: egui::Pos2| origin + pos.to_vec2() * zoom;
+    let primary = brush_settings.primary_color.gamma_multiply(brush_settings.opacity);
+    let secondary = brush_settings.secondary_color.gamma_multiply(brush_settings.opacity);
+    let border_width = brush_settings.border_width * zoom;
+    let border = egui::Stroke::new(border_width, primary);
+    
+    match shape {
+        Shape::Rectangle(rect) => {
+            let rect = egui::Rect::from_min_max(to_screen(rect.min), to_screen(rect.max));
+            if brush_settings.fill_enabled {
+                painter.rect_filled(rect, 0.0, secondary);
+            }
+            if brush_settings.border_enabled {
+                // Borders are drawn inside the outline
+                painter.rect_stroke(rect.shrink(border_width * 0.5), 0.0, border);
+            }
+        }
+        Shape::Ellipse(rect) => {
+            let center = to_screen(rect.center());
+            let radius = rect.size() * zoom * 0.5;
+            if brush_settings.fill_enabled {
+                painter.add(egui::Shape::ellipse_filled(center, radius, secondary));
+            }
+            if brush_settings.border_enabled {
+                let inset = egui::Vec2::splat(border_width * 0.5);
+                painter.add(egui::Shape::ellipse_stroke(center, radius - inset, border));
+            }
+        }
+        Shape::Line { from, to, width } => {
+            painter.line_segment([to_screen(*from), to_screen(*to)], egui::Stroke::new(width * zoom, primary));
+        }
+    }
+}
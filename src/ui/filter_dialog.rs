@@ -0,0 +1,179 @@
+use eframe::egui;
+use crate::image_ops::{ImageData, ImageHistory};
+use crate::image_ops::filters::{EdgeMode, EdgeOperator, Filter};
+use crate::image_ops::tiles::TiledImage;
+
+// Dialog for the Filters menu. Works like the adjustment dialog: the
+// active layer previews the filter, OK keeps it as one undo step and
+// Cancel restores the layer, and the document is locked in between.
+pub struct FilterDialog {
+    filter: Option<Filter>,
+    edge_mode: EdgeMode,
+    // Layer being filtered and its pixels before the dialog opened
+    target: Option<(usize, TiledImage)>,
+    preview: bool,
+    // What the layer currently shows, to only refilter on changes
+    previewed: Option<(Filter, EdgeMode, bool)>,
+}
+
+impl Default for FilterDialog {
+    fn default() -> Self {
+        Self {
+            filter: None,
+            edge_mode: EdgeMode::Clamp,
+            target: None,
+            preview: true,
+            previewed: None,
+        }
+    }
+}
+
+impl FilterDialog {
+    // Starts filtering the active layer, abandoning any filter in progress
+    pub fn open(&mut self, filter: Filter, image_data: &mut ImageData, image_history: &mut ImageHistory) {
+        if self.is_open() {
+            self.cancel(image_data, image_history);
+        }
+        
+        image_history.begin(filter.name(), image_data);
+        let index = image_data.active_layer;
+        self.target = Some((index, image_data.layers[index].data.clone()));
+        // Nothing is applied yet, so the layer shows the unfiltered pixels
+        self.previewed = Some((filter.clone(), self.edge_mode, false));
+        self.filter = Some(filter);
+    }
+    
+    pub fn is_open(&self) -> bool {
+        self.filter.is_some()
+    }
+    
+    // Forgets the dialog without touching the document
+    pub fn close(&mut self) {
+        self.filter = None;
+        self.target = None;
+        self.previewed = None;
+    }
+    
+    // Returns true if a filter was committed
+    pub fn show(&mut self, ctx: &egui::Context, image_data: &mut ImageData, image_history: &mut ImageHistory) -> bool {
+        let mut filter = match self.filter.clone() {
+            Some(filter) => filter,
+            None => return false,
+        };
+        
+        let mut window_open = true;
+        let mut ok = false;
+        let mut cancel = false;
+        
+        egui::Window::new(filter.name())
+            .open(&mut window_open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                match &mut filter {
+                    Filter::GaussianBlur { radius } => {
+                        ui.add(egui::Slider::new(radius, 0.1..=100.0).logarithmic(true).text("Radius").suffix(" px"));
+                    }
+                    Filter::UnsharpMask { amount, radius, threshold } => {
+                        let mut percent = *amount * 100.0;
+                        if ui.add(egui::Slider::new(&mut percent, 1.0..=500.0).text("Amount").suffix("%")).changed() {
+                            *amount = percent / 100.0;
+                        }
+                        ui.add(egui::Slider::new(radius, 0.1..=250.0).logarithmic(true).text("Radius").suffix(" px"));
+                        ui.add(egui::Slider::new(threshold, 0..=255).text("Threshold").suffix(" levels"));
+                    }
+                    Filter::EdgeDetect(operator) => {
+                        ui.horizontal(|ui| {
+                            for choice in EdgeOperator::ALL {
+                                ui.radio_value(operator, choice, choice.name());
+                            }
+                        });
+                    }
+                    Filter::Emboss { angle, height, amount } => {
+                        ui.add(egui::Slider::new(angle, -180.0..=180.0).text("Angle").suffix("°"));
+                        ui.add(egui::Slider::new(height, 1..=10).text("Height").suffix(" px"));
+                        let mut percent = *amount * 100.0;
+                        if ui.add(egui::Slider::new(&mut percent, 1.0..=500.0).text("Amount").suffix("%")).changed() {
+                            *amount = percent / 100.0;
+                        }
+                    }
+                }
+                
+                egui::ComboBox::from_label("Edges")
+                    .selected_text(self.edge_mode.name())
+                    .show_ui(ui, |ui| {
+                        for edge_mode in EdgeMode::ALL {
+                            ui.selectable_value(&mut self.edge_mode, edge_mode, edge_mode.name());
+                        }
+                    });
+                
+                ui.separator();
+                
+                ui.horizontal(|ui| {
+                    if ui.button("OK").clicked() {
+                        ok = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        cancel = true;
+                    }
+                    ui.checkbox(&mut self.preview, "Preview");
+                });
+            });
+        
+        self.filter = Some(filter);
+        
+        if ok {
+            self.commit(image_data, image_history);
+            return true;
+        }
+        if cancel || !window_open {
+            self.cancel(image_data, image_history);
+            return false;
+        }
+        
+        self.update_preview(image_data);
+        false
+    }
+    
+    // Shows the current settings on the layer if they changed since last frame
+    fn update_preview(&mut self, image_data: &mut ImageData) {
+        let (filter, (index, source)) = match (&self.filter, &self.target) {
+            (Some(filter), Some(target)) => (filter, target),
+            _ => return,
+        };
+        
+        let state = (filter.clone(), self.edge_mode, self.preview);
+        match &self.previewed {
+            Some(previewed) if *previewed == state => return,
+            // Toggling a hidden preview's settings changes nothing on screen
+            Some((_, _, false)) if !self.preview => {
+                self.previewed = Some(state);
+                return;
+            }
+            _ => {}
+        }
+        
+        if self.preview {
+            image_data.apply_filter(*index, source, filter, self.edge_mode);
+        } else if let Some(layer) = image_data.layers.get_mut(*index) {
+            layer.data = source.clone();
+            image_data.mark_dirty();
+        }
+        self.previewed = Some(state);
+    }
+    
+    fn commit(&mut self, image_data: &mut ImageData, image_history: &mut ImageHistory) {
+        if let (Some(filter), Some((index, source))) = (&self.filter, &self.target) {
+            if self.previewed.as_ref() != Some(&(filter.clone(), self.edge_mode, true)) {
+                image_data.apply_filter(*index, source, filter, self.edge_mode);
+            }
+            image_history.commit(image_data);
+        }
+        self.close();
+    }
+    
+    pub fn cancel(&mut self, image_data: &mut ImageData, image_history: &mut ImageHistory) {
+        image_history.cancel(image_data);
+        self.close();
+    }
+}
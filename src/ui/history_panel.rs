@@ -0,0 +1,66 @@
+use eframe::egui;
+use crate::image_ops::{ImageData, ImageHistory};
+
+const MB: f64 = 1024.0 * 1024.0;
+
+#[derive(Default)]
+pub struct HistoryPanel;
+
+impl HistoryPanel {
+    // Returns true if the document was changed
+    pub fn show(&mut self, ui: &mut egui::Ui, image_data: &mut Option<ImageData>, image_history: &mut ImageHistory) -> bool {
+        let img_data = match image_data {
+            Some(img_data) => img_data,
+            None => {
+                ui.label("No image loaded");
+                return false;
+            }
+        };
+        
+        let mut jump_to = None;
+        let current = image_history.current_index();
+        
+        egui::ScrollArea::vertical()
+            .id_source("history_entries")
+            .max_height(200.0)
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                if ui.selectable_label(current == 0, image_history.base_label()).clicked() {
+                    jump_to = Some(0);
+                }
+                
+                for (i, label) in image_history.labels().enumerate() {
+                    let index = i + 1;
+                    // Entries past the current point are undone and can be redone
+                    let text = if index > current {
+                        egui::RichText::new(label).weak()
+                    } else {
+                        egui::RichText::new(label)
+                    };
+                    
+                    if ui.selectable_label(current == index, text).clicked() {
+                        jump_to = Some(index);
+                    }
+                }
+            });
+        
+        ui.separator();
+        
+        ui.horizontal(|ui| {
+            ui.label(format!("Memory: {:.1} MB", image_history.memory_used() as f64 / MB));
+        });
+        
+        ui.horizontal(|ui| {
+            ui.label("Budget:");
+            let mut budget_mb = (image_history.memory_budget() as f64 / MB).round() as usize;
+            if ui.add(egui::DragValue::new(&mut budget_mb).clamp_range(16..=8192).suffix(" MB")).changed() {
+                image_history.set_memory_budget(budget_mb * MB as usize);
+            }
+        });
+        
+        match jump_to {
+            Some(index) => image_history.jump_to(index, img_data),
+            None => false,
+        }
+    }
+}
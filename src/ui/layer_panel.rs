@@ -1,26 +1,55 @@
 use eframe::egui;
-use crate::image_ops::ImageData;
+use crate::image_ops::{ImageData, ImageHistory};
+use crate::image_ops::image_data::BlendMode;
 
 #[derive(Default)]
 pub struct LayerPanel;
 
 impl LayerPanel {
-    pub fn show(&mut self, ui: &mut egui::Ui, image_data: &mut Option<ImageData>) {
+    // Returns true if the document was changed
+    pub fn show(&mut self, ui: &mut egui::Ui, image_data: &mut Option<ImageData>, image_history: &mut ImageHistory) -> bool {
+        let mut modified = false;
+        
         if let Some(ref mut img_data) = image_data {
             ui.horizontal(|ui| {
                 if ui.button("Add").clicked() {
-                    img_data.add_layer(format!("Layer {}", img_data.layers.len() + 1));
+                    image_history.record("Add layer", img_data, |img_data| {
+                        img_data.add_layer(format!("Layer {}", img_data.layers.len() + 1));
+                    });
+                    modified = true;
                 }
                 
                 if ui.button("Delete").clicked() && img_data.layers.len() > 1 {
-                    img_data.remove_layer(img_data.active_layer);
+                    image_history.record("Delete layer", img_data, |img_data| {
+                        img_data.remove_layer(img_data.active_layer);
+                    });
+                    modified = true;
                 }
                 
                 if ui.button("Duplicate").clicked() {
-                    let layer = img_data.get_active_layer().clone();
-                    img_data.layers.push(layer);
-                    img_data.active_layer = img_data.layers.len() - 1;
-                    img_data.mark_dirty();
+                    image_history.record("Duplicate layer", img_data, |img_data| {
+                        img_data.duplicate_layer(img_data.active_layer);
+                    });
+                    modified = true;
+                }
+            });
+            
+            ui.horizontal(|ui| {
+                let active = img_data.active_layer;
+                if ui.add_enabled(img_data.can_merge_down(active), egui::Button::new("Merge Down")).clicked() {
+                    image_history.record("Merge down", img_data, |img_data| img_data.merge_down(active));
+                    modified = true;
+                }
+                
+                let visible_layers = img_data.layers.iter().filter(|layer| layer.visible).count();
+                if ui.add_enabled(visible_layers >= 2, egui::Button::new("Merge Visible")).clicked() {
+                    image_history.record("Merge visible", img_data, |img_data| img_data.merge_visible());
+                    modified = true;
+                }
+                
+                if ui.add_enabled(img_data.layers.len() > 1, egui::Button::new("Flatten")).clicked() {
+                    image_history.record("Flatten image", img_data, |img_data| img_data.flatten_image());
+                    modified = true;
                 }
             });
             
@@ -32,18 +61,31 @@ impl LayerPanel {
                     // Show layers in reverse order (top layer first)
                     let mut mark_dirty = false;
                     let mut new_active_layer = img_data.active_layer;
+                    // Layer dropped onto another one, and the gap it goes into
+                    // (0 is below the bottom layer)
+                    let mut dropped: Option<(usize, usize)> = None;
                     
                     for i in (0..img_data.layers.len()).rev() {
                         let is_active = i == img_data.active_layer;
                         
-                        ui.group(|ui| {
+                        let group = ui.group(|ui| {
                             ui.horizontal(|ui| {
+                                // Drag handle for reordering
+                                let handle_id = egui::Id::new("layer_drag").with(img_data.layers[i].id());
+                                ui.dnd_drag_source(handle_id, i, |ui| {
+                                    ui.label("☰");
+                                }).response.on_hover_text("Drag to reorder");
+                                
                                 // Visibility toggle
                                 let mut visible = img_data.layers[i].visible;
                                 if ui.checkbox(&mut visible, "").changed() {
+                                    image_history.record("Layer visibility", img_data, |img_data| img_data.layers[i].visible = visible);
                                     mark_dirty = true;
                                 }
-                                img_data.layers[i].visible = visible;
+                                
+                                if img_data.layers[i].text().is_some() {
+                                    ui.label("T").on_hover_text("Text layer");
+                                }
                                 
                                 // Layer name (clickable to select)
                                 let name_response = ui.selectable_label(is_active, &img_data.layers[i].name);
@@ -56,39 +98,93 @@ impl LayerPanel {
                                 });
                             });
                             
-                            // Opacity slider
+                            ui.small(format!("Memory: {}", format_bytes(img_data.layers[i].memory_bytes())));
+                            
+                            // Opacity slider. A drag is a single undo step from press to release.
                             let mut opacity = img_data.layers[i].opacity;
-                            if ui.add(
+                            let response = ui.add(
                                 egui::Slider::new(&mut opacity, 0.0..=1.0)
                                     .show_value(false)
-                            ).changed() {
+                            );
+                            if response.drag_started() {
+                                image_history.begin("Layer opacity", img_data);
+                            }
+                            if response.changed() {
+                                if response.dragged() || response.drag_stopped() {
+                                    img_data.layers[i].opacity = opacity;
+                                } else {
+                                    image_history.record("Layer opacity", img_data, |img_data| img_data.layers[i].opacity = opacity);
+                                }
                                 mark_dirty = true;
                             }
-                            img_data.layers[i].opacity = opacity;
+                            if response.drag_stopped() {
+                                image_history.commit(img_data);
+                            }
                             
                             // Blend mode
                             let mut blend_mode = img_data.layers[i].blend_mode.clone();
                             egui::ComboBox::from_id_source(format!("blend_mode_{}", i))
-                                .selected_text(format!("{:?}", blend_mode))
+                                .selected_text(blend_mode.name())
                                 .show_ui(ui, |ui| {
-                                    ui.selectable_value(&mut blend_mode, crate::image_ops::image_data::BlendMode::Normal, "Normal");
-                                    ui.selectable_value(&mut blend_mode, crate::image_ops::image_data::BlendMode::Multiply, "Multiply");
-                                    ui.selectable_value(&mut blend_mode, crate::image_ops::image_data::BlendMode::Screen, "Screen");
-                                    ui.selectable_value(&mut blend_mode, crate::image_ops::image_data::BlendMode::Overlay, "Overlay");
+                                    for mode in BlendMode::ALL {
+                                        let name = mode.name();
+                                        ui.selectable_value(&mut blend_mode, mode, name);
+                                    }
                                 });
-                            img_data.layers[i].blend_mode = blend_mode;
+                            if blend_mode != img_data.layers[i].blend_mode {
+                                image_history.record("Blend mode", img_data, |img_data| img_data.layers[i].blend_mode = blend_mode);
+                                mark_dirty = true;
+                            }
                         });
                         
+                        // Dropping on the top half of a row puts the layer above it
+                        let rect = group.response.rect;
+                        let pointer_y = ui.ctx().pointer_interact_pos().map_or(rect.center().y, |pos| pos.y);
+                        let above = pointer_y < rect.center().y;
+                        if group.response.dnd_hover_payload::<usize>().is_some() {
+                            let y = if above { rect.top() } else { rect.bottom() };
+                            ui.painter().hline(rect.x_range(), y, egui::Stroke::new(2.0, ui.visuals().selection.bg_fill));
+                        }
+                        if let Some(from) = group.response.dnd_release_payload::<usize>() {
+                            dropped = Some((*from, if above { i + 1 } else { i }));
+                        }
+                        
                         ui.separator();
                     }
                     
+                    img_data.active_layer = new_active_layer;
+                    if let Some((from, gap)) = dropped {
+                        // Removing the layer first shifts the gaps above it down by one
+                        let to = if from < gap { gap - 1 } else { gap };
+                        if to != from {
+                            image_history.record("Move layer", img_data, |img_data| img_data.move_layer(from, to));
+                            modified = true;
+                        }
+                    }
+                    
                     if mark_dirty {
                         img_data.mark_dirty();
+                        modified = true;
                     }
-                    img_data.active_layer = new_active_layer;
                 });
         } else {
             ui.label("No image loaded");
         }
+        
+        modified
+    }
+}
+
+fn format_bytes(bytes: usize) -> String {
+    const KB: f64 = 1024.0;
+    const MB: f64 = KB * 1024.0;
+    
+    let bytes = bytes as f64;
+    if bytes >= MB {
+        format!("{:.1} MB", bytes / MB)
+    } else if bytes >= KB {
+        format!("{:.0} KB", bytes / KB)
+    } else {
+        format!("{} B", bytes)
     }
 }
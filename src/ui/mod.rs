@@ -4,5 +4,10 @@ pub mod toolbar;
 pub mod menubar;
 pub mod color_picker;
 pub mod layer_panel;
+pub mod history_panel;
+pub mod size_dialogs;
+pub mod adjustment_dialog;
 
 pub use app::PaintMateApp;
+pub mod filter_dialog;
+pub mod selection_dialogs;
@@ -0,0 +1,200 @@
+use eframe::egui;
+use image::Rgba;
+use crate::image_ops::ImageData;
+use crate::image_ops::selection::{SelectionMask, SelectionMode, SelectionModify};
+
+// Select > Color Range: selects every pixel near a colour. The selection
+// follows the settings while the dialog is open and Cancel puts the old
+// one back.
+pub struct ColorRangeDialog {
+    pub open: bool,
+    color: egui::Color32,
+    // Colour distance (0..=1) that still gets partly selected
+    fuzziness: f32,
+    sample_merged: bool,
+    mode: SelectionMode,
+    // Selection when the dialog was opened
+    original: Option<SelectionMask>,
+    // Primary colour last frame, so eyedropper picks become the sampled colour
+    last_primary: egui::Color32,
+    // Settings the current selection was made with
+    previewed: Option<(egui::Color32, f32, bool, SelectionMode)>,
+}
+
+impl Default for ColorRangeDialog {
+    fn default() -> Self {
+        Self {
+            open: false,
+            color: egui::Color32::WHITE,
+            fuzziness: 0.2,
+            sample_merged: false,
+            mode: SelectionMode::Replace,
+            original: None,
+            last_primary: egui::Color32::WHITE,
+            previewed: None,
+        }
+    }
+}
+
+impl ColorRangeDialog {
+    // Opens the dialog sampling the primary colour
+    pub fn show_for(&mut self, image_data: &ImageData, primary: egui::Color32) {
+        self.color = primary;
+        self.last_primary = primary;
+        self.original = image_data.selection().cloned();
+        self.previewed = None;
+        self.open = true;
+    }
+    
+    // Forgets the dialog without touching the document
+    pub fn close(&mut self) {
+        self.open = false;
+        self.original = None;
+        self.previewed = None;
+    }
+    
+    pub fn show(&mut self, ctx: &egui::Context, image_data: &mut ImageData, primary: egui::Color32) {
+        if !self.open {
+            return;
+        }
+        
+        if primary != self.last_primary {
+            self.color = primary;
+            self.last_primary = primary;
+        }
+        
+        let mut window_open = true;
+        let mut ok = false;
+        let mut cancel = false;
+        
+        egui::Window::new("Color Range")
+            .open(&mut window_open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Color:");
+                    ui.color_edit_button_srgba(&mut self.color);
+                });
+                ui.label("Picking a color with the eyedropper also sets it");
+                
+                let mut fuzziness = self.fuzziness * 100.0;
+                if ui.add(egui::Slider::new(&mut fuzziness, 0.0..=100.0).text("Fuzziness").suffix("%")).changed() {
+                    self.fuzziness = fuzziness / 100.0;
+                }
+                
+                ui.horizontal(|ui| {
+                    ui.label("Sample:");
+                    ui.radio_value(&mut self.sample_merged, false, "Current layer");
+                    ui.radio_value(&mut self.sample_merged, true, "All layers");
+                });
+                
+                ui.horizontal_wrapped(|ui| {
+                    for mode in SelectionMode::ALL {
+                        ui.selectable_value(&mut self.mode, mode, mode.name());
+                    }
+                });
+                
+                ui.separator();
+                
+                ui.horizontal(|ui| {
+                    if ui.button("OK").clicked() {
+                        ok = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        cancel = true;
+                    }
+                });
+            });
+        
+        if cancel || !window_open {
+            image_data.set_selection(self.original.take());
+            self.close();
+            return;
+        }
+        
+        self.update_preview(image_data);
+        if ok {
+            self.close();
+        }
+    }
+    
+    // Reselects from the original selection if the settings changed
+    fn update_preview(&mut self, image_data: &mut ImageData) {
+        let state = (self.color, self.fuzziness, self.sample_merged, self.mode);
+        if self.previewed == Some(state) {
+            return;
+        }
+        
+        let color = Rgba(self.color.to_srgba_unmultiplied());
+        let mask = image_data.color_range_mask(color, self.fuzziness, self.sample_merged);
+        image_data.set_selection(self.original.clone());
+        image_data.select_mask(mask, self.mode);
+        self.previewed = Some(state);
+    }
+}
+
+// Select > Modify > Grow, Shrink, Feather and Border
+pub struct ModifySelectionDialog {
+    pub open: bool,
+    modify: SelectionModify,
+    radius: f32,
+}
+
+impl Default for ModifySelectionDialog {
+    fn default() -> Self {
+        Self {
+            open: false,
+            modify: SelectionModify::Grow,
+            radius: 2.0,
+        }
+    }
+}
+
+impl ModifySelectionDialog {
+    pub fn show_for(&mut self, modify: SelectionModify) {
+        self.modify = modify;
+        self.open = true;
+    }
+    
+    pub fn show(&mut self, ctx: &egui::Context, image_data: &mut ImageData) {
+        if !self.open {
+            return;
+        }
+        
+        let mut apply = false;
+        let mut close = false;
+        let label = match self.modify {
+            SelectionModify::Grow => "Grow by:",
+            SelectionModify::Shrink => "Shrink by:",
+            SelectionModify::Feather => "Feather radius:",
+            SelectionModify::Border => "Width:",
+        };
+        
+        egui::Window::new(format!("{} Selection", self.modify.name()))
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(label);
+                    ui.add(egui::DragValue::new(&mut self.radius).clamp_range(1.0..=100.0).speed(0.5).suffix(" px"));
+                });
+                
+                ui.horizontal(|ui| {
+                    if ui.button("OK").clicked() {
+                        apply = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        close = true;
+                    }
+                });
+            });
+        
+        if apply {
+            image_data.modify_selection(self.modify, self.radius);
+        }
+        if apply || close {
+            self.open = false;
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use eframe::egui;
+use image::Rgba;
+use crate::image_ops::{ImageData, ImageHistory};
+use crate::image_ops::transform::{Anchor, Resampling};
+
+// Largest width or height either dialog will produce
+const MAX_DIMENSION: u32 = 30000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SizeUnit {
+    Pixels,
+    Percent,
+}
+
+// Image > Image Size: scales every layer
+pub struct ImageSizeDialog {
+    pub open: bool,
+    unit: SizeUnit,
+    width: u32,
+    height: u32,
+    percent_width: f32,
+    percent_height: f32,
+    lock_aspect: bool,
+    resampling: Resampling,
+    // Size of the document when the dialog was opened
+    original: (u32, u32),
+}
+
+impl Default for ImageSizeDialog {
+    fn default() -> Self {
+        Self {
+            open: false,
+            unit: SizeUnit::Pixels,
+            width: 1,
+            height: 1,
+            percent_width: 100.0,
+            percent_height: 100.0,
+            lock_aspect: true,
+            resampling: Resampling::Bicubic,
+            original: (1, 1),
+        }
+    }
+}
+
+impl ImageSizeDialog {
+    pub fn show_for(&mut self, image_data: &ImageData) {
+        self.original = (image_data.width(), image_data.height());
+        self.width = image_data.width();
+        self.height = image_data.height();
+        self.percent_width = 100.0;
+        self.percent_height = 100.0;
+        self.open = true;
+    }
+    
+    // Returns true if the document was changed
+    pub fn show(&mut self, ctx: &egui::Context, image_data: &mut ImageData, image_history: &mut ImageHistory) -> bool {
+        if !self.open {
+            return false;
+        }
+        
+        let mut apply = false;
+        let mut close = false;
+        let (original_width, original_height) = self.original;
+        let aspect = original_width as f32 / original_height as f32;
+        
+        egui::Window::new("Image Size")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!("Current size: {} x {} px", original_width, original_height));
+                ui.separator();
+                
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut self.unit, SizeUnit::Pixels, "Pixels");
+                    ui.radio_value(&mut self.unit, SizeUnit::Percent, "Percent");
+                });
+                
+                egui::Grid::new("image_size").num_columns(2).show(ui, |ui| {
+                    match self.unit {
+                        SizeUnit::Pixels => {
+                            ui.label("Width:");
+                            if ui.add(egui::DragValue::new(&mut self.width).clamp_range(1..=MAX_DIMENSION).suffix(" px")).changed() && self.lock_aspect {
+                                self.height = ((self.width as f32 / aspect).round() as u32).clamp(1, MAX_DIMENSION);
+                            }
+                            ui.end_row();
+                            ui.label("Height:");
+                            if ui.add(egui::DragValue::new(&mut self.height).clamp_range(1..=MAX_DIMENSION).suffix(" px")).changed() && self.lock_aspect {
+                                self.width = ((self.height as f32 * aspect).round() as u32).clamp(1, MAX_DIMENSION);
+                            }
+                            ui.end_row();
+                        }
+                        SizeUnit::Percent => {
+                            ui.label("Width:");
+                            if ui.add(egui::DragValue::new(&mut self.percent_width).clamp_range(1.0..=1000.0).speed(0.5).suffix(" %")).changed() && self.lock_aspect {
+                                self.percent_height = self.percent_width;
+                            }
+                            ui.end_row();
+                            ui.label("Height:");
+                            if ui.add(egui::DragValue::new(&mut self.percent_height).clamp_range(1.0..=1000.0).speed(0.5).suffix(" %")).changed() && self.lock_aspect {
+                                self.percent_width = self.percent_height;
+                            }
+                            ui.end_row();
+                        }
+                    }
+                });
+                
+                ui.checkbox(&mut self.lock_aspect, "Constrain proportions");
+                
+                egui::ComboBox::from_label("Resampling")
+                    .selected_text(self.resampling.name())
+                    .show_ui(ui, |ui| {
+                        for resampling in Resampling::ALL {
+                            ui.selectable_value(&mut self.resampling, resampling, resampling.name());
+                        }
+                    });
+                
+                let (width, height) = self.target_size();
+                ui.label(format!("New size: {} x {} px", width, height));
+                
+                ui.horizontal(|ui| {
+                    if ui.button("OK").clicked() {
+                        apply = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        close = true;
+                    }
+                });
+            });
+        
+        if apply {
+            self.open = false;
+            let (width, height) = self.target_size();
+            if (width, height) != (image_data.width(), image_data.height()) {
+                let resampling = self.resampling;
+                image_history.record("Image size", image_data, |image_data| image_data.resize_image(width, height, resampling));
+                return true;
+            }
+        }
+        if close {
+            self.open = false;
+        }
+        false
+    }
+    
+    fn target_size(&self) -> (u32, u32) {
+        match self.unit {
+            SizeUnit::Pixels => (self.width, self.height),
+            SizeUnit::Percent => {
+                let (width, height) = self.original;
+                let scale = |size: u32, percent: f32| ((size as f32 * percent / 100.0).round() as u32).clamp(1, MAX_DIMENSION);
+                (scale(width, self.percent_width), scale(height, self.percent_height))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CanvasFill {
+    Transparent,
+    Background,
+}
+
+// Image > Canvas Size: grows or trims the canvas around an anchor without scaling
+pub struct CanvasSizeDialog {
+    pub open: bool,
+    width: u32,
+    height: u32,
+    anchor: Anchor,
+    fill: CanvasFill,
+    original: (u32, u32),
+}
+
+impl Default for CanvasSizeDialog {
+    fn default() -> Self {
+        Self {
+            open: false,
+            width: 1,
+            height: 1,
+            anchor: Anchor::CENTER,
+            fill: CanvasFill::Transparent,
+            original: (1, 1),
+        }
+    }
+}
+
+impl CanvasSizeDialog {
+    pub fn show_for(&mut self, image_data: &ImageData) {
+        self.original = (image_data.width(), image_data.height());
+        self.width = image_data.width();
+        self.height = image_data.height();
+        self.open = true;
+    }
+    
+    // `background` is the colour used when the new area is filled with the
+    // background colour. Returns true if the document was changed.
+    pub fn show(&mut self, ctx: &egui::Context, image_data: &mut ImageData, image_history: &mut ImageHistory, background: egui::Color32) -> bool {
+        if !self.open {
+            return false;
+        }
+        
+        let mut apply = false;
+        let mut close = false;
+        
+        egui::Window::new("Canvas Size")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!("Current size: {} x {} px", self.original.0, self.original.1));
+                ui.separator();
+                
+                egui::Grid::new("canvas_size").num_columns(2).show(ui, |ui| {
+                    ui.label("Width:");
+                    ui.add(egui::DragValue::new(&mut self.width).clamp_range(1..=MAX_DIMENSION).suffix(" px"));
+                    ui.end_row();
+                    ui.label("Height:");
+                    ui.add(egui::DragValue::new(&mut self.height).clamp_range(1..=MAX_DIMENSION).suffix(" px"));
+                    ui.end_row();
+                });
+                
+                ui.label("Anchor:");
+                egui::Grid::new("canvas_anchor").spacing(egui::vec2(2.0, 2.0)).show(ui, |ui| {
+                    for row in 0..3 {
+                        for column in 0..3 {
+                            let anchor = Anchor { column, row };
+                            let icon = if anchor == self.anchor { "⏺" } else { " " };
+                            let button = egui::Button::new(icon)
+                                .selected(anchor == self.anchor)
+                                .min_size(egui::vec2(24.0, 24.0));
+                            if ui.add(button).clicked() {
+                                self.anchor = anchor;
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+                
+                ui.horizontal(|ui| {
+                    ui.label("Fill new area:");
+                    ui.radio_value(&mut self.fill, CanvasFill::Transparent, "Transparent");
+                    ui.radio_value(&mut self.fill, CanvasFill::Background, "Background color");
+                });
+                
+                ui.horizontal(|ui| {
+                    if ui.button("OK").clicked() {
+                        apply = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        close = true;
+                    }
+                });
+            });
+        
+        if apply {
+            self.open = false;
+            let (width, height, anchor) = (self.width, self.height, self.anchor);
+            if (width, height) != (image_data.width(), image_data.height()) {
+                let fill = match self.fill {
+                    CanvasFill::Transparent => None,
+                    CanvasFill::Background => Some(Rgba(background.to_srgba_unmultiplied())),
+                };
+                image_history.record("Canvas size", image_data, |image_data| {
+                    image_data.resize_canvas_anchored(width, height, anchor, fill);
+                });
+                return true;
+            }
+        }
+        if close {
+            self.open = false;
+        }
+        false
+    }
+}
@@ -21,6 +21,12 @@ impl Toolbar {
                 ToolType::Text,
                 ToolType::Crop,
                 ToolType::Eyedropper,
+                ToolType::RectSelect,
+                ToolType::EllipseSelect,
+                ToolType::Lasso,
+                ToolType::PolygonLasso,
+                ToolType::MagicWand,
+                ToolType::Move,
             ];
             
             for tool_type in &tools {